@@ -0,0 +1,71 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+/*!
+# `web-thread-backend`
+
+This crate selects between `web-thread` and `web-thread-shim` by
+feature flag, so that `web-thread-select` and `web-thread-pool` always
+agree on the implementation.
+
+If the target is a Wasm architecture and the `web` feature flag is
+passed, we use the Web implementation of `web-thread`; otherwise, we
+transparently substitute in the shim.  The `force-shim` feature flag
+substitutes in the shim regardless.
+
+You probably want `web-thread-select` rather than this crate.
+*/
+
+cfg_if::cfg_if! {
+    if #[cfg(all(target_family = "wasm", feature = "web", not(feature = "force-shim")))] {
+        pub use web_thread::*;
+
+        /// Compile the first block of items if this crate is using the
+        /// Web implementation, and the optional second block
+        /// otherwise.
+        ///
+        /// This saves repeating (and keeping in sync) the condition this
+        /// crate uses to select the implementation.
+        ///
+        /// ```
+        /// web_thread_backend::if_web! {
+        ///     {
+        ///         fn backend() -> &'static str { "web" }
+        ///     } else {
+        ///         fn backend() -> &'static str { "shim" }
+        ///     }
+        /// }
+        ///
+        /// assert!(["web", "shim"].contains(&backend()));
+        /// ```
+        #[macro_export]
+        macro_rules! if_web {
+            ({ $($web:tt)* } $(else { $($shim:tt)* })?) => { $($web)* };
+        }
+    } else {
+        pub use web_thread_shim::*;
+
+        /// Compile the first block of items if this crate is using the
+        /// Web implementation, and the optional second block
+        /// otherwise.
+        ///
+        /// This saves repeating (and keeping in sync) the condition this
+        /// crate uses to select the implementation.
+        ///
+        /// ```
+        /// web_thread_backend::if_web! {
+        ///     {
+        ///         fn backend() -> &'static str { "web" }
+        ///     } else {
+        ///         fn backend() -> &'static str { "shim" }
+        ///     }
+        /// }
+        ///
+        /// assert!(["web", "shim"].contains(&backend()));
+        /// ```
+        #[macro_export]
+        macro_rules! if_web {
+            ({ $($web:tt)* } $(else { $($shim:tt)* })?) => { $($($shim)*)? };
+        }
+    }
+}
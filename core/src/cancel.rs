@@ -0,0 +1,82 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::Poll,
+};
+
+use crate::sync::Waiters;
+
+#[derive(Default)]
+struct State {
+    cancelled: AtomicBool,
+    // not a `std::sync::Mutex`, which traps if contended on the main
+    // thread
+    waiters: Waiters,
+}
+
+/// A token that can be used to cooperatively cancel tasks.
+///
+/// Tokens live in shared memory, so clones of a token can be moved
+/// into tasks on other threads, which can then check whether they
+/// have been cancelled or wait for cancellation.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<State>);
+
+impl CancellationToken {
+    /// Create a new token that has not yet been cancelled.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the token, waking everything waiting on
+    /// [`CancellationToken::cancelled`].
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::Release);
+        self.0.waiters.wake_all();
+    }
+
+    /// Whether the token has been cancelled.
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Wait for the token to be cancelled.
+    pub fn cancelled(&self) -> impl Future<Output = ()> + Send + 'static {
+        let state = self.0.clone();
+        futures::future::poll_fn(move |context| {
+            if state.cancelled.load(Ordering::Acquire) {
+                return Poll::Ready(());
+            }
+
+            state.waiters.register(context);
+            // check again now we're registered, in case we were
+            // cancelled in the meantime
+            if state.cancelled.load(Ordering::Acquire) {
+                return Poll::Ready(());
+            }
+            Poll::Pending
+        })
+    }
+}
+
+#[test]
+fn cancellation_wakes_waiters_on_other_threads() {
+    let token = CancellationToken::new();
+    let waiter = std::thread::spawn({
+        let token = token.clone();
+        move || futures::executor::block_on(token.cancelled())
+    });
+    assert!(!token.is_cancelled());
+    token.cancel();
+    waiter.join().unwrap();
+    assert!(token.is_cancelled());
+    // waiting after cancellation completes at once
+    futures::executor::block_on(token.cancelled());
+}
@@ -0,0 +1,55 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+/*!
+# `web-thread-core`
+
+Types shared by `web-thread` and `web-thread-shim`, so that code
+written against either (typically through `web-thread-select`) sees
+the same types on every target.
+
+You probably want one of those crates rather than this one.
+ */
+
+mod cancel;
+pub mod sync;
+pub mod watch;
+
+pub use cancel::CancellationToken;
+
+/// The type of errors that may arise from running tasks on threads.
+///
+/// Errors specific to a backend are boxed, so that the variants are
+/// the same for every backend; some variants are only produced by
+/// some backends, as documented.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The thread stopped before the task completed.
+    #[error("thread killed before task completed")]
+    Killed,
+    /// The thread could not be spawned.
+    #[error("failed to spawn thread: {0}")]
+    Spawn(Box<dyn std::error::Error + Send + Sync>),
+    /// A value could not be sent across the thread boundary.  Native
+    /// threads share memory, so the shim reports this only when
+    /// checking serialization.
+    #[error("failed to send a value across threads: {0}")]
+    Serialization(String),
+    /// The task panicked, with the given message.
+    #[error("task panicked: {0}")]
+    Panic(String),
+    /// The task was aborted before it completed.
+    #[error("task aborted")]
+    Aborted,
+    /// The task didn't complete in time.
+    #[error("task timed out")]
+    Timeout,
+    /// Some other error raised by the backend, such as an exception
+    /// thrown on the Web.
+    #[error(transparent)]
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Convenience alias for `Result<T, Error>`.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -0,0 +1,199 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Asynchronous synchronization primitives for state in shared memory.
+//!
+//! The main thread mustn't block, so a `std::sync::Mutex` that is
+//! contended there traps.  These primitives wait asynchronously
+//! instead, so they can be used from the main thread and workers
+//! alike; waking a task on another thread is done with
+//! `Atomics.notify` and `Atomics.waitAsync`.  Where there's no
+//! contention they complete without yielding.
+
+use std::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Waker},
+};
+
+mod barrier;
+mod mutex;
+mod notify;
+mod once_cell;
+mod rwlock;
+mod semaphore;
+
+pub use barrier::{Barrier, BarrierWaitResult};
+pub use mutex::{Mutex, MutexGuard};
+pub use notify::Notify;
+pub use once_cell::OnceCell;
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::{Semaphore, SemaphorePermit};
+
+/// The tasks waiting for a primitive, behind a spin lock, which never
+/// waits long as it is only held to add or take wakers.
+#[derive(Default)]
+pub(crate) struct Waiters {
+    locked: AtomicBool,
+    wakers: UnsafeCell<Vec<Waker>>,
+}
+
+// the wakers are only accessed with the spin lock held
+unsafe impl Sync for Waiters {}
+
+impl Waiters {
+    const fn new() -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            wakers: UnsafeCell::new(Vec::new()),
+        }
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut Vec<Waker>) -> R) -> R {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            std::hint::spin_loop();
+        }
+        let result = f(unsafe { &mut *self.wakers.get() });
+        self.locked.store(false, Ordering::Release);
+        result
+    }
+
+    /// Wake the current task once [`Waiters::wake_all`] is next
+    /// called.
+    pub(crate) fn register(&self, context: &Context<'_>) {
+        self.with(|wakers| {
+            if !wakers.iter().any(|waker| waker.will_wake(context.waker())) {
+                wakers.push(context.waker().clone());
+            }
+        });
+    }
+
+    /// Wake every waiting task, to try again.
+    pub(crate) fn wake_all(&self) {
+        for waker in self.with(std::mem::take) {
+            waker.wake();
+        }
+    }
+}
+
+#[test]
+fn mutexes_exclude_other_threads() {
+    use std::sync::Arc;
+
+    let counter = Arc::new(Mutex::new(0));
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let counter = counter.clone();
+            std::thread::spawn(move || {
+                for _ in 0..1000 {
+                    *futures::executor::block_on(counter.lock()) += 1;
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(*counter.try_lock().unwrap(), 4000);
+}
+
+#[test]
+fn rwlocks_share_reads_and_exclude_writes() {
+    let lock = RwLock::new(1);
+    let first = lock.try_read().unwrap();
+    let second = lock.try_read().unwrap();
+    assert_eq!(*first + *second, 2);
+    assert!(lock.try_write().is_none());
+    drop((first, second));
+
+    let mut write = lock.try_write().unwrap();
+    *write += 1;
+    assert!(lock.try_read().is_none());
+    assert!(lock.try_write().is_none());
+    drop(write);
+    assert_eq!(*lock.try_read().unwrap(), 2);
+}
+
+#[test]
+fn rwlock_writers_wait_for_readers() {
+    use std::sync::Arc;
+
+    let lock = Arc::new(RwLock::new(0));
+    let read = lock.try_read().unwrap();
+    let writer = std::thread::spawn({
+        let lock = lock.clone();
+        move || *futures::executor::block_on(lock.write()) += 1
+    });
+    // the writer can't get in while we're reading
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    assert_eq!(*read, 0);
+    drop(read);
+    writer.join().unwrap();
+    assert_eq!(*futures::executor::block_on(lock.read()), 1);
+}
+
+#[test]
+fn semaphore_permits_are_released_on_drop() {
+    let semaphore = Semaphore::new(2);
+    let permit = futures::executor::block_on(semaphore.acquire_many(2));
+    assert_eq!(semaphore.available_permits(), 0);
+    assert!(semaphore.try_acquire().is_none());
+    drop(permit);
+    assert_eq!(semaphore.available_permits(), 2);
+    assert!(semaphore.try_acquire_many(2).is_some());
+}
+
+#[test]
+fn semaphore_waiters_wake_when_permits_are_released() {
+    use std::sync::Arc;
+
+    let semaphore = Arc::new(Semaphore::new(1));
+    let permit = semaphore.try_acquire().unwrap();
+    let waiter = std::thread::spawn({
+        let semaphore = semaphore.clone();
+        move || drop(futures::executor::block_on(semaphore.acquire()))
+    });
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    assert!(!waiter.is_finished());
+    drop(permit);
+    waiter.join().unwrap();
+    assert_eq!(semaphore.available_permits(), 1);
+}
+
+#[test]
+fn notify_one_before_waiting_is_kept() {
+    use futures::FutureExt as _;
+
+    let notify = Notify::new();
+    notify.notify_one();
+    assert!(notify.notified().now_or_never().is_some());
+    // the notification is used up
+    assert!(notify.notified().now_or_never().is_none());
+
+    // only tasks already waiting are woken by `notify_waiters`
+    notify.notify_waiters();
+    assert!(notify.notified().now_or_never().is_none());
+}
+
+#[test]
+fn notify_wakes_waiters_on_other_threads() {
+    use std::sync::Arc;
+
+    let notify = Arc::new(Notify::new());
+    let (started, receiver) = std::sync::mpsc::channel();
+    let waiter = std::thread::spawn({
+        let notify = notify.clone();
+        move || {
+            let notified = notify.notified();
+            started.send(()).unwrap();
+            futures::executor::block_on(notified);
+        }
+    });
+    receiver.recv().unwrap();
+    notify.notify_waiters();
+    waiter.join().unwrap();
+}
@@ -0,0 +1,80 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Poll,
+};
+
+use super::{Mutex, Waiters};
+
+/// A point that a number of tasks, on any threads, wait at until all
+/// of them have reached it, for keeping the phases of fork-join
+/// algorithms in step.  It can be reused for each phase.
+pub struct Barrier {
+    participants: usize,
+    /// The number of tasks waiting in the current phase.
+    arrived: Mutex<usize>,
+    /// The number of phases completed.
+    phase: AtomicUsize,
+    waiters: Waiters,
+}
+
+impl Barrier {
+    /// Create a barrier for `participants` tasks.  A barrier for no
+    /// tasks doesn't wait.
+    #[must_use]
+    pub fn new(participants: usize) -> Self {
+        Self {
+            participants,
+            arrived: Mutex::new(0),
+            phase: AtomicUsize::new(0),
+            waiters: Waiters::default(),
+        }
+    }
+
+    /// Wait for all the participants to reach the barrier.  The last
+    /// to arrive is told that it is the leader, for doing work once
+    /// per phase.
+    pub async fn wait(&self) -> BarrierWaitResult {
+        let phase = {
+            let mut arrived = self.arrived.lock().await;
+            *arrived += 1;
+            let phase = self.phase.load(Ordering::Acquire);
+            if *arrived >= self.participants {
+                *arrived = 0;
+                self.phase.store(phase.wrapping_add(1), Ordering::Release);
+                drop(arrived);
+                self.waiters.wake_all();
+                return BarrierWaitResult(true);
+            }
+            phase
+        };
+        futures::future::poll_fn(|context| {
+            if self.phase.load(Ordering::Acquire) != phase {
+                return Poll::Ready(BarrierWaitResult(false));
+            }
+            self.waiters.register(context);
+            // check again, in case the phase ended in the meantime
+            if self.phase.load(Ordering::Acquire) == phase {
+                Poll::Pending
+            } else {
+                Poll::Ready(BarrierWaitResult(false))
+            }
+        })
+        .await
+    }
+}
+
+/// What [`Barrier::wait`] tells a participant.
+#[derive(Clone, Copy, Debug)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    /// Whether this participant was the last to arrive.  Exactly one
+    /// participant of each phase is the leader.
+    #[must_use]
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
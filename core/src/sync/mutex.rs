@@ -0,0 +1,106 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
+    task::Poll,
+};
+
+use super::Waiters;
+
+/// A mutual-exclusion lock that is waited for asynchronously.
+#[derive(Default)]
+pub struct Mutex<T: ?Sized> {
+    locked: AtomicBool,
+    waiters: Waiters,
+    value: UnsafeCell<T>,
+}
+
+// the value is only accessed by the holder of the lock
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    /// Create a lock protecting `value`.
+    pub fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            waiters: Waiters::default(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Take the value.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Take the lock if it is free.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+            .then(|| MutexGuard(self))
+    }
+
+    /// Wait for the lock and take it.
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        futures::future::poll_fn(|context| {
+            if let Some(guard) = self.try_lock() {
+                return Poll::Ready(guard);
+            }
+            self.waiters.register(context);
+            // try again, in case the lock was released in the meantime
+            match self.try_lock() {
+                Some(guard) => Poll::Ready(guard),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// The value, which needs no lock as the borrow is exclusive.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+/// Holds a [`Mutex`] until it is dropped.
+///
+/// A guard gives access to the value, so it can be shared between
+/// threads only if the value can:
+///
+/// ```compile_fail,E0277
+/// fn is_sync<T: Sync>() {}
+/// is_sync::<web_thread_core::sync::MutexGuard<'_, std::cell::Cell<u8>>>();
+/// ```
+pub struct MutexGuard<'a, T: ?Sized>(&'a Mutex<T>);
+
+// without this, the guard would be `Sync` whenever the mutex is, and
+// would share `&T` for values that aren't `Sync`
+unsafe impl<T: ?Sized + Send + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.0.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.0.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.0.locked.store(false, Ordering::Release);
+        self.0.waiters.wake_all();
+    }
+}
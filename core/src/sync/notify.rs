@@ -0,0 +1,69 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::Poll,
+};
+
+use super::Waiters;
+
+/// A signal that tasks, on any threads, can wait for, without any
+/// data attached.
+#[derive(Default)]
+pub struct Notify {
+    /// A notification from [`Notify::notify_one`] that no task has
+    /// taken yet.
+    permit: AtomicBool,
+    /// The number of calls to [`Notify::notify_waiters`].
+    generation: AtomicUsize,
+    waiters: Waiters,
+}
+
+impl Notify {
+    /// Create a signal with no notification pending.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wake one waiting task, or if none is waiting the next task to
+    /// wait.
+    pub fn notify_one(&self) {
+        self.permit.store(true, Ordering::Release);
+        self.waiters.wake_all();
+    }
+
+    /// Wake every task waiting now, leaving no notification for tasks
+    /// that wait later.
+    pub fn notify_waiters(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+        self.waiters.wake_all();
+    }
+
+    /// Wait for a notification.  Calls to [`Notify::notify_waiters`]
+    /// count from when this is called rather than when it is first
+    /// polled.
+    pub fn notified(&self) -> impl Future<Output = ()> + '_ {
+        let generation = self.generation.load(Ordering::Acquire);
+        let notified = move || {
+            self.generation.load(Ordering::Acquire) != generation
+                || self
+                    .permit
+                    .compare_exchange(true, false, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+        };
+        futures::future::poll_fn(move |context| {
+            if notified() {
+                return Poll::Ready(());
+            }
+            self.waiters.register(context);
+            // check again, in case of a notification in the meantime
+            if notified() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+    }
+}
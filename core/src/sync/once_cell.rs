@@ -0,0 +1,153 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicU8, Ordering},
+    task::Poll,
+};
+
+use super::Waiters;
+
+const EMPTY: u8 = 0;
+const INITIALIZING: u8 = 1;
+const READY: u8 = 2;
+
+/// A cell that is written once, by the first of any number of tasks
+/// on any threads to initialize it, for lazily initializing shared
+/// resources.  The other tasks wait for the value.
+pub struct OnceCell<T> {
+    state: AtomicU8,
+    waiters: Waiters,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// the value is written once, before it is shared
+unsafe impl<T: Send> Send for OnceCell<T> {}
+unsafe impl<T: Send + Sync> Sync for OnceCell<T> {}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Gives the cell up to other initializers if initialization is
+/// abandoned, by failing or being dropped.
+struct Initializing<'a, T>(&'a OnceCell<T>);
+
+impl<T> Drop for Initializing<'_, T> {
+    fn drop(&mut self) {
+        self.0.state.store(EMPTY, Ordering::Release);
+        self.0.waiters.wake_all();
+    }
+}
+
+impl<T> OnceCell<T> {
+    /// Create an empty cell.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(EMPTY),
+            waiters: Waiters::new(),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// The value, if the cell has been initialized.
+    pub fn get(&self) -> Option<&T> {
+        (self.state.load(Ordering::Acquire) == READY)
+            .then(|| unsafe { (*self.value.get()).assume_init_ref() })
+    }
+
+    /// Initialize the cell with `value`, unless it is already
+    /// initialized or being initialized, in which case `value` is
+    /// given back.
+    ///
+    /// # Errors
+    ///
+    /// `value`, if the cell wasn't empty.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        if self
+            .state
+            .compare_exchange(EMPTY, INITIALIZING, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(value);
+        }
+        self.write(Initializing(self), value);
+        Ok(())
+    }
+
+    /// The value, initializing the cell with `init` if it is empty.
+    /// If another task is initializing it, this waits for its value.
+    pub async fn get_or_init<F: Future<Output = T>>(&self, init: impl FnOnce() -> F) -> &T {
+        match self
+            .get_or_try_init(|| async { Ok::<_, std::convert::Infallible>(init().await) })
+            .await
+        {
+            Ok(value) => value,
+        }
+    }
+
+    /// The value, initializing the cell with `init` if it is empty.
+    /// If another task is initializing it, this waits for its value,
+    /// and if that fails, tries again.
+    ///
+    /// # Errors
+    ///
+    /// The error from `init`, which leaves the cell empty.
+    pub async fn get_or_try_init<E, F: Future<Output = Result<T, E>>>(
+        &self,
+        init: impl FnOnce() -> F,
+    ) -> Result<&T, E> {
+        // wait for the value, unless we get to initialize the cell
+        let initializing = futures::future::poll_fn(|context| {
+            let start = || match self.state.compare_exchange(
+                EMPTY,
+                INITIALIZING,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => Some(Poll::Ready(Some(Initializing(self)))),
+                Err(READY) => Some(Poll::Ready(None)),
+                Err(_) => None,
+            };
+            if let Some(poll) = start() {
+                return poll;
+            }
+            self.waiters.register(context);
+            // try again, in case initialization ended in the meantime
+            start().unwrap_or(Poll::Pending)
+        })
+        .await;
+        if let Some(initializing) = initializing {
+            let value = init().await?;
+            self.write(initializing, value);
+        }
+        // the cell is initialized, by us or another task
+        Ok(unsafe { (*self.value.get()).assume_init_ref() })
+    }
+
+    fn write(&self, initializing: Initializing<'_, T>, value: T) {
+        std::mem::forget(initializing);
+        unsafe { (*self.value.get()).write(value) };
+        self.state.store(READY, Ordering::Release);
+        self.waiters.wake_all();
+    }
+
+    /// Take the value, if the cell has been initialized.
+    pub fn into_inner(mut self) -> Option<T> {
+        (std::mem::replace(self.state.get_mut(), EMPTY) == READY)
+            .then(|| unsafe { self.value.get_mut().assume_init_read() })
+    }
+}
+
+impl<T> Drop for OnceCell<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == READY {
+            unsafe { self.value.get_mut().assume_init_drop() };
+        }
+    }
+}
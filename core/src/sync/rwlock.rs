@@ -0,0 +1,156 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Poll,
+};
+
+use super::Waiters;
+
+/// The state of a lock held for writing; otherwise the state is the
+/// number of readers.
+const WRITING: usize = usize::MAX;
+
+/// A reader-writer lock that is waited for asynchronously.
+///
+/// Readers don't wait for writers that are waiting, so a steady
+/// stream of readers can hold off writers.
+#[derive(Default)]
+pub struct RwLock<T: ?Sized> {
+    state: AtomicUsize,
+    waiters: Waiters,
+    value: UnsafeCell<T>,
+}
+
+// the value is shared only by readers, and is otherwise only accessed
+// by the writer holding the lock
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    /// Create a lock protecting `value`.
+    pub fn new(value: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            waiters: Waiters::default(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Take the value.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Take the lock for reading if no writer holds it.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let mut state = self.state.load(Ordering::Relaxed);
+        while state < WRITING - 1 {
+            match self.state.compare_exchange_weak(
+                state,
+                state + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(RwLockReadGuard(self)),
+                Err(current) => state = current,
+            }
+        }
+        None
+    }
+
+    /// Take the lock for writing if nothing holds it.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.state
+            .compare_exchange(0, WRITING, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+            .then(|| RwLockWriteGuard(self))
+    }
+
+    /// Wait for the lock to be free of writers and take it for
+    /// reading.
+    pub async fn read(&self) -> RwLockReadGuard<'_, T> {
+        futures::future::poll_fn(|context| {
+            if let Some(guard) = self.try_read() {
+                return Poll::Ready(guard);
+            }
+            self.waiters.register(context);
+            // try again, in case the lock was released in the meantime
+            match self.try_read() {
+                Some(guard) => Poll::Ready(guard),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Wait for the lock to be free and take it for writing.
+    pub async fn write(&self) -> RwLockWriteGuard<'_, T> {
+        futures::future::poll_fn(|context| {
+            if let Some(guard) = self.try_write() {
+                return Poll::Ready(guard);
+            }
+            self.waiters.register(context);
+            // try again, in case the lock was released in the meantime
+            match self.try_write() {
+                Some(guard) => Poll::Ready(guard),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// The value, which needs no lock as the borrow is exclusive.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+/// Holds an [`RwLock`] for reading until it is dropped.
+pub struct RwLockReadGuard<'a, T: ?Sized>(&'a RwLock<T>);
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.0.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // only the last reader lets a writer in
+        if self.0.state.fetch_sub(1, Ordering::Release) == 1 {
+            self.0.waiters.wake_all();
+        }
+    }
+}
+
+/// Holds an [`RwLock`] for writing until it is dropped.
+pub struct RwLockWriteGuard<'a, T: ?Sized>(&'a RwLock<T>);
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.0.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.0.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.0.state.store(0, Ordering::Release);
+        self.0.waiters.wake_all();
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Poll,
+};
+
+use super::Waiters;
+
+/// A count of permits that tasks wait for asynchronously, for limiting
+/// how many tasks, on any threads, do something at once.
+#[derive(Default)]
+pub struct Semaphore {
+    permits: AtomicUsize,
+    waiters: Waiters,
+}
+
+impl Semaphore {
+    /// Create a semaphore with `permits` permits.
+    #[must_use]
+    pub fn new(permits: usize) -> Self {
+        Self {
+            permits: AtomicUsize::new(permits),
+            waiters: Waiters::default(),
+        }
+    }
+
+    /// The number of permits that are free.
+    #[must_use]
+    pub fn available_permits(&self) -> usize {
+        self.permits.load(Ordering::Acquire)
+    }
+
+    /// Add `count` permits.
+    pub fn add_permits(&self, count: usize) {
+        self.permits.fetch_add(count, Ordering::Release);
+        self.waiters.wake_all();
+    }
+
+    /// Take a permit if one is free.
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    /// Take `count` permits if they are free.
+    pub fn try_acquire_many(&self, count: usize) -> Option<SemaphorePermit<'_>> {
+        self.permits
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |permits| {
+                permits.checked_sub(count)
+            })
+            .is_ok()
+            .then(|| SemaphorePermit {
+                semaphore: self,
+                count,
+            })
+    }
+
+    /// Wait for a permit and take it.
+    pub async fn acquire(&self) -> SemaphorePermit<'_> {
+        self.acquire_many(1).await
+    }
+
+    /// Wait for `count` permits to be free at once and take them.
+    pub async fn acquire_many(&self, count: usize) -> SemaphorePermit<'_> {
+        futures::future::poll_fn(|context| {
+            if let Some(permit) = self.try_acquire_many(count) {
+                return Poll::Ready(permit);
+            }
+            self.waiters.register(context);
+            // try again, in case permits were released in the meantime
+            match self.try_acquire_many(count) {
+                Some(permit) => Poll::Ready(permit),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+}
+
+/// Permits taken from a [`Semaphore`], which are given back when it
+/// is dropped.
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    count: usize,
+}
+
+impl SemaphorePermit<'_> {
+    /// Keep the permits taken for good, rather than giving them back.
+    pub fn forget(mut self) {
+        self.count = 0;
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        if self.count > 0 {
+            self.semaphore.add_permits(self.count);
+        }
+    }
+}
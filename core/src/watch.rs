@@ -0,0 +1,151 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A cell holding the latest of a series of values, in shared memory,
+//! for pushing state such as configuration into long-running loops on
+//! other threads without a queue building up.
+//!
+//! The value is kept behind an asynchronous [`RwLock`], so neither
+//! half blocks its thread, and both can be used on the main thread.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use super::{
+    Error, Result,
+    sync::{Notify, RwLock, RwLockReadGuard},
+};
+
+struct Shared<T> {
+    value: RwLock<T>,
+    /// The number of values sent so far, updated with the value's
+    /// lock held.
+    version: AtomicU64,
+    /// Whether the sender has been dropped.
+    closed: AtomicBool,
+    changed: Notify,
+}
+
+/// Create a watch channel holding `initial`, returning its sender and
+/// a receiver, which can be cloned.
+///
+/// Both halves live in shared memory, so they can be moved into tasks
+/// on other threads.
+pub fn watch<T>(initial: T) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        value: RwLock::new(initial),
+        version: AtomicU64::new(0),
+        closed: AtomicBool::new(false),
+        changed: Notify::new(),
+    });
+    (Sender(shared.clone()), Receiver { shared, seen: 0 })
+}
+
+/// The sending half of a [`watch`] channel.
+pub struct Sender<T>(Arc<Shared<T>>);
+
+impl<T> Sender<T> {
+    /// Replace the value, waking the receivers waiting for a change.
+    pub async fn send(&self, value: T) {
+        self.send_modify(|current| *current = value).await;
+    }
+
+    /// Modify the value in place, waking the receivers waiting for a
+    /// change.  Waits for the receivers' [`Ref`]s to be dropped.
+    pub async fn send_modify(&self, modify: impl FnOnce(&mut T)) {
+        {
+            let mut value = self.0.value.write().await;
+            modify(&mut value);
+            self.0.version.fetch_add(1, Ordering::Release);
+        }
+        self.0.changed.notify_waiters();
+    }
+
+    /// The current value.
+    pub async fn borrow(&self) -> Ref<'_, T> {
+        Ref(self.0.value.read().await)
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.0.closed.store(true, Ordering::Release);
+        self.0.changed.notify_waiters();
+    }
+}
+
+/// The receiving half of a [`watch`] channel.  Each clone keeps track
+/// of which values it has seen.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    /// The version of the last value seen.
+    seen: u64,
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            seen: self.seen,
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// The current value, without marking it seen.
+    pub async fn borrow(&self) -> Ref<'_, T> {
+        Ref(self.shared.value.read().await)
+    }
+
+    /// The current value, marking it seen.
+    pub async fn borrow_and_update(&mut self) -> Ref<'_, T> {
+        let value = self.shared.value.read().await;
+        self.seen = self.shared.version.load(Ordering::Acquire);
+        Ref(value)
+    }
+
+    /// Whether a value has been sent since the last one seen.
+    #[must_use]
+    pub fn has_changed(&self) -> bool {
+        self.shared.version.load(Ordering::Acquire) != self.seen
+    }
+
+    /// Wait for a value that hasn't been seen, and mark it seen.
+    /// Intermediate values sent in the meantime are skipped.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Killed`] if the sender has been dropped, so the value
+    /// will never change again.
+    pub async fn changed(&mut self) -> Result<()> {
+        loop {
+            // wait from before checking, so as not to miss a change in
+            // the meantime
+            let changed = self.shared.changed.notified();
+            let version = self.shared.version.load(Ordering::Acquire);
+            if version != self.seen {
+                self.seen = version;
+                return Ok(());
+            }
+            if self.shared.closed.load(Ordering::Acquire) {
+                return Err(Error::Killed);
+            }
+            changed.await;
+        }
+    }
+}
+
+/// A reference to the value in a [`watch`] channel.  Until it is
+/// dropped, senders wait (asynchronously) to send, so it shouldn't be
+/// held across long waits.
+pub struct Ref<'a, T>(RwLockReadGuard<'a, T>);
+
+impl<T> std::ops::Deref for Ref<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
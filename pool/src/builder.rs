@@ -0,0 +1,154 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{num::NonZero, sync::Arc, time::Duration};
+
+use crate::{
+    Hooks, Pool, Reuse,
+    hooks::NoHooks,
+    pool::{Scaling, Watchdog},
+    spawn_background, web_thread,
+};
+
+/// Configuration for a [`Pool`] of threads.
+#[derive(Clone)]
+pub struct PoolBuilder {
+    capacity: usize,
+    blocking_capacity: Option<usize>,
+    scaling: Scaling,
+    watchdog: Watchdog,
+    reuse: Reuse,
+    hooks: Arc<dyn Hooks>,
+}
+
+impl std::fmt::Debug for PoolBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PoolBuilder")
+            .field("capacity", &self.capacity)
+            .field("blocking_capacity", &self.blocking_capacity)
+            .field("scaling", &self.scaling)
+            .field("watchdog", &self.watchdog)
+            .field("reuse", &self.reuse)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for PoolBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PoolBuilder {
+    /// Start configuring a pool with one thread per available core,
+    /// or four threads if the number of cores can't be determined.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            capacity: std::thread::available_parallelism().map_or(4, NonZero::get),
+            blocking_capacity: None,
+            scaling: Scaling::default(),
+            watchdog: Watchdog::default(),
+            reuse: Reuse::default(),
+            hooks: Arc::new(NoHooks),
+        }
+    }
+
+    /// Set the maximum number of threads for running async tasks.
+    #[must_use]
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Set the maximum number of threads for
+    /// [`Pool::spawn_blocking`].  Defaults to four times the
+    /// capacity.
+    #[must_use]
+    pub fn blocking_capacity(mut self, blocking_capacity: usize) -> Self {
+        self.blocking_capacity = Some(blocking_capacity);
+        self
+    }
+
+    /// Set the number of threads to spawn up front, which will never
+    /// be retired.
+    #[must_use]
+    pub fn min_threads(mut self, min: usize) -> Self {
+        self.scaling.min = min;
+        self
+    }
+
+    /// Only spawn a new thread once a task has been waiting `delay`
+    /// for one to become free, rather than whenever all threads are
+    /// busy.  This stops short bursts of tasks from spawning threads
+    /// that won't be needed again.
+    #[must_use]
+    pub fn spawn_after(mut self, delay: Duration) -> Self {
+        self.scaling.spawn_after = Some(delay);
+        self
+    }
+
+    /// Retire threads that have been idle for `timeout`, down to the
+    /// minimum set with [`PoolBuilder::min_threads`].
+    #[must_use]
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.scaling.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the order in which idle threads are reused.  With
+    /// [`Reuse::Lifo`] and an idle timeout, threads that are rarely
+    /// needed are retired rather than kept warm by occasional tasks.
+    #[must_use]
+    pub fn reuse(mut self, reuse: Reuse) -> Self {
+        self.reuse = reuse;
+        self
+    }
+
+    /// Report tasks that hold a thread for longer than `threshold`,
+    /// such as tasks that block their thread waiting on a lock, with
+    /// a warning and [`Hooks::on_long_task`].  Running tasks are
+    /// checked on a timer, so they are reported while they still hold
+    /// their thread.
+    #[must_use]
+    pub fn long_task_threshold(mut self, threshold: Duration) -> Self {
+        self.watchdog.long_task = Some(threshold);
+        self
+    }
+
+    /// Report when more than `watermark` tasks are queued waiting for
+    /// a thread, with a warning and [`Hooks::on_queue_saturated`].
+    #[must_use]
+    pub fn queue_watermark(mut self, watermark: usize) -> Self {
+        self.watchdog.queue_watermark = Some(watermark);
+        self
+    }
+
+    /// Register `hooks` to be called on events in the pool's life.
+    #[must_use]
+    pub fn hooks(mut self, hooks: impl Hooks + 'static) -> Self {
+        self.hooks = Arc::new(hooks);
+        self
+    }
+
+    /// Create the configured pool.
+    #[must_use]
+    pub fn build(self) -> Pool<web_thread::Thread> {
+        let mut pool = Pool::with_blocking_capacity(
+            self.capacity,
+            self.blocking_capacity
+                .unwrap_or(self.capacity.saturating_mul(4)),
+        );
+        pool.set_hooks_arc(self.hooks);
+        pool.set_scaling(self.scaling);
+        if let Some(retire_idle) = pool.retire_idle() {
+            spawn_background(retire_idle);
+        }
+        pool.set_watchdog(self.watchdog);
+        if let Some(watch_long_tasks) = pool.watch_long_tasks() {
+            spawn_background(watch_long_tasks);
+        }
+        pool.set_reuse(self.reuse);
+        pool
+    }
+}
@@ -0,0 +1,65 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    error::Error as StdError,
+    sync::{Arc, RwLock},
+};
+
+use futures::task::LocalFutureObj;
+
+use crate::{Pool, trace, web_thread};
+
+type ErrorHandler = Arc<dyn Fn(&(dyn StdError + 'static)) + Send + Sync>;
+
+static ERROR_HANDLER: RwLock<Option<ErrorHandler>> = RwLock::new(None);
+
+/// Register `handler` to be called with the errors of tasks started
+/// with [`Pool::run_detached`], replacing any handler previously
+/// registered.
+///
+/// The handler is called on the pool thread that ran the failed task,
+/// or with [`Error::Closed`](crate::Error::Closed) on the thread that
+/// submitted the task if the pool was closed.
+pub fn set_error_handler(handler: impl Fn(&(dyn StdError + 'static)) + Send + Sync + 'static) {
+    *ERROR_HANDLER.write().unwrap() = Some(Arc::new(handler));
+}
+
+fn report(error: &(dyn StdError + 'static)) {
+    trace::detached_failed(error);
+    let handler = ERROR_HANDLER.read().unwrap().clone();
+    if let Some(handler) = handler {
+        handler(error);
+    }
+}
+
+impl Pool<web_thread::Thread> {
+    /// Run a job whose result isn't needed, without waiting for a
+    /// thread to become available.  The job is queued in the default
+    /// lane, and its thread is released when it completes.  If it
+    /// fails, the error is passed to the handler registered with
+    /// [`set_error_handler`].
+    ///
+    /// Unlike [`Pool::run`], the context is moved
+    /// through Rust memory rather than `Post`ed, so must be `Send`.
+    pub fn run_detached<Context, F, E>(
+        &self,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Send + 'static,
+    ) where
+        Context: Send + 'static,
+        F: Future<Output = Result<(), E>> + 'static,
+        E: StdError + 'static,
+    {
+        let job = self.spawn_job(Box::new(move || {
+            LocalFutureObj::new(Box::new(async move {
+                if let Err(error) = code(context).await {
+                    report(&error);
+                }
+            }))
+        }));
+        if job.is_err() {
+            report(&crate::Error::Closed);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::OnceLock;
+
+use crate::{Error, Pool, PoolBuilder, Result, web_thread};
+
+static CONFIG: OnceLock<PoolBuilder> = OnceLock::new();
+
+fn build() -> Pool<web_thread::Thread> {
+    CONFIG.get_or_init(PoolBuilder::new).clone().build()
+}
+
+/// Configure the pool returned by [`global`].  This can only be done
+/// once, before the global pool is first used.
+///
+/// # Errors
+///
+/// [`Error::GlobalInitialized`] if the global pool has already been
+/// configured or used.
+pub fn init_global(builder: PoolBuilder) -> Result<()> {
+    CONFIG.set(builder).map_err(|_| Error::GlobalInitialized)
+}
+
+/// A handle to the pool returned by [`global`], which dereferences
+/// to the [`Pool`].
+#[derive(Clone)]
+pub struct GlobalPool(Handle);
+
+#[cfg(not(target_family = "wasm"))]
+type Handle = &'static Pool<web_thread::Thread>;
+#[cfg(target_family = "wasm")]
+type Handle = std::rc::Rc<Pool<web_thread::Thread>>;
+
+impl std::ops::Deref for GlobalPool {
+    type Target = Pool<web_thread::Thread>;
+
+    fn deref(&self) -> &Self::Target {
+        std::ops::Deref::deref(&self.0)
+    }
+}
+
+/// Get the shared pool, creating it on first use with the
+/// configuration passed to [`init_global`], or the default
+/// configuration if there was none.
+///
+/// Libraries should prefer this to creating their own pools, so that
+/// they can share threads rather than multiplying the number of
+/// workers.
+///
+/// Natively, there is one pool for the whole process.  On the Web,
+/// workers are owned by the JavaScript thread that created them, so
+/// the pool can't be shared between threads: instead each thread that
+/// calls this gets its own pool with the shared configuration, which
+/// is dropped, with its workers, when the thread exits.  Typically
+/// only the main thread calls it.
+#[must_use]
+pub fn global() -> GlobalPool {
+    #[cfg(not(target_family = "wasm"))]
+    {
+        static POOL: OnceLock<Pool<web_thread::Thread>> = OnceLock::new();
+        GlobalPool(POOL.get_or_init(build))
+    }
+    #[cfg(target_family = "wasm")]
+    {
+        thread_local! {
+            static POOL: std::rc::Rc<Pool<web_thread::Thread>> = std::rc::Rc::new(build());
+        }
+        GlobalPool(POOL.with(std::rc::Rc::clone))
+    }
+}
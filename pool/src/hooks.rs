@@ -0,0 +1,84 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{sync::Arc, time::Duration};
+
+use crate::Error;
+
+/// Callbacks for events in the life of a [`Pool`](crate::Pool), for
+/// integrating the pool with telemetry, busy indicators, or logging.
+/// Register them with [`Pool::set_hooks`](crate::Pool::set_hooks) or
+/// [`PoolBuilder::hooks`](crate::PoolBuilder::hooks).
+///
+/// Every method does nothing by default.  Hooks are called
+/// synchronously on whichever thread caused the event, so they should
+/// return quickly.  The pool is never locked while a hook runs, so
+/// hooks may call back into the pool.
+#[allow(unused_variables)]
+pub trait Hooks: Send + Sync {
+    /// A new thread with index `thread` was added to the pool.
+    fn on_thread_spawned(&self, thread: usize) {}
+
+    /// The thread with index `thread` was removed from the pool.
+    fn on_thread_retired(&self, thread: usize) {}
+
+    /// A task had to queue in `lane` because no thread was available.
+    fn on_task_queued(&self, lane: &str) {}
+
+    /// A task from `lane` started running on `thread`.
+    fn on_task_started(&self, lane: &str, thread: usize) {}
+
+    /// A task from `lane` finished running on `thread`.
+    fn on_task_finished(&self, lane: &str, thread: usize) {}
+
+    /// A task from `lane` failed to produce a result, either because
+    /// it was aborted or because its thread failed.
+    fn on_task_failed(&self, lane: &str, error: &Error) {}
+
+    /// A client in `lane` was lent `thread` after waiting `wait`.
+    fn on_resource_acquired(&self, lane: &str, thread: usize, wait: Duration) {}
+
+    /// A client in `lane` returned `thread` to the pool after holding
+    /// it for `held`.
+    fn on_resource_released(&self, lane: &str, thread: usize, held: Duration) {}
+
+    /// The task `task` from `lane` has held `thread` for `elapsed`,
+    /// longer than the threshold set with
+    /// [`PoolBuilder::long_task_threshold`](crate::PoolBuilder::long_task_threshold).
+    /// Called at most once per task, either while the task is still
+    /// running or when it finishes.
+    fn on_long_task(&self, lane: &str, thread: usize, task: &str, elapsed: Duration) {}
+
+    /// More clients are queued than the watermark set with
+    /// [`PoolBuilder::queue_watermark`](crate::PoolBuilder::queue_watermark),
+    /// leaving `waiting` queued in all lanes.  Called each time the
+    /// queue grows past the watermark.
+    fn on_queue_saturated(&self, waiting: usize) {}
+}
+
+/// The hooks of a pool with none registered.
+pub(crate) struct NoHooks;
+
+impl Hooks for NoHooks {}
+
+/// The hooks to call for a particular task.
+#[derive(Clone)]
+pub(crate) struct TaskHooks {
+    pub(crate) hooks: Arc<dyn Hooks>,
+    pub(crate) lane: Arc<str>,
+    pub(crate) thread: usize,
+}
+
+impl TaskHooks {
+    pub(crate) fn started(&self) {
+        self.hooks.on_task_started(&self.lane, self.thread);
+    }
+
+    pub(crate) fn finished(&self) {
+        self.hooks.on_task_finished(&self.lane, self.thread);
+    }
+
+    pub(crate) fn failed(&self, error: &Error) {
+        self.hooks.on_task_failed(&self.lane, error);
+    }
+}
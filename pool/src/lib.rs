@@ -1,106 +1,379 @@
 // Copyright (c) Zefchain Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+// resources are stored in per-slot `Arc`s, so guards can own them
+// without any pointer tricks
+#![forbid(unsafe_code)]
+
+/*!
+# `web-thread-pool`
+
+Pools of threads from `web-thread`, and of other resources, shared
+fairly between the parts of an application that use them.
+
+## Features
+
+- `web`: on Wasm targets, run the pool's threads as Web workers with
+  `web-thread`.  Without it the threads come from `web-thread-shim`,
+  which on Wasm runs tasks on the current thread.  The backend is
+  chosen by `web-thread-backend`, as for `web-thread-select`, so
+  enabling `web` on either crate switches both.
+- `force-shim`: use the shim even with the `web` feature, for
+  environments that can't run module workers with shared memory.
+- `rayon`: run Rayon thread pools on the pool's threads.
+- `tracing`: report the pool's activity with `tracing`.
+*/
+
 use std::{
     pin::Pin,
-    sync::RwLock,
     task::{Context, Poll},
+    time::Duration,
 };
 
-use web_thread_select as web_thread;
+use futures::task::{FutureObj, Spawn, SpawnError};
+use web_thread_backend as web_thread;
+use web_time::Instant;
+
+mod builder;
+mod detached;
+mod global;
+mod hooks;
+pub mod pool;
+#[cfg(feature = "rayon")]
+mod rayon;
+mod retry;
+mod trace;
+
+pub use builder::PoolBuilder;
+pub use detached::set_error_handler;
+pub use global::{GlobalPool, global, init_global};
+pub use hooks::Hooks;
+use hooks::TaskHooks;
+use pool::{Claim, Job, job};
+pub use pool::{DEFAULT_LANE, Lane, MappedGuard, OwnedGuard, Pool, Reuse, Stats};
+pub use retry::RetryPolicy;
+pub use web_thread::CancellationToken;
+
+/// The type of errors that may arise from operations in this crate.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    #[error(transparent)]
+    Thread(#[from] web_thread::Error),
+    #[error("timed out waiting for a resource to become available")]
+    PoolTimeout,
+    #[error("task aborted by `Pool::abort_all`")]
+    Aborted,
+    #[error("the global pool has already been initialized")]
+    GlobalInitialized,
+    #[error("the pool has been closed")]
+    Closed,
+    #[error("failed to create a pool resource: {0}")]
+    Factory(Box<dyn std::error::Error + Send + Sync>),
+}
 
-type Id = usize;
+/// Convenience alias for `Result<T, Error>`.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
 
-pub use web_thread::Error;
 pub type Task<T> = Guard<web_thread::Task<T>>;
 pub type SendTask<T> = Guard<web_thread::SendTask<T>>;
 
-struct ResourceHandle {
-    id: Id,
-    sender: flume::Sender<Id>,
+pin_project_lite::pin_project! {
+    /// A future that causes the thread to be considered claimed until
+    /// the task it is running completes.
+    ///
+    /// Dropping the guard doesn't release the thread early: the
+    /// thread remains claimed until the task finishes running.  Use
+    /// [`Guard::detach`] to release the thread immediately.
+    pub struct Guard<F> {
+        // `None` if the task failed before it claimed a thread
+        #[pin]
+        future: Option<F>,
+        claim: Option<Claim>,
+        span: trace::Span,
+        hooks: Option<TaskHooks>,
+        // why the task failed to claim a thread
+        error: Option<Error>,
+    }
 }
 
-impl Drop for ResourceHandle {
-    fn drop(&mut self) {
-        let _ = self.sender.send(self.id);
+impl<F> Guard<F> {
+    fn new(claim: Claim, span: trace::Span, hooks: TaskHooks, future: F) -> Self {
+        Self {
+            future: Some(future),
+            claim: Some(claim),
+            span,
+            hooks: Some(hooks),
+            error: None,
+        }
+    }
+
+    fn failed(error: Error) -> Self {
+        Self {
+            future: None,
+            claim: None,
+            span: trace::Span::none(),
+            hooks: None,
+            error: Some(error),
+        }
+    }
+
+    /// Replace the thread running the task with a new one, if the
+    /// task hasn't yet released it.
+    fn respawn_thread(&self, pool: &Pool<web_thread::Thread>) {
+        if let Some(claim) = &self.claim {
+            claim.replace_resource(pool);
+        }
+    }
+
+    /// Release the thread back to the pool immediately, even if the
+    /// task is still running.  The pool may then run other tasks on
+    /// the thread concurrently with this one.
+    ///
+    /// The guard can still be awaited to retrieve the task's result.
+    pub fn detach(&self) {
+        if let Some(claim) = &self.claim {
+            claim.release();
+        }
     }
 }
 
-/// A pool of shared resources, each of which can only be used once at a time.
-pub struct Pool {
-    threads: RwLock<Vec<web_thread::Thread>>,
-    capacity: usize,
-    sender: flume::Sender<Id>,
-    // we have to use an mpmc receiver here in order to be able to
-    // receive using a reference: otherwise we would have to hold the
-    // mutex guard over the await
-    receiver: flume::Receiver<Id>,
+impl<T, F: Future<Output = Result<T, web_thread::Error>>> Future for Guard<F> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let Some(future) = this.future.as_pin_mut() else {
+            return Poll::Ready(Err(this.error.take().unwrap_or(Error::Aborted)));
+        };
+        let output = std::task::ready!(future.poll(context)).map_err(Error::from);
+        trace::outcome(this.span, &output);
+        if let (Err(error), Some(hooks)) = (&output, this.hooks) {
+            hooks.failed(error);
+        }
+        Poll::Ready(output)
+    }
 }
 
 pin_project_lite::pin_project! {
-    /// A future that, while running, causes the thread to be considered
-    /// claimed.
-    pub struct Guard<F> {
+    /// A future running on a pool thread that releases the thread's
+    /// claim when it completes.
+    struct Released<F> {
         #[pin]
         future: F,
-        handle: ResourceHandle,
+        claim: Claim,
+        span: trace::Span,
+        hooks: TaskHooks,
     }
 }
 
-impl<F: Future> Future for Guard<F> {
+impl<F: Future> Future for Released<F> {
     type Output = F::Output;
 
     fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
-        self.project().future.poll(context)
+        let this = self.project();
+        let future = this.future;
+        let output = std::task::ready!(trace::in_span(this.span, || future.poll(context)));
+        this.hooks.finished();
+        this.claim.release();
+        Poll::Ready(output)
     }
 }
 
-impl Pool {
-    /// Create a new pool of `capacity` items, using `factory` to
-    /// generate new items.
-    pub fn new(capacity: usize) -> Self {
-        let (sender, receiver) = flume::unbounded();
-        Self {
-            threads: RwLock::new(Vec::with_capacity(capacity)),
-            capacity,
-            sender,
-            receiver,
+/// `code` to run on a pool thread, wrapped to run in `span`, call
+/// `hooks`, and release `claim` once the future it returns completes.
+struct Releasing<Code> {
+    code: Code,
+    claim: Claim,
+    span: trace::Span,
+    hooks: TaskHooks,
+}
+
+impl<Code> Releasing<Code> {
+    fn call<Context, F: Future>(self, context: Context) -> Released<F>
+    where
+        Code: FnOnce(Context) -> F,
+    {
+        self.hooks.started();
+        Released {
+            future: trace::in_span(&self.span, || (self.code)(context)),
+            claim: self.claim,
+            span: self.span,
+            hooks: self.hooks,
         }
     }
+}
 
-    async fn get(&self) -> Id {
-        let mut id = self.receiver.try_recv().ok();
+/// Create the span for a task running `code` on `thread`, which was
+/// requested from `lane` at `requested`, and name the task in reports
+/// of long tasks.
+fn task_span<T>(
+    lane: &Lane<'_, T>,
+    thread: &pool::Guard<'_, T>,
+    requested: Instant,
+    code: &impl Sized,
+) -> trace::Span {
+    let name = std::any::type_name_of_val(code);
+    thread.set_task_name(name);
+    trace::task(lane.name(), thread.id(), requested.elapsed(), name)
+}
 
-        if id.is_none() {
-            let mut threads = self.threads.write().unwrap();
-            let len = threads.len();
-            if len < self.capacity {
-                threads.push(web_thread::Thread::new());
-                id = Some(len);
-            }
+/// Run the jobs sent to a pool thread by the pool, one after another.
+fn run_jobs(thread: &web_thread::Thread, jobs: flume::Receiver<Job>) {
+    drop(thread.run((), |()| async move {
+        while let Ok(job) = jobs.recv_async().await {
+            job().await;
         }
+    }));
+}
 
-        if id.is_none() {
-            id = self.receiver.recv_async().await.ok();
-        }
+/// Run `future` in the background: on this thread on the Web, so
+/// that it never waits for the pool's lock, or natively on a thread of
+/// its own.
+#[cfg(target_family = "wasm")]
+fn spawn_background(future: impl Future<Output = ()> + 'static) {
+    wasm_bindgen_futures::spawn_local(future);
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn spawn_background(future: impl Future<Output = ()> + Send + 'static) {
+    std::thread::spawn(move || futures::executor::block_on(future));
+}
+
+/// Running code on a pool of threads, for code generic over
+/// [`Pool`]s and their [`Lane`]s.  Both also have these methods
+/// inherently, so calling them doesn't need the trait in scope.
+pub trait Run {
+    /// Run a job, creating a new thread if necessary or waiting for
+    /// one to become available.
+    fn run<Context: web_thread::Post, F: Future<Output: web_thread::Post> + 'static>(
+        &self,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Send + 'static,
+    ) -> impl Future<Output = Task<F::Output>>;
+
+    /// Like [`Run::run`], but the output can be sent through Rust
+    /// memory without `Post`ing.
+    fn run_send<Context: web_thread::Post, F: Future<Output: Send> + 'static>(
+        &self,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Send + 'static,
+    ) -> impl Future<Output = SendTask<F::Output>>;
+
+    /// Like [`Run::run`], but giving up if no thread becomes
+    /// available within `timeout`.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::PoolTimeout`] if no thread became available in time.
+    fn run_with_acquire_timeout<
+        Context: web_thread::Post,
+        F: Future<Output: web_thread::Post> + 'static,
+    >(
+        &self,
+        timeout: Duration,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Send + 'static,
+    ) -> impl Future<Output = Result<Task<F::Output>>>;
+
+    /// Run a job and wait for its result, retrying according to
+    /// `policy` if it fails.  If the job's thread failed, it is
+    /// replaced with a new thread before the job is retried.
+    ///
+    /// # Errors
+    ///
+    /// The error from the last attempt, if every attempt failed or
+    /// the error wasn't one `policy` retries.
+    fn run_with_retry<
+        Context: web_thread::Post + Clone,
+        F: Future<Output: web_thread::Post> + 'static,
+    >(
+        &self,
+        policy: &RetryPolicy,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Clone + Send + 'static,
+    ) -> impl Future<Output = Result<F::Output>>;
+}
+
+impl Pool<web_thread::Thread> {
+    /// Create a new pool of `capacity` threads.
+    ///
+    /// The pool may additionally spawn up to four times `capacity`
+    /// threads for blocking tasks.
+    pub fn new(capacity: usize) -> Self {
+        Self::with_blocking_capacity(capacity, capacity.saturating_mul(4))
+    }
+
+    /// Create a new pool of `capacity` threads, with up to
+    /// `blocking_capacity` further threads for
+    /// [`Pool::spawn_blocking`].
+    pub fn with_blocking_capacity(capacity: usize, blocking_capacity: usize) -> Self {
+        let mut pool = Self::with_factory(capacity, web_thread::Thread::new);
+        pool.executor = Some(run_jobs);
+        pool.blocking_capacity = blocking_capacity;
+        // threads finish their tasks on the Web while the pool's owner
+        // may be using it, and the owner can't wait for them to unlock
+        // it
+        #[cfg(target_family = "wasm")]
+        wasm_bindgen_futures::spawn_local(pool.release_on_this_thread());
+        pool
+    }
+
+    /// Spawn a batch of futures onto a single thread, where they will
+    /// run one after another.  Threads that are or become idle will
+    /// steal futures from the batch, so it may not all run on the
+    /// same thread.
+    pub fn spawn_batch(&self, futures: impl IntoIterator<Item = FutureObj<'static, ()>>) {
+        self.queue_batch(futures.into_iter().map(job));
+    }
+
+    /// Spawn a future that must run on the thread with index
+    /// `thread`, and won't be stolen by other threads.  Threads are
+    /// indexed in order of creation, and will be created if
+    /// necessary.
+    ///
+    /// # Panics
+    ///
+    /// If `thread` is not less than the pool's capacity.
+    pub fn spawn_pinned(&self, thread: usize, future: FutureObj<'static, ()>) {
+        self.queue_pinned(thread, job(future));
+    }
+
+    /// Run a blocking function on the pool's separate set of blocking
+    /// threads, creating a new thread if necessary or waiting for one
+    /// to become available.
+    ///
+    /// This should be used for work that doesn't yield, such as
+    /// synchronous cryptography, synchronous OPFS access, or code that
+    /// uses `Atomics.wait`.
+    pub async fn spawn_blocking<Context: web_thread::Post, T: web_thread::Post + 'static>(
+        &self,
+        context: Context,
+        code: impl FnOnce(Context) -> T + Send + 'static,
+    ) -> Task<T> {
+        self.blocking_pool()
+            .run(context, |context| futures::future::ready(code(context)))
+            .await
+    }
 
-        id.expect("we hold a sender")
+    fn blocking_pool(&self) -> &Self {
+        self.blocking.get_or_init(|| {
+            let blocking = Pool::with_blocking_capacity(self.blocking_capacity, 0);
+            blocking.set_hooks_arc(self.hooks());
+            Box::new(blocking)
+        })
     }
+}
 
-    /// Run a job, creating a new thread if necessary or waiting for one to become available.
+impl Pool<web_thread::Thread> {
+    /// Run a job, creating a new thread if necessary or waiting for
+    /// one to become available.
     pub async fn run<Context: web_thread::Post, F: Future<Output: web_thread::Post> + 'static>(
         &self,
         context: Context,
         code: impl FnOnce(Context) -> F + Send + 'static,
     ) -> Task<F::Output> {
-        let id = self.get().await;
-        Guard {
-            future: self.threads.read().unwrap()[id].run(context, code),
-            handle: ResourceHandle {
-                sender: self.sender.clone(),
-                id,
-            },
-        }
+        self.lane(DEFAULT_LANE).run(context, code).await
     }
 
     /// Like [`Pool::run`], but the output can be sent through Rust
@@ -110,13 +383,838 @@ impl Pool {
         context: Context,
         code: impl FnOnce(Context) -> F + Send + 'static,
     ) -> SendTask<F::Output> {
-        let id = self.get().await;
-        Guard {
-            future: self.threads.read().unwrap()[id].run_send(context, code),
-            handle: ResourceHandle {
-                sender: self.sender.clone(),
-                id,
+        self.lane(DEFAULT_LANE).run_send(context, code).await
+    }
+
+    /// Like [`Pool::run`], but giving up if no thread becomes
+    /// available within `timeout`.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::PoolTimeout`] if no thread became available in time.
+    pub async fn run_with_acquire_timeout<
+        Context: web_thread::Post,
+        F: Future<Output: web_thread::Post> + 'static,
+    >(
+        &self,
+        timeout: Duration,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Send + 'static,
+    ) -> Result<Task<F::Output>> {
+        self.lane(DEFAULT_LANE)
+            .run_with_acquire_timeout(timeout, context, code)
+            .await
+    }
+
+    /// Run a job and wait for its result, retrying according to
+    /// `policy` if it fails.  If the job's thread failed, it is
+    /// replaced with a new thread before the job is retried.
+    ///
+    /// # Errors
+    ///
+    /// The error from the last attempt, if every attempt failed or
+    /// the error wasn't one `policy` retries.
+    pub async fn run_with_retry<
+        Context: web_thread::Post + Clone,
+        F: Future<Output: web_thread::Post> + 'static,
+    >(
+        &self,
+        policy: &RetryPolicy,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Clone + Send + 'static,
+    ) -> Result<F::Output> {
+        self.lane(DEFAULT_LANE)
+            .run_with_retry(policy, context, code)
+            .await
+    }
+}
+
+impl<'a> Lane<'a, web_thread::Thread> {
+    /// Run a job on a thread of this lane, creating a new thread if
+    /// necessary or waiting for one to become available.
+    pub async fn run<Context: web_thread::Post, F: Future<Output: web_thread::Post> + 'static>(
+        &self,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Send + 'static,
+    ) -> Task<F::Output> {
+        self.submit(self.get(), code, |thread, code| {
+            thread.run(context, move |context| code.call(context))
+        })
+        .await
+        .unwrap_or_else(Guard::failed)
+    }
+
+    /// Like [`Lane::run`], but the output can be sent through Rust
+    /// memory without `Post`ing.
+    pub async fn run_send<Context: web_thread::Post, F: Future<Output: Send> + 'static>(
+        &self,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Send + 'static,
+    ) -> SendTask<F::Output> {
+        self.submit(self.get(), code, |thread, code| {
+            thread.run_send(context, move |context| code.call(context))
+        })
+        .await
+        .unwrap_or_else(Guard::failed)
+    }
+
+    /// Like [`Lane::run`], but giving up if no thread becomes
+    /// available within `timeout`.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::PoolTimeout`] if no thread became available in time.
+    pub async fn run_with_acquire_timeout<
+        Context: web_thread::Post,
+        F: Future<Output: web_thread::Post> + 'static,
+    >(
+        &self,
+        timeout: Duration,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Send + 'static,
+    ) -> Result<Task<F::Output>> {
+        self.submit(self.get_timeout(timeout), code, |thread, code| {
+            thread.run(context, move |context| code.call(context))
+        })
+        .await
+    }
+
+    /// Claim the thread `acquire` resolves to and `submit` `code` to
+    /// it, wrapped to release the thread once it completes.
+    async fn submit<Code, R>(
+        &self,
+        acquire: impl Future<Output = Result<pool::Guard<'a, web_thread::Thread>>>,
+        code: Code,
+        submit: impl FnOnce(&web_thread::Thread, Releasing<Code>) -> R,
+    ) -> Result<Guard<R>> {
+        let requested = Instant::now();
+        let thread = acquire.await.inspect_err(|error| {
+            self.hooks().on_task_failed(self.name(), error);
+        })?;
+        let span = task_span(self, &thread, requested, &code);
+        let hooks = thread.task_hooks();
+        let (thread, claim) = thread.into_claim();
+        let future = submit(
+            &thread,
+            Releasing {
+                code,
+                claim: claim.clone(),
+                span: span.clone(),
+                hooks: hooks.clone(),
             },
+        );
+        Ok(Guard::new(claim, span, hooks, future))
+    }
+
+    /// Like [`Lane::run`], but waiting for the result and retrying
+    /// according to `policy` if the job fails.
+    ///
+    /// # Errors
+    ///
+    /// The error from the last attempt, if every attempt failed or
+    /// the error wasn't one `policy` retries.
+    pub async fn run_with_retry<
+        Context: web_thread::Post + Clone,
+        F: Future<Output: web_thread::Post> + 'static,
+    >(
+        &self,
+        policy: &RetryPolicy,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Clone + Send + 'static,
+    ) -> Result<F::Output> {
+        retry::run_with_retry(self, policy, context, code).await
+    }
+}
+
+impl Run for Pool<web_thread::Thread> {
+    async fn run<Context: web_thread::Post, F: Future<Output: web_thread::Post> + 'static>(
+        &self,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Send + 'static,
+    ) -> Task<F::Output> {
+        Pool::run(self, context, code).await
+    }
+
+    async fn run_send<Context: web_thread::Post, F: Future<Output: Send> + 'static>(
+        &self,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Send + 'static,
+    ) -> SendTask<F::Output> {
+        Pool::run_send(self, context, code).await
+    }
+
+    async fn run_with_acquire_timeout<
+        Context: web_thread::Post,
+        F: Future<Output: web_thread::Post> + 'static,
+    >(
+        &self,
+        timeout: Duration,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Send + 'static,
+    ) -> Result<Task<F::Output>> {
+        Pool::run_with_acquire_timeout(self, timeout, context, code).await
+    }
+
+    async fn run_with_retry<
+        Context: web_thread::Post + Clone,
+        F: Future<Output: web_thread::Post> + 'static,
+    >(
+        &self,
+        policy: &RetryPolicy,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Clone + Send + 'static,
+    ) -> Result<F::Output> {
+        Pool::run_with_retry(self, policy, context, code).await
+    }
+}
+
+impl Run for Lane<'_, web_thread::Thread> {
+    async fn run<Context: web_thread::Post, F: Future<Output: web_thread::Post> + 'static>(
+        &self,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Send + 'static,
+    ) -> Task<F::Output> {
+        Lane::run(self, context, code).await
+    }
+
+    async fn run_send<Context: web_thread::Post, F: Future<Output: Send> + 'static>(
+        &self,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Send + 'static,
+    ) -> SendTask<F::Output> {
+        Lane::run_send(self, context, code).await
+    }
+
+    async fn run_with_acquire_timeout<
+        Context: web_thread::Post,
+        F: Future<Output: web_thread::Post> + 'static,
+    >(
+        &self,
+        timeout: Duration,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Send + 'static,
+    ) -> Result<Task<F::Output>> {
+        Lane::run_with_acquire_timeout(self, timeout, context, code).await
+    }
+
+    async fn run_with_retry<
+        Context: web_thread::Post + Clone,
+        F: Future<Output: web_thread::Post> + 'static,
+    >(
+        &self,
+        policy: &RetryPolicy,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Clone + Send + 'static,
+    ) -> Result<F::Output> {
+        Lane::run_with_retry(self, policy, context, code).await
+    }
+}
+
+/// Spawning a future onto a [`Pool`] runs it to completion on the next
+/// available thread, claiming the thread until it completes.  If no
+/// thread is available the future is queued in the default lane.
+/// Spawning fails once the pool is closed.
+impl Spawn for Pool<web_thread::Thread> {
+    fn spawn_obj(&self, future: FutureObj<'static, ()>) -> Result<(), SpawnError> {
+        // the job is dropped without the lock held, as its future may
+        // hold claimed threads
+        self.spawn_job(job(future))
+            .map_err(|_| SpawnError::shutdown())
+    }
+
+    fn status(&self) -> Result<(), SpawnError> {
+        if self.is_closed() {
+            Err(SpawnError::shutdown())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn spawn_runs_to_completion() {
+    use futures::task::SpawnExt as _;
+
+    let pool = Pool::new(2);
+    let (sender, receiver) = flume::unbounded();
+    for i in 0..10 {
+        let sender = sender.clone();
+        pool.spawn(async move { sender.send(i).unwrap() }).unwrap();
+    }
+    drop(sender);
+
+    let mut results: Vec<_> = receiver.iter().collect();
+    results.sort_unstable();
+    assert_eq!(results, (0..10).collect::<Vec<_>>());
+}
+
+#[test]
+fn lanes_share_threads_by_weight() {
+    use std::sync::{Arc, Mutex};
+
+    use futures::{StreamExt as _, stream::FuturesUnordered};
+
+    let pool = Pool::new(1);
+    pool.lane("interactive").set_weight(3);
+    let order = Arc::new(Mutex::new(Vec::new()));
+
+    futures::executor::block_on(async {
+        // hold the only thread while both lanes queue up work
+        let (unblock, blocked) = flume::bounded::<()>(0);
+        let blocker = pool
+            .run_send((), move |()| async move { blocked.recv_async().await })
+            .await;
+        let tasks: FuturesUnordered<_> = (0..8)
+            .map(|i| {
+                let lane = if i % 2 == 0 { "interactive" } else { "bulk" };
+                let order = order.clone();
+                let pool = &pool;
+                async move {
+                    let task = pool
+                        .lane(lane)
+                        .run((), |()| async {
+                            std::thread::sleep(std::time::Duration::from_millis(10));
+                        })
+                        .await;
+                    order.lock().unwrap().push(lane);
+                    task.await.unwrap();
+                }
+            })
+            .collect();
+        let mut tasks = tasks.collect::<()>();
+        // let every task enqueue before releasing the thread
+        assert!(futures::poll!(&mut tasks).is_pending());
+        unblock.send_async(()).await.unwrap();
+        blocker.await.unwrap().unwrap();
+        tasks.await;
+    });
+
+    let order = order.lock().unwrap();
+    let interactive = order[..4]
+        .iter()
+        .filter(|&&lane| lane == "interactive")
+        .count();
+    assert!(interactive >= 3, "{order:?}");
+}
+
+#[test]
+fn acquire_timeout() {
+    let pool = Pool::new(1);
+    futures::executor::block_on(async {
+        let (unblock, blocked) = flume::bounded::<()>(0);
+        let blocker = pool
+            .run_send((), move |()| async move { blocked.recv_async().await })
+            .await;
+        assert!(matches!(
+            pool.run_with_acquire_timeout(Duration::from_millis(10), (), |()| async {})
+                .await,
+            Err(Error::PoolTimeout),
+        ));
+        unblock.send_async(()).await.unwrap();
+        blocker.await.unwrap().unwrap();
+        pool.run_with_acquire_timeout(Duration::from_millis(10), (), |()| async {})
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+    });
+}
+
+#[test]
+fn blocking_tasks_use_separate_threads() {
+    let pool = Pool::with_blocking_capacity(1, 1);
+    futures::executor::block_on(async {
+        let (sender, receiver) = flume::bounded(0);
+        let blocked = pool
+            .spawn_blocking((), move |()| receiver.recv().unwrap())
+            .await;
+        assert_eq!(
+            pool.run(3u8, |three| async move { three + 5 })
+                .await
+                .await
+                .unwrap(),
+            8
+        );
+        sender.send(12u8).unwrap();
+        assert_eq!(blocked.await.unwrap(), 12);
+    });
+}
+
+#[test]
+fn idle_threads_steal_unpinned_work() {
+    let pool = Pool::new(2);
+    let (sender, receiver) = flume::unbounded();
+    let job = |sender: flume::Sender<_>| {
+        FutureObj::new(Box::new(async move {
+            std::thread::sleep(Duration::from_millis(10));
+            sender.send(std::thread::current().id()).unwrap();
+        }))
+    };
+
+    // start both threads
+    for _ in 0..2 {
+        pool.spawn_pinned(1, job(sender.clone()));
+    }
+    let pinned = [receiver.recv().unwrap(), receiver.recv().unwrap()];
+    assert_eq!(pinned[0], pinned[1]);
+
+    pool.spawn_batch((0..8).map(|_| job(sender.clone())));
+    drop(sender);
+    let batch: std::collections::HashSet<_> = receiver.iter().collect();
+    assert_eq!(batch.len(), 2);
+}
+
+#[test]
+fn spawning_fails_once_closed() {
+    use futures::task::SpawnExt as _;
+
+    let pool = Pool::new(1);
+    assert!(pool.status().is_ok());
+    pool.close();
+    assert!(pool.status().unwrap_err().is_shutdown());
+    assert!(pool.spawn(async {}).unwrap_err().is_shutdown());
+}
+
+#[test]
+fn abort_all() {
+    use futures::task::SpawnExt as _;
+
+    let pool = Pool::new(1);
+    futures::executor::block_on(async {
+        let token = pool.cancellation_token();
+        let running = pool
+            .run_send((), |()| async move {
+                token.cancelled().await;
+                "cancelled"
+            })
+            .await;
+        let queued = pool.run((), |()| async {});
+        let (sender, receiver) = futures::channel::oneshot::channel::<()>();
+        pool.spawn(async move { drop(sender) }).unwrap();
+        futures::pin_mut!(queued);
+        assert!(futures::poll!(&mut queued).is_pending());
+
+        pool.abort_all();
+        assert!(matches!(queued.await.await, Err(Error::Aborted)));
+        assert!(receiver.await.is_err());
+        assert_eq!(running.await.unwrap(), "cancelled");
+
+        // the pool is still usable
+        assert_eq!(
+            pool.run(3u8, |three| async move { three + 5 })
+                .await
+                .await
+                .unwrap(),
+            8
+        );
+    });
+}
+
+#[test]
+fn threads_are_claimed_until_tasks_complete() {
+    let pool = Pool::new(1);
+    let timeout = Duration::from_millis(10);
+    futures::executor::block_on(async {
+        let (sender, receiver) = flume::bounded::<()>(0);
+        drop(
+            pool.run_send((), move |()| async move { receiver.recv_async().await })
+                .await,
+        );
+        assert!(matches!(
+            pool.run_with_acquire_timeout(timeout, (), |()| async {})
+                .await,
+            Err(Error::PoolTimeout),
+        ));
+
+        sender.send_async(()).await.unwrap();
+        pool.run((), |()| async {}).await.await.unwrap();
+
+        let (sender, receiver) = flume::bounded::<u8>(0);
+        let detached = pool
+            .run_send((), move |()| async move { receiver.recv_async().await })
+            .await;
+        detached.detach();
+        pool.run_with_acquire_timeout(timeout, (), |()| async {})
+            .await
+            .unwrap()
+            .await
+            .unwrap();
+        sender.send_async(5).await.unwrap();
+        assert_eq!(detached.await.unwrap(), Ok(5));
+    });
+}
+
+#[test]
+fn threads_are_released_on_the_owning_thread() {
+    use futures::task::LocalSpawnExt as _;
+
+    struct Releases(flume::Sender<std::thread::ThreadId>);
+
+    impl Hooks for Releases {
+        fn on_resource_released(&self, _lane: &str, _thread: usize, _held: Duration) {
+            self.0.send(std::thread::current().id()).unwrap();
+        }
+    }
+
+    let (sender, released) = flume::unbounded();
+    let pool = PoolBuilder::new()
+        .capacity(2)
+        .hooks(Releases(sender))
+        .build();
+    let mut executor = futures::executor::LocalPool::new();
+    executor
+        .spawner()
+        .spawn_local(pool.release_on_this_thread())
+        .unwrap();
+    executor.run_until(async {
+        // the threads finish tasks while others are waiting to
+        // acquire them
+        let results = futures::future::join_all((0..20u8).map(|i| {
+            let pool = &pool;
+            async move { pool.run(i, |i| async move { i * 2 }).await.await }
+        }))
+        .await;
+        assert!(results.into_iter().all(|result| result.is_ok()));
+        for _ in 0..20 {
+            assert_eq!(
+                released.recv_async().await.unwrap(),
+                std::thread::current().id()
+            );
+        }
+    });
+}
+
+#[test]
+fn global_pool() {
+    init_global(PoolBuilder::new().capacity(2)).unwrap();
+    assert!(matches!(
+        init_global(PoolBuilder::new()),
+        Err(Error::GlobalInitialized)
+    ));
+    assert!(std::ptr::eq(&*global(), &*global()));
+    futures::executor::block_on(async {
+        assert_eq!(
+            global()
+                .run(3u8, |three| async move { three + 5 })
+                .await
+                .await
+                .unwrap(),
+            8
+        );
+    });
+}
+
+#[test]
+fn hooks_observe_lifecycle() {
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Hooks for Recorder {
+        fn on_thread_spawned(&self, thread: usize) {
+            self.0.lock().unwrap().push(format!("spawned {thread}"));
+        }
+
+        fn on_task_queued(&self, lane: &str) {
+            self.0.lock().unwrap().push(format!("queued {lane}"));
+        }
+
+        fn on_task_started(&self, lane: &str, thread: usize) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("started {lane} {thread}"));
+        }
+
+        fn on_task_finished(&self, lane: &str, thread: usize) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("finished {lane} {thread}"));
+        }
+    }
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let pool = PoolBuilder::new()
+        .capacity(1)
+        .hooks(Recorder(events.clone()))
+        .build();
+    futures::executor::block_on(async {
+        let lane = pool.lane("ui");
+        let (started, has_started) = flume::bounded(0);
+        let (unblock, blocked) = flume::bounded::<()>(0);
+        let first = lane
+            .run_send((), move |()| async move {
+                started.send_async(()).await.unwrap();
+                blocked.recv_async().await.unwrap();
+            })
+            .await;
+        has_started.recv_async().await.unwrap();
+        let second = lane.run((), |()| async {});
+        futures::pin_mut!(second);
+        assert!(futures::poll!(&mut second).is_pending());
+        unblock.send_async(()).await.unwrap();
+        first.await.unwrap();
+        second.await.await.unwrap();
+    });
+
+    assert_eq!(
+        *events.lock().unwrap(),
+        [
+            "spawned 0",
+            "started ui 0",
+            "queued ui",
+            "finished ui 0",
+            "started ui 0",
+            "finished ui 0",
+        ],
+    );
+}
+
+#[test]
+fn heavy_tasks_wait_for_permits() {
+    let pool = Pool::new(2);
+    let timeout = Duration::from_millis(10);
+    let heavy = pool.lane("heavy").with_permits(2);
+    futures::executor::block_on(async {
+        let (unblock, blocked) = flume::bounded::<()>(0);
+        let light = pool
+            .run_send((), move |()| async move { blocked.recv_async().await })
+            .await;
+        // a thread is free, but only one permit
+        assert!(matches!(
+            heavy
+                .run_with_acquire_timeout(timeout, (), |()| async {})
+                .await,
+            Err(Error::PoolTimeout),
+        ));
+        unblock.send_async(()).await.unwrap();
+        light.await.unwrap().unwrap();
+
+        let (unblock, blocked) = flume::bounded::<()>(0);
+        let heavy = heavy
+            .run_send((), move |()| async move { blocked.recv_async().await })
+            .await;
+        assert!(matches!(
+            pool.run_with_acquire_timeout(timeout, (), |()| async {})
+                .await,
+            Err(Error::PoolTimeout),
+        ));
+        unblock.send_async(()).await.unwrap();
+        heavy.await.unwrap().unwrap();
+        pool.run((), |()| async {}).await.await.unwrap();
+    });
+}
+
+#[test]
+fn detached_failures_are_reported() {
+    let (sender, receiver) = flume::unbounded();
+    set_error_handler(move |error| sender.send(error.to_string()).unwrap());
+
+    let pool = Pool::new(1);
+    pool.run_detached(3u8, |three| async move {
+        assert_eq!(three, 3);
+        Ok::<_, std::fmt::Error>(())
+    });
+    pool.run_detached((), |()| async { Err(std::fmt::Error) });
+    assert_eq!(receiver.recv().unwrap(), std::fmt::Error.to_string());
+}
+
+#[test]
+fn checked_out_threads_are_exclusive() {
+    let pool = Pool::new(1);
+    futures::executor::block_on(async {
+        let thread = pool.checkout().await.unwrap();
+        for i in 0..3u8 {
+            assert_eq!(
+                thread.run(i, |i| async move { i * 2 }).await.unwrap(),
+                i * 2
+            );
+        }
+        assert!(matches!(
+            pool.run_with_acquire_timeout(Duration::from_millis(10), (), |()| async {})
+                .await,
+            Err(Error::PoolTimeout),
+        ));
+        drop(thread);
+        pool.run((), |()| async {}).await.await.unwrap();
+    });
+}
+
+#[test]
+fn adopted_threads_join_the_rotation() {
+    let pool = Pool::new(1);
+    pool.adopt(web_thread::Thread::new());
+    futures::executor::block_on(async {
+        let first = pool.checkout().await.unwrap();
+        let second = pool.checkout().await.unwrap();
+        assert!(!std::ptr::eq(&*first, &*second));
+    });
+}
+
+#[test]
+fn pool_scales_with_demand() {
+    struct Retired(flume::Sender<usize>);
+
+    impl Hooks for Retired {
+        fn on_thread_retired(&self, thread: usize) {
+            let _ = self.0.send(thread);
+        }
+    }
+
+    let (sender, retired) = flume::unbounded();
+    let pool = PoolBuilder::new()
+        .capacity(3)
+        .min_threads(1)
+        .spawn_after(Duration::from_millis(10))
+        .idle_timeout(Duration::from_millis(10))
+        .hooks(Retired(sender))
+        .build();
+    futures::executor::block_on(async {
+        // a second thread is spawned once a task has waited long
+        // enough for the first
+        let (unblock, blocked) = flume::bounded::<()>(0);
+        let blocker = pool
+            .run_send((), move |()| async move { blocked.recv_async().await })
+            .await;
+        pool.run((), |()| async {}).await.await.unwrap();
+        unblock.send_async(()).await.unwrap();
+        blocker.await.unwrap().unwrap();
+
+        // the thread above the minimum is retired without any more
+        // work being submitted, and the minimum is kept
+        retired.recv_async().await.unwrap();
+        assert_eq!(
+            retired.recv_timeout(Duration::from_millis(50)),
+            Err(flume::RecvTimeoutError::Timeout)
+        );
+    });
+}
+
+#[test]
+fn crashed_tasks_are_retried_on_new_threads() {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let pool = Pool::new(1);
+    futures::executor::block_on(async {
+        let result = pool
+            .run_with_retry(&RetryPolicy::new(3), (), {
+                let attempts = attempts.clone();
+                move |()| async move {
+                    // crash the thread on all but the last attempt
+                    assert!(attempts.fetch_add(1, Ordering::Relaxed) >= 2);
+                    7u8
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 7);
+
+        attempts.store(0, Ordering::Relaxed);
+        let result = pool
+            .run_with_retry(&RetryPolicy::new(2), (), {
+                let attempts = attempts.clone();
+                move |()| async move {
+                    assert!(attempts.fetch_add(1, Ordering::Relaxed) >= 2);
+                }
+            })
+            .await;
+        assert!(matches!(result, Err(Error::Thread(_))));
+    });
+}
+
+#[test]
+fn only_crashes_are_retried_by_default() {
+    let policy = RetryPolicy::new(3);
+    assert!(policy.retries(&Error::Thread(web_thread::Error::Killed)));
+    assert!(policy.retries(&Error::Thread(web_thread::Error::Panic("oops".into()))));
+    assert!(
+        !policy.retries(&Error::Thread(web_thread::Error::Serialization(
+            "oops".into()
+        )))
+    );
+    assert!(!policy.retries(&Error::Thread(web_thread::Error::Aborted)));
+    assert!(!policy.retries(&Error::PoolTimeout));
+}
+
+#[test]
+fn watchdog_reports_long_tasks_and_saturation() {
+    use std::sync::{Arc, Mutex};
+
+    struct Reports(Arc<Mutex<Vec<String>>>);
+
+    impl Hooks for Reports {
+        fn on_long_task(&self, _lane: &str, _thread: usize, task: &str, _elapsed: Duration) {
+            self.0.lock().unwrap().push(task.to_owned());
+        }
+
+        fn on_queue_saturated(&self, waiting: usize) {
+            self.0.lock().unwrap().push(format!("saturated: {waiting}"));
         }
     }
+
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let pool = PoolBuilder::new()
+        .capacity(1)
+        .long_task_threshold(Duration::from_millis(10))
+        .queue_watermark(1)
+        .hooks(Reports(reports.clone()))
+        .build();
+    futures::executor::block_on(async {
+        let blocker = pool
+            .run((), |()| async {
+                std::thread::sleep(Duration::from_millis(20));
+            })
+            .await;
+        let queued = futures::future::join_all(
+            (0..3).map(|_| async { pool.run((), |()| async {}).await.await }),
+        );
+        let (blocked, queued) = futures::join!(blocker, queued);
+        blocked.unwrap();
+        assert!(queued.into_iter().all(|result| result.is_ok()));
+    });
+
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.len(), 2, "{reports:?}");
+    assert!(reports.contains(&"saturated: 2".to_owned()));
+    assert!(
+        reports
+            .iter()
+            .any(|report| report.contains("watchdog_reports"))
+    );
+}
+
+#[test]
+fn long_tasks_are_reported_while_running() {
+    struct Reports(flume::Sender<String>);
+
+    impl Hooks for Reports {
+        fn on_long_task(&self, _lane: &str, _thread: usize, task: &str, _elapsed: Duration) {
+            let _ = self.0.send(task.to_owned());
+        }
+    }
+
+    let (reports, reported) = flume::unbounded();
+    let pool = PoolBuilder::new()
+        .capacity(1)
+        .long_task_threshold(Duration::from_millis(10))
+        .hooks(Reports(reports))
+        .build();
+    let (release, released) = flume::bounded::<()>(1);
+    futures::executor::block_on(async {
+        let task = pool
+            .run_send((), move |()| async move {
+                let _ = released.recv_async().await;
+            })
+            .await;
+        // nothing else is submitted to the pool while the task runs
+        let report = reported.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(report.contains("long_tasks_are_reported"), "{report}");
+        release.send(()).unwrap();
+        task.await.unwrap();
+    });
+    assert!(reported.try_recv().is_err());
 }
@@ -0,0 +1,2469 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A generic pool of resources, each of which can only be used by
+//! one client at a time.
+
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    sync::{
+        Arc, Mutex, MutexGuard, OnceLock, RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::ThreadId,
+    time::Duration,
+};
+
+use futures::{
+    channel::oneshot,
+    future::{self, FutureExt as _, LocalBoxFuture, TryFutureExt as _},
+    task::{FutureObj, LocalFutureObj},
+};
+use web_time::SystemTime;
+
+use crate::{
+    CancellationToken, Error, Result,
+    hooks::{Hooks, NoHooks, TaskHooks},
+    trace,
+};
+
+pub(crate) type Id = usize;
+
+/// Work to be run by a resource, such as a thread.  The future is
+/// created on the thread that runs it, so needn't be `Send`.
+pub(crate) type Job = Box<dyn FnOnce() -> LocalFutureObj<'static, ()> + Send>;
+
+/// A job that runs a `Send` future.
+pub(crate) fn job(future: FutureObj<'static, ()>) -> Job {
+    Box::new(move || future.into())
+}
+
+/// The name of the lane used when no lane is specified.
+pub const DEFAULT_LANE: &str = "default";
+
+/// Something waiting in a lane for a resource to become available.
+enum Waiter {
+    /// A call to [`Lane::get`].
+    Task {
+        sender: oneshot::Sender<ResourceHandle>,
+        permits: usize,
+    },
+    /// A job to be run by the resource.
+    Job(Job),
+}
+
+impl Waiter {
+    fn permits(&self) -> usize {
+        match self {
+            Self::Task { permits, .. } => *permits,
+            Self::Job(_) => 1,
+        }
+    }
+}
+
+/// A resource given to a waiter by the scheduler, to be handed over
+/// once the scheduler is unlocked.
+struct Assignment {
+    id: Id,
+    lane: Arc<str>,
+    waiter: Waiter,
+}
+
+struct LaneState {
+    weight: u32,
+    // the resource time used by this lane, in seconds, divided by
+    // its weight
+    virtual_time: f64,
+    waiters: VecDeque<Waiter>,
+}
+
+impl Default for LaneState {
+    fn default() -> Self {
+        Self {
+            weight: 1,
+            virtual_time: 0.,
+            waiters: VecDeque::new(),
+        }
+    }
+}
+
+/// A job waiting in a particular resource's queue.
+struct LocalJob {
+    job: Job,
+    // pinned jobs may not be stolen by other resources
+    pinned: bool,
+}
+
+/// How a pool grows and shrinks between its minimum size and its
+/// capacity.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Scaling {
+    /// The number of resources to create up front and never retire.
+    pub(crate) min: usize,
+    /// How long a client should wait for a resource before the pool
+    /// creates a new one, or `None` to create them as soon as they're
+    /// needed.
+    pub(crate) spawn_after: Option<Duration>,
+    /// How long a resource can be idle before it is retired, or
+    /// `None` to keep resources forever.
+    pub(crate) idle_timeout: Option<Duration>,
+}
+
+/// Thresholds past which the pool warns that it is being misused.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Watchdog {
+    /// How long a client can hold a resource before it is reported,
+    /// or `None` to never report it.
+    pub(crate) long_task: Option<Duration>,
+    /// How many clients can be queued before the pool is reported as
+    /// saturated, or `None` to never report it.
+    pub(crate) queue_watermark: Option<usize>,
+}
+
+/// The order in which a [`Pool`] reuses idle resources.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Reuse {
+    /// Reuse the resource that has been idle for longest, spreading
+    /// work evenly over the pool's resources.
+    #[default]
+    Fifo,
+    /// Reuse the resource that was most recently returned, keeping
+    /// frequently used resources warm and leaving the rest idle long
+    /// enough to be retired.
+    Lifo,
+}
+
+/// A snapshot of how much a [`Pool`] has been used, from
+/// [`Pool::stats`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Stats {
+    /// The number of times a resource was lent to a client.
+    pub acquisitions: u64,
+    /// The total time clients waited for a resource, including any
+    /// time spent creating it.
+    pub total_wait: Duration,
+    /// The longest time a client waited for a resource.
+    pub max_wait: Duration,
+    /// The number of times a resource was returned to the pool,
+    /// including after running jobs.
+    pub releases: u64,
+    /// The total time resources were held before being returned.
+    pub total_held: Duration,
+    /// The longest time a resource was held before being returned.
+    pub max_held: Duration,
+}
+
+/// When resources are dropped and replaced, regardless of their health.
+#[derive(Clone, Copy, Debug, Default)]
+struct Recycling {
+    max_uses: Option<usize>,
+    max_lifetime: Option<Duration>,
+}
+
+/// A claimed resource being watched for long tasks.
+struct Running {
+    lane: Arc<str>,
+    name: &'static str,
+    since: SystemTime,
+    // whether the task has already been reported as long-running
+    reported: bool,
+}
+
+#[derive(Default)]
+struct Scheduler {
+    idle: VecDeque<Id>,
+    // when each resource last became idle, indexed by `Id`
+    idle_since: Vec<SystemTime>,
+    // when each resource was created, and how many times it has been
+    // lent out since, indexed by `Id`
+    created: Vec<SystemTime>,
+    uses: Vec<usize>,
+    recycling: Recycling,
+    // the number of live resources
+    len: usize,
+    // the indices of retired resources, to be reused by new ones
+    free: BTreeSet<Id>,
+    // the number of resources the pool may hold, including any
+    // adopted with `Pool::adopt`
+    capacity: usize,
+    // the permits not held by any client; see `Lane::with_permits`
+    permits: usize,
+    // each resource's own queue of jobs, indexed by `Id`
+    local: Vec<VecDeque<LocalJob>>,
+    // the virtual time of the last lane to be given a resource, used
+    // to stop lanes banking time while they have nothing to do
+    virtual_time: f64,
+    lanes: HashMap<Arc<str>, LaneState>,
+    reuse: Reuse,
+    // whether new clients may take free resources ahead of clients
+    // already waiting; see `Pool::set_fair`
+    barging: bool,
+    stats: Stats,
+    // set by `Pool::close`
+    closed: bool,
+    // cancelled and replaced by `Pool::abort_all`
+    token: CancellationToken,
+}
+
+impl Scheduler {
+    fn lane(&mut self, name: &Arc<str>) -> &mut LaneState {
+        self.lanes.entry(name.clone()).or_default()
+    }
+
+    /// Whether the resource `id` is due to be replaced because of its
+    /// age or the number of times it has been lent out.
+    fn is_worn_out(&self, id: Id) -> bool {
+        self.recycling
+            .max_uses
+            .is_some_and(|max_uses| self.uses[id] >= max_uses)
+            || self.recycling.max_lifetime.is_some_and(|max_lifetime| {
+                self.created[id].elapsed().unwrap_or_default() >= max_lifetime
+            })
+    }
+
+    /// Reset the age and use count of the newly created resource `id`.
+    fn renew(&mut self, id: Id) {
+        self.created[id] = SystemTime::now();
+        self.uses[id] = 0;
+    }
+
+    /// Queue `waiter` in the lane called `name`, returning the number
+    /// of waiters in all lanes.
+    fn enqueue(&mut self, name: &Arc<str>, waiter: Waiter) -> usize {
+        let virtual_time = self.virtual_time;
+        let lane = self.lane(name);
+        if lane.waiters.is_empty() {
+            lane.virtual_time = lane.virtual_time.max(virtual_time);
+        }
+        lane.waiters.push_back(waiter);
+        trace::saturated(name, lane.waiters.len());
+        self.waiters()
+    }
+
+    fn is_live(&self, id: Id) -> bool {
+        id < self.local.len() && !self.free.contains(&id)
+    }
+
+    fn has_waiters(&self) -> bool {
+        self.lanes.values().any(|lane| !lane.waiters.is_empty())
+    }
+
+    fn waiters(&self) -> usize {
+        self.lanes.values().map(|lane| lane.waiters.len()).sum()
+    }
+
+    /// Take the next waiter from the lane that has used the least
+    /// resource time relative to its weight, if there are enough
+    /// permits free for it.
+    ///
+    /// A waiter that needs more permits than are free blocks its lane
+    /// rather than being overtaken, so that heavy clients aren't
+    /// starved by light ones.
+    fn next_waiter(&mut self) -> Option<(Arc<str>, Waiter)> {
+        let (name, lane) = self
+            .lanes
+            .iter_mut()
+            .filter(|(_, lane)| !lane.waiters.is_empty())
+            .min_by(|(_, a), (_, b)| a.virtual_time.total_cmp(&b.virtual_time))?;
+        let permits = lane.waiters.front()?.permits();
+        if permits > self.permits {
+            return None;
+        }
+        self.permits -= permits;
+        self.virtual_time = lane.virtual_time;
+        Some((name.clone(), lane.waiters.pop_front()?))
+    }
+
+    /// Steal the most recently queued unpinned job from the resource
+    /// with the most unpinned jobs queued.
+    fn steal(&mut self) -> Option<Job> {
+        let queue = self
+            .local
+            .iter_mut()
+            .max_by_key(|queue| queue.iter().filter(|job| !job.pinned).count())?;
+        let index = queue.iter().rposition(|job| !job.pinned)?;
+        queue.remove(index).map(|job| job.job)
+    }
+
+    /// Find work for the resource `id`, taking the permits it needs.
+    ///
+    /// A resource's own queue takes priority, followed by the lanes.
+    /// Only if there is nothing else to do does the resource steal
+    /// work from other resources' queues.
+    fn assign(&mut self, id: Id) -> Option<Assignment> {
+        let (lane, waiter) = if self.permits > 0
+            && let Some(LocalJob { job, .. }) = self.local[id].pop_front()
+        {
+            self.permits -= 1;
+            (DEFAULT_LANE.into(), Waiter::Job(job))
+        } else if let Some(next) = self.next_waiter() {
+            next
+        } else if self.permits > 0
+            && let Some(job) = self.steal()
+        {
+            self.permits -= 1;
+            (DEFAULT_LANE.into(), Waiter::Job(job))
+        } else {
+            return None;
+        };
+        Some(Assignment { id, lane, waiter })
+    }
+
+    /// Find work for as many idle resources as possible.
+    fn assign_idle(&mut self) -> Vec<Assignment> {
+        let mut assignments = Vec::new();
+        while let Some(id) = self.next_idle() {
+            let Some(assignment) = self.assign(id) else {
+                break;
+            };
+            self.take_idle();
+            assignments.push(assignment);
+        }
+        assignments
+    }
+
+    /// The idle resource to reuse next.
+    fn next_idle(&self) -> Option<Id> {
+        match self.reuse {
+            Reuse::Fifo => self.idle.front().copied(),
+            Reuse::Lifo => self.idle.back().copied(),
+        }
+    }
+
+    fn take_idle(&mut self) -> Option<Id> {
+        match self.reuse {
+            Reuse::Fifo => self.idle.pop_front(),
+            Reuse::Lifo => self.idle.pop_back(),
+        }
+    }
+}
+
+/// A resource given back to the pool, with how it was used.
+struct Release {
+    id: Id,
+    lane: Arc<str>,
+    permits: usize,
+    held: Duration,
+    // set if the factory failed to create the resource
+    uncreated: bool,
+}
+
+/// The thread that owns a pool, to which resources released on other
+/// threads are sent to be given back.
+struct Owner {
+    thread: ThreadId,
+    releases: flume::Sender<Release>,
+}
+
+/// The part of the pool that can be shared with (and released from)
+/// other threads.
+struct Shared {
+    scheduler: Mutex<Scheduler>,
+    // set by `Pool::release_on_this_thread`
+    owner: OnceLock<Owner>,
+    // the sending half of each resource's job queue, indexed by `Id`,
+    // if the pool's resources can run jobs
+    queues: RwLock<Vec<Option<flume::Sender<Job>>>>,
+    hooks: RwLock<Arc<dyn Hooks>>,
+    watchdog: RwLock<Watchdog>,
+    // the resources claimed while the watchdog is looking for long
+    // tasks
+    running: Mutex<HashMap<Id, Running>>,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Scheduler> {
+        self.scheduler.lock().unwrap()
+    }
+
+    fn hooks(&self) -> Arc<dyn Hooks> {
+        self.hooks.read().unwrap().clone()
+    }
+
+    fn claim(self: &Arc<Self>, id: Id, lane: Arc<str>, permits: usize) -> ResourceHandle {
+        let started = SystemTime::now();
+        if self.watchdog.read().unwrap().long_task.is_some() {
+            self.running.lock().unwrap().insert(
+                id,
+                Running {
+                    lane: lane.clone(),
+                    name: "unnamed",
+                    since: started,
+                    reported: false,
+                },
+            );
+        }
+        ResourceHandle {
+            id,
+            lane,
+            permits,
+            started,
+            uncreated: false,
+            shared: self.clone(),
+        }
+    }
+
+    /// Note that a client queued in `lane`, leaving `waiting` clients
+    /// queued in total.
+    fn queued(&self, lane: &str, waiting: usize) {
+        let hooks = self.hooks();
+        hooks.on_task_queued(lane);
+        // report only when the watermark is first crossed, rather than
+        // for every client queued above it
+        if self.watchdog.read().unwrap().queue_watermark == Some(waiting - 1) {
+            trace::queue_watermark(waiting);
+            hooks.on_queue_saturated(waiting);
+        }
+    }
+
+    /// Report every client that has held its resource for longer than
+    /// the watchdog allows and hasn't yet been reported, returning how
+    /// long until the next is due to be, or `None` if the watchdog
+    /// doesn't look for long tasks.
+    fn report_long_tasks(&self) -> Option<Duration> {
+        let threshold = self.watchdog.read().unwrap().long_task?;
+        let mut long = Vec::new();
+        // a client that claims a resource later is due no sooner than
+        // `threshold` from now
+        let mut next = threshold;
+        for (&id, running) in &mut *self.running.lock().unwrap() {
+            if running.reported {
+                continue;
+            }
+            let elapsed = running.since.elapsed().unwrap_or_default();
+            if elapsed >= threshold {
+                running.reported = true;
+                long.push((id, running.lane.clone(), running.name, elapsed));
+            } else {
+                next = next.min(threshold - elapsed);
+            }
+        }
+
+        if !long.is_empty() {
+            let hooks = self.hooks();
+            for (id, lane, name, elapsed) in long {
+                trace::long_task(&lane, id, name, elapsed);
+                hooks.on_long_task(&lane, id, name, elapsed);
+            }
+        }
+        Some(next)
+    }
+
+    /// Stop watching the resource `id` for long tasks, reporting its
+    /// client if it held the resource for too long.
+    fn finished(&self, id: Id) {
+        let Some(threshold) = self.watchdog.read().unwrap().long_task else {
+            return;
+        };
+        let Some(running) = self.running.lock().unwrap().remove(&id) else {
+            return;
+        };
+        let elapsed = running.since.elapsed().unwrap_or_default();
+        if !running.reported && elapsed >= threshold {
+            trace::long_task(&running.lane, id, running.name, elapsed);
+            self.hooks()
+                .on_long_task(&running.lane, id, running.name, elapsed);
+        }
+    }
+
+    /// Account for a released resource and give it back to the pool.
+    fn give_back(self: &Arc<Self>, release: Release) {
+        let Release {
+            id,
+            lane,
+            permits,
+            held,
+            uncreated,
+        } = release;
+        {
+            let mut scheduler = self.lock();
+            scheduler.permits += permits;
+            let stats = &mut scheduler.stats;
+            stats.releases += 1;
+            stats.total_held += held;
+            stats.max_held = stats.max_held.max(held);
+            let lane = scheduler.lane(&lane);
+            lane.virtual_time += held.as_secs_f64() / f64::from(lane.weight);
+        }
+        self.hooks().on_resource_released(&lane, id, held);
+        self.finished(id);
+        if uncreated {
+            self.release_uncreated(id);
+        } else {
+            self.release(id);
+        }
+    }
+
+    /// Give a resource back to the pool, handing it to the next waiter
+    /// if there is one.  The permits returned with it may also allow
+    /// idle resources to be handed to waiters.
+    fn release(self: &Arc<Self>, id: Id) {
+        let mut scheduler = self.lock();
+        let assigned = scheduler.assign(id);
+        if assigned.is_none() {
+            scheduler.idle.push_back(id);
+            scheduler.idle_since[id] = SystemTime::now();
+        }
+        let assignments = scheduler.assign_idle();
+        drop(scheduler);
+        self.start(assigned.into_iter().chain(assignments));
+    }
+
+    /// Give back a resource the factory failed to create, handing it
+    /// to the next waiter to try again, or else freeing its slot for
+    /// a new resource.
+    fn release_uncreated(self: &Arc<Self>, id: Id) {
+        let mut scheduler = self.lock();
+        if scheduler.has_waiters() {
+            drop(scheduler);
+            self.release(id);
+        } else {
+            scheduler.len -= 1;
+            scheduler.free.insert(id);
+        }
+    }
+
+    /// Hand resources over to the waiters they were assigned to.  The
+    /// scheduler must not be locked.
+    fn start(self: &Arc<Self>, assignments: impl IntoIterator<Item = Assignment>) {
+        for Assignment { id, lane, waiter } in assignments {
+            let handle = self.claim(id, lane, waiter.permits());
+            match waiter {
+                // if the waiter has gone away we get the handle back,
+                // and dropping it releases the resource to the next
+                // waiter
+                Waiter::Task { sender, .. } => drop(sender.send(handle)),
+                Waiter::Job(job) => self.dispatch(handle, job),
+            }
+        }
+    }
+
+    /// Run a job on a claimed resource, releasing the resource once
+    /// the job is complete or aborted.
+    fn dispatch(&self, handle: ResourceHandle, job: Job) {
+        let cancelled = self.lock().token.cancelled();
+        let Some(queue) = self.queues.read().unwrap()[handle.id].clone() else {
+            return;
+        };
+        let hooks = TaskHooks {
+            hooks: self.hooks(),
+            lane: handle.lane.clone(),
+            thread: handle.id,
+        };
+        let _ = queue.send(Box::new(move || {
+            LocalFutureObj::new(Box::new(async move {
+                hooks.started();
+                if let future::Either::Left(((), _)) =
+                    future::select(job(), Box::pin(cancelled)).await
+                {
+                    hooks.finished();
+                } else {
+                    hooks.failed(&Error::Aborted);
+                }
+                drop(handle);
+            }))
+        }));
+    }
+}
+
+/// A claimed resource, which is released when this is dropped.
+pub(crate) struct ResourceHandle {
+    id: Id,
+    lane: Arc<str>,
+    permits: usize,
+    started: SystemTime,
+    // set if the factory failed to create the resource
+    uncreated: bool,
+    shared: Arc<Shared>,
+}
+
+impl Drop for ResourceHandle {
+    fn drop(&mut self) {
+        let release = Release {
+            id: self.id,
+            lane: self.lane.clone(),
+            permits: self.permits,
+            held: self.started.elapsed().unwrap_or_default(),
+            uncreated: self.uncreated,
+        };
+        let release = match self.shared.owner.get() {
+            Some(owner) if owner.thread != std::thread::current().id() => {
+                match owner.releases.send(release) {
+                    Ok(()) => return,
+                    // the owner has stopped taking releases, so is no
+                    // longer using the pool
+                    Err(flume::SendError(release)) => release,
+                }
+            }
+            _ => release,
+        };
+        self.shared.give_back(release);
+    }
+}
+
+/// A claim on a resource that can be shared between threads.  The
+/// resource is released when any holder releases the claim, or when
+/// all holders have been dropped.
+#[derive(Clone)]
+pub(crate) struct Claim(Arc<ClaimState>);
+
+struct ClaimState {
+    // set by the first holder to release the claim, which is then the
+    // only one to lock the handle, so that releasing never waits
+    released: AtomicBool,
+    handle: Mutex<Option<ResourceHandle>>,
+}
+
+impl Claim {
+    fn new(handle: ResourceHandle) -> Self {
+        Self(Arc::new(ClaimState {
+            released: AtomicBool::new(false),
+            handle: Mutex::new(Some(handle)),
+        }))
+    }
+
+    pub(crate) fn release(&self) {
+        if !self.0.released.swap(true, Ordering::AcqRel) {
+            let handle = self.0.handle.lock().unwrap().take();
+            drop(handle);
+        }
+    }
+
+    /// Replace the claimed resource with a new one, if it hasn't yet
+    /// been released.
+    pub(crate) fn replace_resource<T>(&self, pool: &Pool<T>) {
+        // if the handle is locked, the claim is being released
+        if let Ok(handle) = self.0.handle.try_lock()
+            && let Some(handle) = &*handle
+        {
+            pool.replace(handle.id);
+        }
+    }
+}
+
+/// How a pool creates new resources.
+enum Factory<T> {
+    Sync(Box<dyn FnMut() -> T + Send>),
+    /// Resources created by an async or fallible factory are created
+    /// by the client that first claims them, rather than by the
+    /// scheduler.
+    Async(Box<dyn FnMut() -> LocalBoxFuture<'static, Result<T>> + Send>),
+}
+
+/// A resource removed from the pool, with its job queue.
+type Retired<T> = (Id, Option<Arc<T>>, Option<flume::Sender<Job>>);
+
+/// The pool's resources, indexed by `Id`, with `None` for retired
+/// resources, for resources an async factory has yet to create, and
+/// for resources that have been lent out.
+type Slots<T> = Arc<RwLock<Vec<Option<Arc<T>>>>>;
+
+/// A check run on a resource before it is lent out.
+type HealthCheck<T> = Arc<dyn Fn(Arc<T>) -> LocalBoxFuture<'static, bool> + Send + Sync>;
+
+/// A pool of shared resources, each of which can only be used once at a time.
+///
+/// Resources are created on demand by a factory, up to the pool's
+/// capacity.
+///
+/// Clients of the pool are divided into named [`Lane`]s.  When a
+/// resource becomes available, it is given to the lane that has used
+/// the least resource time relative to its weight, so that one
+/// subsystem making heavy use of the pool can't starve another.
+///
+/// # Fairness
+///
+/// Clients in a lane are served in the order they arrived, and a
+/// returned resource is handed straight to the next client rather
+/// than being put up for grabs, so a client that has been waiting is
+/// never overtaken by one that arrives later.  This can leave
+/// resources idle while the next client waits for permits (see
+/// [`Lane::with_permits`]); [`Pool::set_fair`] trades the guarantee
+/// away for throughput.
+pub struct Pool<T> {
+    resources: Slots<T>,
+    factory: Mutex<Factory<T>>,
+    health_check: RwLock<Option<HealthCheck<T>>>,
+    shared: Arc<Shared>,
+    scaling: Scaling,
+    // starts a new resource running the jobs sent to it, for pools
+    // whose resources can run jobs
+    pub(crate) executor: Option<fn(&T, flume::Receiver<Job>)>,
+    pub(crate) blocking_capacity: usize,
+    pub(crate) blocking: OnceLock<Box<Pool<T>>>,
+}
+
+/// A resource that has been claimed from a [`Pool`].  The resource is
+/// released back to the pool when the guard is dropped.
+pub struct Guard<'a, T> {
+    // must be dropped before the handle, so that the resource is back
+    // in its slot by the time it's released
+    resource: Lent<T>,
+    handle: ResourceHandle,
+    pool: &'a Pool<T>,
+}
+
+impl<T> std::ops::Deref for Guard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.resource.get()
+    }
+}
+
+impl<T> std::ops::DerefMut for Guard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.resource.get_mut()
+    }
+}
+
+impl<T> Guard<'_, T> {
+    /// Keep the resource out of the pool until the returned guard is
+    /// dropped, independently of the lifetime of the pool.
+    pub fn into_owned(self) -> OwnedGuard<T> {
+        OwnedGuard {
+            resource: self.resource,
+            handle: self.handle,
+        }
+    }
+
+    /// Project the guard onto part of the resource, such as one of
+    /// its fields.  The resource stays out of the pool until the
+    /// returned guard is dropped.
+    pub fn map<'a, U: ?Sized>(self, f: impl Fn(&T) -> &U + 'a) -> MappedGuard<'a, U>
+    where
+        T: 'a,
+    {
+        MappedGuard {
+            value: Box::new(Projection {
+                source: self.resource,
+                f,
+            }),
+            _handle: self.handle,
+        }
+    }
+
+    /// Remove the resource from the pool, leaving its place to be
+    /// filled by a new resource from the factory when one is next
+    /// needed.
+    pub fn take(self) -> T {
+        self.pool.discard(self.handle.id);
+        Arc::into_inner(self.resource.take()).expect("resources still shared aren't lent")
+    }
+
+    pub(crate) fn id(&self) -> Id {
+        self.handle.id
+    }
+
+    /// Name the client using this resource in reports of long tasks.
+    pub(crate) fn set_task_name(&self, name: &'static str) {
+        if let Some(running) = self
+            .handle
+            .shared
+            .running
+            .lock()
+            .unwrap()
+            .get_mut(&self.handle.id)
+        {
+            running.name = name;
+        }
+    }
+
+    /// The hooks to call for a task running on this resource.
+    pub(crate) fn task_hooks(&self) -> TaskHooks {
+        TaskHooks {
+            hooks: self.handle.shared.hooks(),
+            lane: self.handle.lane.clone(),
+            thread: self.handle.id,
+        }
+    }
+
+    /// Convert the guard into a [`Claim`] that can be released from
+    /// other threads.
+    pub(crate) fn into_claim(self) -> (Arc<T>, Claim) {
+        (self.resource.share(), Claim::new(self.handle))
+    }
+}
+
+/// A resource taken out of its slot while it is lent out, so that its
+/// borrower has it to itself.  The resource is put back when this is
+/// dropped.
+///
+/// Health checks and [`Pool::for_each_idle_async`] are given the
+/// resource's `Arc`, and may keep a clone of it; a resource shared
+/// like that fails [`Pool::is_healthy`] and is replaced rather than
+/// lent, so a lent resource is never shared.
+struct Lent<T> {
+    // `None` once the resource has been taken or shared
+    resource: Option<Arc<T>>,
+    slots: Slots<T>,
+    id: Id,
+}
+
+impl<T> Lent<T> {
+    fn get(&self) -> &T {
+        self.resource.as_ref().expect("resource is still lent")
+    }
+
+    fn get_mut(&mut self) -> &mut T {
+        Arc::get_mut(self.resource.as_mut().expect("resource is still lent"))
+            .expect("resources still shared aren't lent")
+    }
+
+    /// Take the resource for good, leaving its slot empty.
+    fn take(mut self) -> Arc<T> {
+        self.resource.take().expect("resource is still lent")
+    }
+
+    /// Put the resource back in its slot now, so that it can be
+    /// shared with the pool while it is still claimed.
+    fn share(mut self) -> Arc<T> {
+        let resource = self.resource.take().expect("resource is still lent");
+        self.slots.write().unwrap()[self.id] = Some(resource.clone());
+        resource
+    }
+}
+
+impl<T> Drop for Lent<T> {
+    fn drop(&mut self) {
+        if let Some(resource) = self.resource.take() {
+            let mut slots = self.slots.write().unwrap();
+            // the slot may have been refilled if the resource was
+            // replaced while it was lent
+            if slots[self.id].is_none() {
+                slots[self.id] = Some(resource);
+            }
+        }
+    }
+}
+
+/// A resource that has been checked out of a [`Pool`] with
+/// [`Pool::checkout`].  The resource is released back to the pool when
+/// the guard is dropped.
+pub struct OwnedGuard<T> {
+    resource: Lent<T>,
+    handle: ResourceHandle,
+}
+
+impl<T> std::ops::Deref for OwnedGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.resource.get()
+    }
+}
+
+impl<T> std::ops::DerefMut for OwnedGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.resource.get_mut()
+    }
+}
+
+impl<T: 'static> OwnedGuard<T> {
+    /// Like [`Guard::map`], but for an owned guard.
+    pub fn map<U: ?Sized>(self, f: impl Fn(&T) -> &U + 'static) -> MappedGuard<'static, U> {
+        MappedGuard {
+            value: Box::new(Projection {
+                source: self.resource,
+                f,
+            }),
+            _handle: self.handle,
+        }
+    }
+}
+
+/// Something that can be borrowed as part of a resource.
+trait Project {
+    type Target: ?Sized;
+
+    fn project(&self) -> &Self::Target;
+}
+
+/// Part of a resource, found by applying `f` to `source`.
+struct Projection<S, F> {
+    source: S,
+    f: F,
+}
+
+impl<T, U: ?Sized, F: Fn(&T) -> &U> Project for Projection<Lent<T>, F> {
+    type Target = U;
+
+    fn project(&self) -> &U {
+        (self.f)(self.source.get())
+    }
+}
+
+impl<'a, U: ?Sized, V: ?Sized, F: Fn(&U) -> &V> Project
+    for Projection<Box<dyn Project<Target = U> + 'a>, F>
+{
+    type Target = V;
+
+    fn project(&self) -> &V {
+        (self.f)(self.source.project())
+    }
+}
+
+/// Part of a resource claimed from a [`Pool`], created with
+/// [`Guard::map`] or [`OwnedGuard::map`].  The resource is released
+/// back to the pool when the guard is dropped.
+pub struct MappedGuard<'a, U: ?Sized> {
+    value: Box<dyn Project<Target = U> + 'a>,
+    _handle: ResourceHandle,
+}
+
+impl<U: ?Sized> std::ops::Deref for MappedGuard<'_, U> {
+    type Target = U;
+
+    fn deref(&self) -> &U {
+        self.value.project()
+    }
+}
+
+impl<'a, U: ?Sized + 'a> MappedGuard<'a, U> {
+    /// Project the guard further onto part of `U`.
+    pub fn map<V: ?Sized>(self, f: impl Fn(&U) -> &V + 'a) -> MappedGuard<'a, V> {
+        MappedGuard {
+            value: Box::new(Projection {
+                source: self.value,
+                f,
+            }),
+            _handle: self._handle,
+        }
+    }
+}
+
+/// A named queue of clients on a [`Pool`], created with [`Pool::lane`].
+pub struct Lane<'a, T> {
+    pool: &'a Pool<T>,
+    name: Arc<str>,
+    permits: usize,
+}
+
+impl<T> Drop for Pool<T> {
+    fn drop(&mut self) {
+        let live = {
+            let scheduler = self.shared.lock();
+            (0..scheduler.local.len())
+                .filter(|&id| scheduler.is_live(id))
+                .collect::<Vec<_>>()
+        };
+        let hooks = self.shared.hooks();
+        for id in live {
+            trace::retired(id);
+            hooks.on_thread_retired(id);
+        }
+    }
+}
+
+impl<T> Pool<T> {
+    /// Create a new pool of `capacity` items, using `factory` to
+    /// generate new items.
+    pub fn with_factory(capacity: usize, factory: impl FnMut() -> T + Send + 'static) -> Self {
+        Self::with_factory_impl(capacity, Factory::Sync(Box::new(factory)))
+    }
+
+    /// Create a new pool of `capacity` items, using `factory` to
+    /// generate new items asynchronously, such as by opening a
+    /// database or handshaking over a port.
+    ///
+    /// A new item is created by the client that claims it, so
+    /// [`Pool::get`] waits for the item to be created, while
+    /// [`Pool::try_get`] only returns items that already exist.  If the
+    /// client stops waiting before the item is created, the next
+    /// client to claim it creates it instead.
+    pub fn with_async_factory<F: Future<Output = T> + 'static>(
+        capacity: usize,
+        mut factory: impl FnMut() -> F + Send + 'static,
+    ) -> Self
+    where
+        T: 'static,
+    {
+        Self::with_factory_impl(
+            capacity,
+            Factory::Async(Box::new(move || Box::pin(factory().map(Ok)))),
+        )
+    }
+
+    /// Create a new pool of `capacity` items, using `factory` to
+    /// generate new items, which may fail.
+    ///
+    /// As with [`Pool::with_async_factory`], a new item is created by
+    /// the client that claims it, and [`Pool::try_get`] only returns
+    /// items that already exist.  If creating an item fails, the
+    /// client gets [`Error::Factory`], and the item's place in the
+    /// pool is given to the next client to try again.
+    pub fn with_try_factory<E: Into<Box<dyn std::error::Error + Send + Sync>>>(
+        capacity: usize,
+        mut factory: impl FnMut() -> Result<T, E> + Send + 'static,
+    ) -> Self
+    where
+        T: 'static,
+    {
+        Self::with_factory_impl(
+            capacity,
+            Factory::Async(Box::new(move || {
+                Box::pin(future::ready(
+                    factory().map_err(|error| Error::Factory(error.into())),
+                ))
+            })),
+        )
+    }
+
+    /// Like [`Pool::with_try_factory`], but creating items
+    /// asynchronously.
+    pub fn with_try_async_factory<
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+        F: Future<Output = Result<T, E>> + 'static,
+    >(
+        capacity: usize,
+        mut factory: impl FnMut() -> F + Send + 'static,
+    ) -> Self
+    where
+        T: 'static,
+    {
+        Self::with_factory_impl(
+            capacity,
+            Factory::Async(Box::new(move || {
+                Box::pin(factory().map_err(|error| Error::Factory(error.into())))
+            })),
+        )
+    }
+
+    fn with_factory_impl(capacity: usize, factory: Factory<T>) -> Self {
+        Self {
+            resources: Arc::new(RwLock::new(Vec::with_capacity(capacity))),
+            factory: Mutex::new(factory),
+            health_check: RwLock::new(None),
+            shared: Arc::new(Shared {
+                scheduler: Mutex::new(Scheduler {
+                    capacity,
+                    permits: capacity,
+                    ..Scheduler::default()
+                }),
+                owner: OnceLock::new(),
+                queues: RwLock::new(Vec::with_capacity(capacity)),
+                hooks: RwLock::new(Arc::new(NoHooks)),
+                watchdog: RwLock::default(),
+                running: Mutex::default(),
+            }),
+            scaling: Scaling::default(),
+            executor: None,
+            blocking_capacity: 0,
+            blocking: OnceLock::new(),
+        }
+    }
+
+    /// Configure how the pool grows and shrinks, creating the
+    /// minimum number of resources immediately.
+    pub(crate) fn set_scaling(&mut self, scaling: Scaling) {
+        self.scaling = scaling;
+        let mut new = Vec::new();
+        {
+            let mut scheduler = self.shared.lock();
+            while scheduler.len < scaling.min.min(scheduler.capacity) {
+                new.push(self.reserve(&mut scheduler));
+            }
+        }
+        self.start_new(new);
+    }
+
+    /// Give resources released on other threads back to the pool on
+    /// this thread instead, so that they never contend with this
+    /// thread for the scheduler: the main thread of a Web page traps
+    /// if it has to wait for a lock.  The returned future gives them
+    /// back, and must be run on this thread for as long as the pool
+    /// is in use.
+    // natively, threads may wait for locks, so this is only for tests
+    #[cfg_attr(not(target_family = "wasm"), allow(dead_code))]
+    pub(crate) fn release_on_this_thread(&self) -> impl Future<Output = ()> + 'static {
+        let (sender, receiver) = flume::unbounded();
+        let _ = self.shared.owner.set(Owner {
+            thread: std::thread::current().id(),
+            releases: sender,
+        });
+        let shared = Arc::downgrade(&self.shared);
+        async move {
+            while let Ok(release) = receiver.recv_async().await {
+                let Some(shared) = shared.upgrade() else {
+                    break;
+                };
+                shared.give_back(release);
+            }
+        }
+    }
+
+    pub(crate) fn set_watchdog(&self, watchdog: Watchdog) {
+        *self.shared.watchdog.write().unwrap() = watchdog;
+    }
+
+    /// Get a handle to the lane called `name`.  Lanes are created on
+    /// first use with a weight of 1.
+    pub fn lane(&self, name: &str) -> Lane<'_, T> {
+        Lane {
+            pool: self,
+            name: name.into(),
+            permits: 1,
+        }
+    }
+
+    /// Wait for a resource to become available in the default lane,
+    /// creating a new one if there are none available and the pool
+    /// has capacity to spare.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Aborted`] if the wait was aborted by
+    /// [`Pool::abort_all`], [`Error::Closed`] if the pool has been
+    /// closed with [`Pool::close`], or [`Error::Factory`] if a fallible
+    /// factory failed to create a new resource.
+    pub async fn get(&self) -> Result<Guard<'_, T>> {
+        self.lane(DEFAULT_LANE).get().await
+    }
+
+    /// Like [`Pool::get`], but giving up if no resource becomes
+    /// available within `timeout`, including any time spent creating
+    /// or health-checking it.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::PoolTimeout`] if no resource became available within
+    /// `timeout`, or as for [`Pool::get`].
+    pub async fn get_timeout(&self, timeout: Duration) -> Result<Guard<'_, T>> {
+        self.lane(DEFAULT_LANE).get_timeout(timeout).await
+    }
+
+    /// Get a resource from the default lane without waiting, creating
+    /// a new one if there are none free and the pool has capacity to
+    /// spare.  Returns `None` if every resource is in use, or if other
+    /// clients are already queued.
+    pub fn try_get(&self) -> Option<Guard<'_, T>> {
+        self.lane(DEFAULT_LANE).try_get()
+    }
+
+    /// The number of resources in the pool, whether idle or in use.
+    pub fn len(&self) -> usize {
+        self.shared.lock().len
+    }
+
+    /// Whether the pool has no resources.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of idle resources, available without waiting.
+    pub fn available(&self) -> usize {
+        self.shared.lock().idle.len()
+    }
+
+    /// The maximum number of resources the pool may hold.
+    pub fn capacity(&self) -> usize {
+        self.shared.lock().capacity
+    }
+
+    /// A snapshot of the pool's usage so far.  To be notified of each
+    /// acquisition and release as it happens, see
+    /// [`Hooks::on_resource_acquired`] and
+    /// [`Hooks::on_resource_released`].
+    pub fn stats(&self) -> Stats {
+        self.shared.lock().stats.clone()
+    }
+
+    /// The number of clients in all lanes waiting for a resource.
+    pub fn waiters(&self) -> usize {
+        self.shared.lock().waiters()
+    }
+
+    /// Add a resource created elsewhere to the pool's rotation, such
+    /// as a thread that needed special configuration.  The resource
+    /// is in addition to the pool's capacity, and brings a permit
+    /// with it.
+    pub fn adopt(&self, resource: T) {
+        let id = {
+            let mut scheduler = self.shared.lock();
+            scheduler.capacity += 1;
+            scheduler.permits += 1;
+            self.reserve(&mut scheduler)
+        };
+        self.install(id, resource);
+        self.shared.release(id);
+    }
+
+    /// Take a resource out of rotation for an extended session.  The
+    /// pool won't hand the resource to anyone else until the returned
+    /// guard is dropped, so for a pool of threads the guard's thread
+    /// can be used repeatedly without interleaving with other tasks.
+    ///
+    /// # Errors
+    ///
+    /// As for [`Pool::get`].
+    pub async fn checkout(&self) -> Result<OwnedGuard<T>> {
+        self.get().await.map(Guard::into_owned)
+    }
+
+    /// Check each resource with `check` before lending it out.  A
+    /// resource that fails the check, such as a closed port or a
+    /// thread whose worker has died, is dropped and replaced with a
+    /// new one from the factory.
+    pub fn set_health_check(&self, check: impl Fn(&T) -> bool + Send + Sync + 'static)
+    where
+        T: 'static,
+    {
+        *self.health_check.write().unwrap() = Some(Arc::new(move |resource| {
+            Box::pin(future::ready(check(&resource)))
+        }));
+    }
+
+    /// Like [`Pool::set_health_check`], but checking resources
+    /// asynchronously.  [`Pool::try_get`] skips resources whose check
+    /// doesn't complete immediately.  Guards have their resources to
+    /// themselves, so a resource whose `Arc` is kept by the check is
+    /// replaced rather than lent.
+    pub fn set_async_health_check<F: Future<Output = bool> + 'static>(
+        &self,
+        check: impl Fn(Arc<T>) -> F + Send + Sync + 'static,
+    ) {
+        *self.health_check.write().unwrap() =
+            Some(Arc::new(move |resource| Box::pin(check(resource))));
+    }
+
+    /// Choose whether clients are served in the order they arrive,
+    /// as described under [Fairness](Pool#fairness).  This is the
+    /// default.  If not, new clients may take free resources while
+    /// earlier clients are waiting for more permits than are free,
+    /// which keeps resources busy but can starve clients that need
+    /// many permits.
+    pub fn set_fair(&self, fair: bool) {
+        self.shared.lock().barging = !fair;
+    }
+
+    /// Set the order in which idle resources are reused.  Defaults to
+    /// [`Reuse::Fifo`].
+    pub fn set_reuse(&self, reuse: Reuse) {
+        self.shared.lock().reuse = reuse;
+    }
+
+    /// Replace each resource after it has been lent out `max_uses`
+    /// times, for resources that degrade with use.
+    pub fn set_max_uses(&self, max_uses: usize) {
+        self.shared.lock().recycling.max_uses = Some(max_uses);
+    }
+
+    /// Replace each resource once it is `max_lifetime` old, for
+    /// resources that degrade over time.
+    pub fn set_max_lifetime(&self, max_lifetime: Duration) {
+        self.shared.lock().recycling.max_lifetime = Some(max_lifetime);
+    }
+
+    /// Register `hooks` to be called on events in the pool's life,
+    /// replacing any previously registered.
+    pub fn set_hooks(&self, hooks: impl Hooks + 'static) {
+        self.set_hooks_arc(Arc::new(hooks));
+    }
+
+    pub(crate) fn set_hooks_arc(&self, hooks: Arc<dyn Hooks>) {
+        *self.shared.hooks.write().unwrap() = hooks;
+    }
+
+    pub(crate) fn hooks(&self) -> Arc<dyn Hooks> {
+        self.shared.hooks()
+    }
+
+    /// Get a token that will be cancelled by the next call to
+    /// [`Pool::abort_all`].  Long-running clients can use it to stop
+    /// early.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.shared.lock().token.clone()
+    }
+
+    /// Abort all outstanding work on the pool.
+    ///
+    /// Queued clients are dropped immediately, resolving to
+    /// [`Error::Aborted`].  Running jobs are dropped the next time
+    /// they yield, and the token returned by
+    /// [`Pool::cancellation_token`] is cancelled, so that running
+    /// clients can stop cooperatively.
+    pub fn abort_all(&self) {
+        let (waiters, token) = {
+            let mut scheduler = self.shared.lock();
+            (
+                (
+                    scheduler
+                        .local
+                        .iter_mut()
+                        .map(std::mem::take)
+                        .collect::<Vec<_>>(),
+                    scheduler
+                        .lanes
+                        .values_mut()
+                        .map(|lane| std::mem::take(&mut lane.waiters))
+                        .collect::<Vec<_>>(),
+                ),
+                std::mem::take(&mut scheduler.token),
+            )
+        };
+        // waiters must be dropped without holding the lock, as they
+        // may hold claimed resources
+        drop(waiters);
+        token.cancel();
+
+        if let Some(blocking) = self.blocking.get() {
+            blocking.abort_all();
+        }
+    }
+
+    /// Claim a free resource and `permits` permits, creating a new
+    /// resource if there are none free and we have capacity to spare.
+    /// Clients already waiting take priority.
+    ///
+    /// Unless `eager` is set, a new resource is only created if the
+    /// pool is below its minimum size or has no `spawn_after` delay.
+    fn try_claim(&self, scheduler: &mut Scheduler, permits: usize, eager: bool) -> Option<Id> {
+        if scheduler.closed
+            || permits > scheduler.permits
+            || (!scheduler.barging && scheduler.has_waiters())
+        {
+            return None;
+        }
+
+        let grow =
+            eager || self.scaling.spawn_after.is_none() || scheduler.len < self.scaling.min.max(1);
+        let id = match scheduler.take_idle() {
+            Some(id) => id,
+            None if grow && scheduler.len < scheduler.capacity => self.reserve(scheduler),
+            None => return None,
+        };
+        scheduler.permits -= permits;
+        Some(id)
+    }
+
+    /// Make a new resource with a synchronous factory, or return
+    /// `None` if the factory is async and the resource must be created
+    /// later by [`Pool::make_async`].
+    fn make(&self) -> Option<T> {
+        match &mut *self.factory.lock().unwrap() {
+            Factory::Sync(factory) => Some(factory()),
+            Factory::Async(_) => None,
+        }
+    }
+
+    /// Create the claimed resource `id`, which the factory has yet to
+    /// create.
+    async fn make_async(&self, id: Id) -> Result<Arc<T>> {
+        let future = match &mut *self.factory.lock().unwrap() {
+            Factory::Sync(factory) => future::ready(Ok(factory())).left_future(),
+            Factory::Async(factory) => factory().right_future(),
+        };
+        Ok(self.install(id, future.await?))
+    }
+
+    /// Put a newly created resource in the empty slot `id`.
+    fn install(&self, id: Id, resource: T) -> Arc<T> {
+        self.shared.lock().renew(id);
+        let resource = Arc::new(resource);
+        self.resources.write().unwrap()[id] = Some(resource.clone());
+        self.shared.queues.write().unwrap()[id] = self.start(&resource);
+        trace::spawned(id);
+        self.shared.hooks().on_thread_spawned(id);
+        resource
+    }
+
+    /// Drop the claimed resource `id`, leaving its slot empty for a
+    /// replacement.
+    fn discard(&self, id: Id) {
+        let old = (
+            self.resources.write().unwrap()[id].take(),
+            self.shared.queues.write().unwrap()[id].take(),
+        );
+        drop(old);
+        trace::retired(id);
+        self.shared.hooks().on_thread_retired(id);
+    }
+
+    /// Start a resource running the jobs sent to the returned queue,
+    /// if the pool's resources can run jobs.
+    fn start(&self, resource: &T) -> Option<flume::Sender<Job>> {
+        self.executor.map(|executor| {
+            let (sender, receiver) = flume::unbounded();
+            executor(resource, receiver);
+            sender
+        })
+    }
+
+    /// Add a slot for a new resource to the pool, returning it
+    /// claimed.  The resource itself is created once the scheduler is
+    /// unlocked, by [`Pool::fill`] or by the client that claims it, so
+    /// that a slow factory doesn't hold up the rest of the pool.
+    fn reserve(&self, scheduler: &mut Scheduler) -> Id {
+        let mut resources = self.resources.write().unwrap();
+        let mut queues = self.shared.queues.write().unwrap();
+        let id = if let Some(id) = scheduler.free.pop_first() {
+            resources[id] = None;
+            queues[id] = None;
+            id
+        } else {
+            resources.push(None);
+            queues.push(None);
+            scheduler.local.push(VecDeque::new());
+            scheduler.idle_since.push(SystemTime::now());
+            scheduler.created.push(SystemTime::now());
+            scheduler.uses.push(0);
+            scheduler.local.len() - 1
+        };
+        scheduler.renew(id);
+        scheduler.len += 1;
+        id
+    }
+
+    /// Create the resource for the claimed slot `id` if it is empty
+    /// and the factory is synchronous.  The scheduler must not be
+    /// locked.
+    fn fill(&self, id: Id) {
+        let empty = self.resources.read().unwrap()[id].is_none();
+        if empty && let Some(resource) = self.make() {
+            self.install(id, resource);
+        }
+    }
+
+    /// Fill the newly reserved slots `ids` and give them to the pool.
+    /// The scheduler must not be locked.
+    fn start_new(&self, ids: Vec<Id>) {
+        for id in ids {
+            self.fill(id);
+            self.shared.release(id);
+        }
+    }
+
+    /// Replace the claimed resource `id` with a new one from the
+    /// factory, for example because it has failed.
+    fn replace(&self, id: Id) {
+        self.discard(id);
+        if let Some(resource) = self.make() {
+            self.install(id, resource);
+        }
+    }
+
+    /// Whether the resource `id` is fit to lend out: that is, it isn't
+    /// worn out, passes the pool's health check, if it has one, and
+    /// isn't still shared with a health check or
+    /// [`Pool::for_each_idle_async`] that kept hold of it, so that its
+    /// borrower will have it to itself.
+    async fn is_healthy(&self, id: Id, resource: &Arc<T>) -> bool {
+        if self.shared.lock().is_worn_out(id) {
+            return false;
+        }
+        let check = self.health_check.read().unwrap().clone();
+        let healthy = match check {
+            Some(check) => check(resource.clone()).await,
+            None => true,
+        };
+        // the only other reference is the resource's slot
+        healthy && Arc::strong_count(resource) == 2
+    }
+
+    /// Create resources until at least `min_idle` are idle, or the
+    /// pool is at capacity, so that the first clients needn't wait for
+    /// resources to be created.  With a synchronous factory this
+    /// completes immediately.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Factory`] if a fallible factory failed to create a
+    /// resource.
+    pub async fn populate(&self, min_idle: usize) -> Result<()> {
+        loop {
+            let id = {
+                let mut scheduler = self.shared.lock();
+                if scheduler.idle.len() >= min_idle || scheduler.len >= scheduler.capacity {
+                    return Ok(());
+                }
+                self.reserve(&mut scheduler)
+            };
+            // holding the resource with no permits releases it if we
+            // stop waiting for it to be created
+            let mut handle = self.shared.claim(id, DEFAULT_LANE.into(), 0);
+            if let Err(error) = self.make_async(id).await {
+                handle.uncreated = true;
+                return Err(error);
+            }
+            drop(handle);
+        }
+    }
+
+    /// Call `f` on every idle resource, for maintenance such as
+    /// flushing caches.  The pool is locked while `f` runs, so no
+    /// resource can be lent out in the meantime; `f` must not use the
+    /// pool.
+    pub fn for_each_idle(&self, mut f: impl FnMut(&T)) {
+        let scheduler = self.shared.lock();
+        let resources = self.resources.read().unwrap();
+        for &id in &scheduler.idle {
+            if let Some(resource) = &resources[id] {
+                f(resource);
+            }
+        }
+    }
+
+    /// Like [`Pool::for_each_idle`], but for asynchronous maintenance.
+    /// Every idle resource is claimed until its future completes, so
+    /// the pool can be used in the meantime, and the futures are run
+    /// concurrently.  As for [`Pool::set_async_health_check`], a
+    /// resource whose `Arc` is kept is replaced rather than lent.
+    pub async fn for_each_idle_async<F: Future<Output = ()>>(
+        &self,
+        mut f: impl FnMut(Arc<T>) -> F,
+    ) {
+        let claimed = {
+            let mut scheduler = self.shared.lock();
+            let resources = self.resources.read().unwrap();
+            std::mem::take(&mut scheduler.idle)
+                .into_iter()
+                .map(|id| {
+                    (
+                        self.shared.claim(id, DEFAULT_LANE.into(), 0),
+                        resources[id].clone(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        future::join_all(claimed.into_iter().map(|(handle, resource)| {
+            let visit = resource.map(&mut f);
+            async move {
+                if let Some(visit) = visit {
+                    visit.await;
+                }
+                drop(handle);
+            }
+        }))
+        .await;
+    }
+
+    /// Create a new resource for the clients waiting, if there's
+    /// capacity for one.
+    fn grow(&self) {
+        let mut scheduler = self.shared.lock();
+        if !scheduler.has_waiters() || scheduler.len >= scheduler.capacity {
+            return;
+        }
+        let id = self.reserve(&mut scheduler);
+        drop(scheduler);
+        self.start_new(vec![id]);
+    }
+
+    /// Retire resources once they have been idle for longer than the
+    /// idle timeout, down to the pool's minimum size, or return `None`
+    /// if the pool has no idle timeout.  The returned future sleeps
+    /// until the next idle resource is due to retire, and must be run
+    /// for as long as the pool is in use; it finishes once the pool is
+    /// dropped.
+    pub(crate) fn retire_idle(&self) -> Option<impl Future<Output = ()> + 'static>
+    where
+        T: 'static,
+    {
+        let timeout = self.scaling.idle_timeout?;
+        let min = self.scaling.min;
+        let shared = Arc::downgrade(&self.shared);
+        let resources = Arc::downgrade(&self.resources);
+        Some(async move {
+            loop {
+                let (Some(shared), Some(resources)) = (shared.upgrade(), resources.upgrade())
+                else {
+                    break;
+                };
+                let next = Self::retire_expired(&shared, &resources, min, timeout);
+                drop((shared, resources));
+                futures_timer::Delay::new(next).await;
+            }
+        })
+    }
+
+    /// Report clients as they hold their resources for longer than the
+    /// watchdog allows, or return `None` if the watchdog doesn't look
+    /// for long tasks.  The returned future sleeps until the next
+    /// client is due to be reported, and must be run for as long as
+    /// the pool is in use; it finishes once the pool is dropped.
+    pub(crate) fn watch_long_tasks(&self) -> Option<impl Future<Output = ()> + 'static> {
+        self.shared.watchdog.read().unwrap().long_task?;
+        let shared = Arc::downgrade(&self.shared);
+        Some(async move {
+            loop {
+                let Some(next) = shared
+                    .upgrade()
+                    .and_then(|shared| shared.report_long_tasks())
+                else {
+                    break;
+                };
+                futures_timer::Delay::new(next).await;
+            }
+        })
+    }
+
+    /// Retire the resources that have been idle for `timeout`, down to
+    /// `min` resources, returning how long until the next is due to
+    /// retire.
+    fn retire_expired(
+        shared: &Shared,
+        resources: &Slots<T>,
+        min: usize,
+        timeout: Duration,
+    ) -> Duration {
+        let mut retired = Vec::new();
+        let mut scheduler = shared.lock();
+        while scheduler.len > min
+            && let Some(index) = scheduler
+                .idle
+                .iter()
+                .position(|&id| scheduler.idle_since[id].elapsed().unwrap_or_default() >= timeout)
+        {
+            retired.push(Self::retire(shared, resources, &mut scheduler, index));
+        }
+        // a resource that becomes idle later is due no sooner than
+        // `timeout` from now
+        let next = if scheduler.len > min {
+            scheduler
+                .idle
+                .iter()
+                .map(|&id| {
+                    timeout.saturating_sub(scheduler.idle_since[id].elapsed().unwrap_or_default())
+                })
+                .min()
+        } else {
+            None
+        };
+        drop(scheduler);
+        Self::drop_retired(shared, retired);
+        next.unwrap_or(timeout)
+    }
+
+    /// Remove the idle resource at `index` in the idle list from the
+    /// pool.  The resource must be removed while the scheduler is
+    /// locked, before its index can be reused, but is returned to be
+    /// dropped by [`Pool::drop_retired`] once it is unlocked.
+    fn retire(
+        shared: &Shared,
+        resources: &Slots<T>,
+        scheduler: &mut Scheduler,
+        index: usize,
+    ) -> Retired<T> {
+        let id = scheduler.idle.remove(index).expect("index is in bounds");
+        scheduler.len -= 1;
+        scheduler.free.insert(id);
+        let resource = resources.write().unwrap()[id].take();
+        let queue = shared.queues.write().unwrap()[id].take();
+        (id, resource, queue)
+    }
+
+    fn drop_retired(shared: &Shared, retired: Vec<Retired<T>>) {
+        let hooks = shared.hooks();
+        for (id, resource, queue) in retired {
+            drop((resource, queue));
+            trace::retired(id);
+            hooks.on_thread_retired(id);
+        }
+    }
+
+    /// Drop every idle resource, so that new ones are created as
+    /// they're needed.  Resources in use are unaffected.
+    pub fn clear(&self) {
+        let mut retired = Vec::new();
+        let mut scheduler = self.shared.lock();
+        while !scheduler.idle.is_empty() {
+            retired.push(Self::retire(
+                &self.shared,
+                &self.resources,
+                &mut scheduler,
+                0,
+            ));
+        }
+        drop(scheduler);
+        Self::drop_retired(&self.shared, retired);
+    }
+
+    /// Close the pool, so that it lends out no more resources.
+    ///
+    /// Queued clients are dropped immediately, resolving to
+    /// [`Error::Closed`], as are any clients that try to get a resource
+    /// later.  Idle resources are dropped.  Resources in use are
+    /// returned to the pool as usual, and can be dropped with
+    /// [`Pool::clear`] or by dropping the pool.
+    pub fn close(&self) {
+        let waiters = {
+            let mut scheduler = self.shared.lock();
+            scheduler.closed = true;
+            (
+                scheduler
+                    .local
+                    .iter_mut()
+                    .map(std::mem::take)
+                    .collect::<Vec<_>>(),
+                scheduler
+                    .lanes
+                    .values_mut()
+                    .map(|lane| std::mem::take(&mut lane.waiters))
+                    .collect::<Vec<_>>(),
+            )
+        };
+        // waiters must be dropped without holding the lock, as they
+        // may hold claimed resources
+        drop(waiters);
+        self.clear();
+
+        if let Some(blocking) = self.blocking.get() {
+            blocking.close();
+        }
+    }
+
+    /// Whether the pool has been closed with [`Pool::close`].
+    pub fn is_closed(&self) -> bool {
+        self.shared.lock().closed
+    }
+
+    /// The error for a client whose wait for a resource was cut
+    /// short.
+    fn cancelled(&self) -> Error {
+        if self.shared.lock().closed {
+            Error::Closed
+        } else {
+            Error::Aborted
+        }
+    }
+
+    /// Wrap a claimed resource in a guard without waiting, if it
+    /// passes the health check.  If the resource has yet to be
+    /// created or fails the check, it is replaced if the factory is
+    /// synchronous.
+    fn try_guard(&self, mut handle: ResourceHandle, requested: SystemTime) -> Option<Guard<'_, T>> {
+        self.fill(handle.id);
+        let resource = self.resources.read().unwrap()[handle.id].clone();
+        let healthy = match &resource {
+            Some(resource) => self.is_healthy(handle.id, resource).now_or_never()?,
+            None => false,
+        };
+        let resource = match resource {
+            Some(resource) if healthy => resource,
+            unhealthy => {
+                if unhealthy.is_some() {
+                    drop(unhealthy);
+                    self.discard(handle.id);
+                }
+                let Some(resource) = self.make() else {
+                    // the factory is async, so rather than leave the
+                    // slot idle with nothing in it, free it for a
+                    // client that can wait for the resource
+                    handle.uncreated = true;
+                    return None;
+                };
+                self.install(handle.id, resource)
+            }
+        };
+        Some(self.lend(handle, resource, requested))
+    }
+
+    /// Wrap a claimed resource in a guard, creating the resource
+    /// first if need be, or replacing it if it fails the health check.
+    async fn guard(
+        &self,
+        mut handle: ResourceHandle,
+        requested: SystemTime,
+    ) -> Result<Guard<'_, T>> {
+        self.fill(handle.id);
+        let resource = self.resources.read().unwrap()[handle.id].clone();
+        let healthy = match &resource {
+            Some(resource) => self.is_healthy(handle.id, resource).await,
+            None => false,
+        };
+        let resource = match resource {
+            Some(resource) if healthy => resource,
+            unhealthy => {
+                if unhealthy.is_some() {
+                    drop(unhealthy);
+                    self.discard(handle.id);
+                }
+                // the slot is freed for another client to fill if
+                // creating the resource fails or we stop waiting for it
+                handle.uncreated = true;
+                let resource = self.make_async(handle.id).await?;
+                handle.uncreated = false;
+                resource
+            }
+        };
+        Ok(self.lend(handle, resource, requested))
+    }
+
+    /// Lend a claimed resource out to a client that asked for it at
+    /// `requested`, counting it as a use.
+    fn lend(
+        &self,
+        handle: ResourceHandle,
+        resource: Arc<T>,
+        requested: SystemTime,
+    ) -> Guard<'_, T> {
+        let wait = requested.elapsed().unwrap_or_default();
+        // the borrower has the resource to itself until it is returned
+        drop(self.resources.write().unwrap()[handle.id].take());
+        let resource = Lent {
+            resource: Some(resource),
+            slots: self.resources.clone(),
+            id: handle.id,
+        };
+        {
+            let mut scheduler = self.shared.lock();
+            scheduler.uses[handle.id] += 1;
+            let stats = &mut scheduler.stats;
+            stats.acquisitions += 1;
+            stats.total_wait += wait;
+            stats.max_wait = stats.max_wait.max(wait);
+        }
+        self.shared
+            .hooks()
+            .on_resource_acquired(&handle.lane, handle.id, wait);
+        Guard {
+            resource,
+            handle,
+            pool: self,
+        }
+    }
+
+    /// Run a job on the next available resource, queueing it in the
+    /// default lane if there is none, or give the job back if the pool
+    /// is closed.
+    pub(crate) fn spawn_job(&self, job: Job) -> Result<(), Job> {
+        let lane: Arc<str> = DEFAULT_LANE.into();
+        let mut scheduler = self.shared.lock();
+        if scheduler.closed {
+            return Err(job);
+        } else if let Some(id) = self.try_claim(&mut scheduler, 1, true) {
+            drop(scheduler);
+            self.fill(id);
+            self.shared.dispatch(self.shared.claim(id, lane, 1), job);
+        } else {
+            let waiting = scheduler.enqueue(&lane, Waiter::Job(job));
+            drop(scheduler);
+            self.shared.queued(&lane, waiting);
+        }
+        Ok(())
+    }
+
+    /// Run a job on an available resource, giving the job back if
+    /// there is none.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn try_spawn_job(&self, job: Job) -> Result<(), Job> {
+        let mut scheduler = self.shared.lock();
+        let Some(id) = self.try_claim(&mut scheduler, 1, true) else {
+            return Err(job);
+        };
+        drop(scheduler);
+        self.fill(id);
+        self.shared
+            .dispatch(self.shared.claim(id, DEFAULT_LANE.into(), 1), job);
+        Ok(())
+    }
+
+    /// Queue a batch of jobs on a single resource.  Resources that are
+    /// or become idle will steal jobs from the batch.
+    pub(crate) fn queue_batch(&self, jobs: impl IntoIterator<Item = Job>) {
+        let mut scheduler = self.shared.lock();
+        if scheduler.closed {
+            drop(scheduler);
+            return;
+        }
+        let claimed = self.try_claim(&mut scheduler, 1, true);
+        let Some(id) = claimed.or_else(|| {
+            (0..scheduler.local.len())
+                .filter(|&id| scheduler.is_live(id))
+                .min_by_key(|&id| scheduler.local[id].len())
+        }) else {
+            // we have no resources at all, so queue the batch in the
+            // default lane instead
+            let waiting = jobs
+                .into_iter()
+                .map(|job| scheduler.enqueue(&DEFAULT_LANE.into(), Waiter::Job(job)))
+                .collect::<Vec<_>>();
+            drop(scheduler);
+            for waiting in waiting {
+                self.shared.queued(DEFAULT_LANE, waiting);
+            }
+            return;
+        };
+
+        scheduler.local[id].extend(jobs.into_iter().map(|job| LocalJob { job, pinned: false }));
+        let first = claimed.map(|id| {
+            let first = scheduler.local[id].pop_front();
+            if first.is_none() {
+                // the batch was empty, so the resource is given back
+                // below
+                scheduler.permits += 1;
+            }
+            (id, first)
+        });
+        let stolen = scheduler.assign_idle();
+        drop(scheduler);
+        let first = first.and_then(|(id, first)| {
+            self.fill(id);
+            if first.is_none() {
+                self.shared.release(id);
+            }
+            first.map(|LocalJob { job, .. }| Assignment {
+                id,
+                lane: DEFAULT_LANE.into(),
+                waiter: Waiter::Job(job),
+            })
+        });
+        self.shared.start(first.into_iter().chain(stolen));
+    }
+
+    /// Queue a job that must run on the resource with index `id`,
+    /// creating resources up to that index if necessary.
+    ///
+    /// # Panics
+    ///
+    /// If `id` is not less than the pool's capacity.
+    pub(crate) fn queue_pinned(&self, id: Id, job: Job) {
+        let mut scheduler = self.shared.lock();
+        assert!(id < scheduler.capacity, "resource index out of range");
+        if scheduler.closed {
+            drop(scheduler);
+            drop(job);
+            return;
+        }
+        let mut new = Vec::new();
+        while !scheduler.is_live(id) {
+            new.push(self.reserve(&mut scheduler));
+        }
+        if !new.is_empty() {
+            drop(scheduler);
+            self.start_new(new);
+            scheduler = self.shared.lock();
+        }
+
+        if scheduler.permits > 0
+            && let Some(index) = scheduler.idle.iter().position(|&idle| idle == id)
+        {
+            scheduler.idle.remove(index);
+            scheduler.permits -= 1;
+            drop(scheduler);
+            self.shared
+                .dispatch(self.shared.claim(id, DEFAULT_LANE.into(), 1), job);
+        } else {
+            scheduler.local[id].push_back(LocalJob { job, pinned: true });
+        }
+    }
+}
+
+impl<'a, T> Lane<'a, T> {
+    /// The name of this lane.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn pool(&self) -> &'a Pool<T> {
+        self.pool
+    }
+
+    pub(crate) fn hooks(&self) -> Arc<dyn Hooks> {
+        self.pool.hooks()
+    }
+
+    /// Set the weight of this lane.  When several lanes are waiting
+    /// for resources, each receives resource time in proportion to
+    /// its weight.
+    ///
+    /// # Panics
+    ///
+    /// If `weight` is zero.
+    pub fn set_weight(&self, weight: u32) {
+        assert!(weight > 0, "lane weights must be positive");
+        self.pool.shared.lock().lane(&self.name).weight = weight;
+    }
+
+    /// Count clients of this lane as using `permits` permits each.
+    ///
+    /// A pool has as many permits as its capacity, and each client
+    /// uses one by default.  A client that needs more, such as a task
+    /// that spawns its own threads or uses most of the available
+    /// memory bandwidth, is only given a resource once enough permits
+    /// are free.
+    ///
+    /// # Panics
+    ///
+    /// If `permits` is zero or greater than the pool's capacity.
+    #[must_use]
+    pub fn with_permits(mut self, permits: usize) -> Self {
+        assert!(
+            (1..=self.pool.shared.lock().capacity).contains(&permits),
+            "clients must use between one permit and the pool's capacity"
+        );
+        self.permits = permits;
+        self
+    }
+
+    /// Claim a resource if one is available, or else join the queue
+    /// for one.
+    fn claim_or_wait(&self) -> Result<ResourceHandle, oneshot::Receiver<ResourceHandle>> {
+        let mut scheduler = self.pool.shared.lock();
+        if let Some(id) = self.pool.try_claim(&mut scheduler, self.permits, false) {
+            return Ok(self.pool.shared.claim(id, self.name.clone(), self.permits));
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        if scheduler.closed {
+            // dropping the sender tells the client the pool is closed
+            return Err(receiver);
+        }
+        let waiting = scheduler.enqueue(
+            &self.name,
+            Waiter::Task {
+                sender,
+                permits: self.permits,
+            },
+        );
+        drop(scheduler);
+        self.pool.shared.queued(&self.name, waiting);
+        Err(receiver)
+    }
+
+    /// Like [`Pool::get`], but queueing in this lane.
+    ///
+    /// # Errors
+    ///
+    /// As for [`Pool::get`].
+    pub async fn get(&self) -> Result<Guard<'a, T>> {
+        let requested = SystemTime::now();
+        let handle = match self.claim_or_wait() {
+            Ok(handle) => handle,
+            Err(receiver) => self.wait(receiver).await?,
+        };
+        self.pool.guard(handle, requested).await
+    }
+
+    /// Like [`Pool::try_get`], but in this lane.
+    pub fn try_get(&self) -> Option<Guard<'a, T>> {
+        let requested = SystemTime::now();
+        let id = self
+            .pool
+            .try_claim(&mut self.pool.shared.lock(), self.permits, true)?;
+        // a resource the factory has yet to create is given back
+        self.pool.try_guard(
+            self.pool.shared.claim(id, self.name.clone(), self.permits),
+            requested,
+        )
+    }
+
+    /// Wait for a resource to be handed over, growing the pool if the
+    /// wait takes longer than its `spawn_after` delay.
+    async fn wait(
+        &self,
+        mut receiver: oneshot::Receiver<ResourceHandle>,
+    ) -> Result<ResourceHandle> {
+        if let Some(spawn_after) = self.pool.scaling.spawn_after {
+            match future::select(&mut receiver, futures_timer::Delay::new(spawn_after)).await {
+                future::Either::Left((handle, _)) => {
+                    return handle.map_err(|_| self.pool.cancelled());
+                }
+                future::Either::Right(((), _)) => self.pool.grow(),
+            }
+        }
+        receiver.await.map_err(|_| self.pool.cancelled())
+    }
+
+    /// Like [`Pool::get_timeout`], but queueing in this lane.
+    ///
+    /// # Errors
+    ///
+    /// As for [`Pool::get_timeout`].
+    pub async fn get_timeout(&self, timeout: Duration) -> Result<Guard<'a, T>> {
+        // the timeout covers creating the resource as well as waiting
+        // for it, so that a factory that hangs can't hold us up
+        match future::select(Box::pin(self.get()), futures_timer::Delay::new(timeout)).await {
+            future::Either::Left((guard, _)) => guard,
+            future::Either::Right(((), get)) => {
+                // dropping the client releases the resource if it was
+                // given to us in the meantime
+                drop(get);
+                self.pool.shared.lock().lane(&self.name).waiters.retain(
+                    |waiter| !matches!(waiter, Waiter::Task { sender, .. } if sender.is_canceled()),
+                );
+                Err(Error::PoolTimeout)
+            }
+        }
+    }
+}
+
+#[test]
+fn resources_are_reused() {
+    let created = Arc::new(Mutex::new(0));
+    let pool = Pool::with_factory(2, {
+        let created = created.clone();
+        move || {
+            let mut created = created.lock().unwrap();
+            *created += 1;
+            *created
+        }
+    });
+
+    futures::executor::block_on(async {
+        let first = pool.get().await.unwrap();
+        let second = pool.get().await.unwrap();
+        assert_eq!((*first, *second), (1, 2));
+        drop(first);
+        assert_eq!(*pool.get().await.unwrap(), 1);
+    });
+    assert_eq!(*created.lock().unwrap(), 2);
+}
+
+#[test]
+fn aborting_drops_queued_jobs_unlocked() {
+    let pool = Pool::with_factory(2, || ());
+    futures::executor::block_on(async {
+        let busy = pool.get().await.unwrap();
+        let claimed = pool.checkout().await.unwrap();
+        // the job is queued behind `busy`, holding `claimed`
+        pool.queue_pinned(
+            busy.id(),
+            job(FutureObj::new(Box::new(async move { drop(claimed) }))),
+        );
+        assert_eq!(pool.available(), 0);
+
+        pool.abort_all();
+        assert_eq!(pool.available(), 1);
+        drop(busy);
+        assert_eq!(pool.available(), 2);
+    });
+}
+
+#[test]
+fn get_times_out_while_creating() {
+    let pool = Pool::with_async_factory(1, future::pending::<()>);
+    let timeout = Duration::from_millis(10);
+    futures::executor::block_on(async {
+        assert!(matches!(
+            pool.get_timeout(timeout).await,
+            Err(Error::PoolTimeout)
+        ));
+        // the slot was freed for the next client to try again
+        assert_eq!(pool.len(), 0);
+        assert!(matches!(
+            pool.get_timeout(timeout).await,
+            Err(Error::PoolTimeout)
+        ));
+    });
+}
+
+#[test]
+fn try_get_does_not_create_asynchronously() {
+    let pool = Pool::with_async_factory(1, || async {});
+    assert!(pool.try_get().is_none());
+    assert_eq!((pool.len(), pool.available()), (0, 0));
+    futures::executor::block_on(async {
+        drop(pool.get().await.unwrap());
+        assert_eq!((pool.len(), pool.available()), (1, 1));
+        assert!(pool.try_get().is_some());
+    });
+}
+
+#[test]
+fn try_get_does_not_wait() {
+    let pool = Pool::with_factory(1, || ());
+    let first = pool.try_get().unwrap();
+    assert!(pool.try_get().is_none());
+    drop(first);
+    assert!(pool.try_get().is_some());
+}
+
+#[test]
+fn get_times_out() {
+    let pool = Pool::with_factory(1, || ());
+    futures::executor::block_on(async {
+        let first = pool.get().await.unwrap();
+        assert!(matches!(
+            pool.get_timeout(Duration::from_millis(10)).await,
+            Err(Error::PoolTimeout),
+        ));
+        drop(first);
+        pool.get_timeout(Duration::from_millis(10)).await.unwrap();
+    });
+}
+
+#[test]
+fn async_factories_create_resources_on_claim() {
+    let pool = Pool::with_async_factory(2, || async { 5 });
+    assert!(pool.try_get().is_none());
+    futures::executor::block_on(async {
+        let first = pool.get().await.unwrap();
+        assert_eq!(*first, 5);
+        drop(first);
+        assert_eq!(*pool.try_get().unwrap(), 5);
+    });
+}
+
+#[test]
+fn failed_creation_frees_the_slot() {
+    let attempts = Arc::new(Mutex::new(0));
+    let pool = Pool::with_try_factory(1, {
+        let attempts = attempts.clone();
+        move || {
+            let mut attempts = attempts.lock().unwrap();
+            *attempts += 1;
+            if *attempts == 1 {
+                Err("out of ports")
+            } else {
+                Ok(*attempts)
+            }
+        }
+    });
+    futures::executor::block_on(async {
+        assert!(matches!(pool.get().await, Err(Error::Factory(_))));
+        assert_eq!(*pool.get().await.unwrap(), 2);
+    });
+}
+
+#[test]
+fn unhealthy_resources_are_replaced() {
+    let created = Arc::new(Mutex::new(0));
+    let pool = Pool::with_factory(1, {
+        let created = created.clone();
+        move || {
+            let mut created = created.lock().unwrap();
+            *created += 1;
+            *created
+        }
+    });
+    pool.set_health_check(|&resource| resource != 1);
+    futures::executor::block_on(async {
+        assert_eq!(*pool.get().await.unwrap(), 2);
+        assert_eq!(*pool.try_get().unwrap(), 2);
+    });
+    assert_eq!(*created.lock().unwrap(), 2);
+}
+
+#[test]
+fn worn_out_resources_are_recycled() {
+    let created = Arc::new(Mutex::new(0));
+    let pool = Pool::with_factory(1, {
+        let created = created.clone();
+        move || {
+            let mut created = created.lock().unwrap();
+            *created += 1;
+            *created
+        }
+    });
+    pool.set_max_uses(2);
+    futures::executor::block_on(async {
+        for expected in [1, 1, 2, 2, 3] {
+            assert_eq!(*pool.get().await.unwrap(), expected);
+        }
+    });
+}
+
+#[test]
+fn pool_state_is_observable() {
+    let pool = Pool::with_factory(2, || ());
+    assert_eq!((pool.len(), pool.available(), pool.capacity()), (0, 0, 2));
+    futures::executor::block_on(async {
+        let first = pool.get().await.unwrap();
+        let second = pool.get().await.unwrap();
+        assert_eq!((pool.len(), pool.available()), (2, 0));
+
+        let mut third = Box::pin(pool.get());
+        assert!(futures::poll!(third.as_mut()).is_pending());
+        assert_eq!(pool.waiters(), 1);
+
+        drop(first);
+        third.await.unwrap();
+        drop(second);
+        assert_eq!((pool.available(), pool.waiters()), (2, 0));
+    });
+}
+
+#[test]
+fn closing_wakes_waiters() {
+    let pool = Pool::with_factory(1, || ());
+    futures::executor::block_on(async {
+        let first = pool.get().await.unwrap();
+        let mut second = Box::pin(pool.get());
+        assert!(futures::poll!(second.as_mut()).is_pending());
+
+        pool.close();
+        assert!(matches!(second.await, Err(Error::Closed)));
+        assert!(matches!(pool.get().await, Err(Error::Closed)));
+        assert!(pool.try_get().is_none());
+
+        drop(first);
+        assert_eq!(pool.len(), 1);
+        pool.clear();
+        assert_eq!(pool.len(), 0);
+    });
+}
+
+#[test]
+fn guards_can_be_mapped() {
+    let pool = Pool::with_factory(1, || (1, String::from("resource")));
+    futures::executor::block_on(async {
+        let name = pool.get().await.unwrap().map(|resource| &resource.1);
+        assert_eq!(&*name, "resource");
+        let first = name.map(|name| &name[..1]);
+        assert_eq!(&*first, "r");
+        assert!(pool.try_get().is_none());
+        drop(first);
+
+        let number = pool.checkout().await.unwrap().map(|resource| &resource.0);
+        assert_eq!(*number, 1);
+    });
+}
+
+#[test]
+fn taken_resources_are_recreated() {
+    let created = Arc::new(Mutex::new(0));
+    let pool = Pool::with_factory(1, {
+        let created = created.clone();
+        move || {
+            let mut created = created.lock().unwrap();
+            *created += 1;
+            *created
+        }
+    });
+    futures::executor::block_on(async {
+        assert_eq!(pool.get().await.unwrap().take(), 1);
+        assert_eq!(*pool.try_get().unwrap(), 2);
+        assert_eq!(*pool.get().await.unwrap(), 2);
+    });
+}
+
+#[test]
+fn shared_resources_are_replaced() {
+    let created = Arc::new(Mutex::new(0));
+    let pool = Pool::with_factory(1, {
+        let created = created.clone();
+        move || {
+            let mut created = created.lock().unwrap();
+            *created += 1;
+            vec![*created]
+        }
+    });
+    futures::executor::block_on(async {
+        pool.populate(1).await.unwrap();
+        let mut kept = None;
+        pool.for_each_idle_async(|resource| {
+            kept = Some(resource);
+            async {}
+        })
+        .await;
+        // the kept resource is replaced, so the new one can be
+        // mutated and taken
+        let mut guard = pool.get().await.unwrap();
+        guard.push(0);
+        assert_eq!(guard.take(), [2, 0]);
+        assert_eq!(*kept.unwrap(), [1]);
+        assert_eq!(*pool.get().await.unwrap(), [3]);
+    });
+}
+
+#[test]
+fn pools_can_be_populated() {
+    let pool = Pool::with_async_factory(3, || async {});
+    futures::executor::block_on(async {
+        pool.populate(2).await.unwrap();
+        assert_eq!(pool.available(), 2);
+        let first = pool.try_get().unwrap();
+        let second = pool.try_get().unwrap();
+        assert!(pool.try_get().is_none());
+        drop((first, second));
+    });
+}
+
+#[test]
+fn lifo_reuses_recent_resources() {
+    let created = Arc::new(Mutex::new(0));
+    let pool = Pool::with_factory(2, {
+        let created = created.clone();
+        move || {
+            let mut created = created.lock().unwrap();
+            *created += 1;
+            *created
+        }
+    });
+    futures::executor::block_on(async {
+        let first = pool.get().await.unwrap();
+        let second = pool.get().await.unwrap();
+        drop((first, second));
+        assert_eq!(*pool.get().await.unwrap(), 1);
+
+        pool.set_reuse(Reuse::Lifo);
+        let first = pool.get().await.unwrap();
+        let second = pool.get().await.unwrap();
+        assert_eq!((*first, *second), (1, 2));
+        drop((first, second));
+        assert_eq!(*pool.get().await.unwrap(), 2);
+    });
+}
+
+#[test]
+fn usage_is_recorded() {
+    let pool = Pool::with_factory(1, || ());
+    futures::executor::block_on(async {
+        let first = pool.get().await.unwrap();
+        let second = pool.get_timeout(Duration::from_millis(50));
+        let ((), second) = futures::join!(
+            async move {
+                futures_timer::Delay::new(Duration::from_millis(10)).await;
+                drop(first);
+            },
+            second,
+        );
+        drop(second.unwrap());
+    });
+
+    let stats = pool.stats();
+    assert_eq!((stats.acquisitions, stats.releases), (2, 2));
+    assert!(stats.max_wait >= Duration::from_millis(10));
+    assert!(stats.max_held >= Duration::from_millis(10));
+}
+
+#[test]
+fn fair_pools_are_not_overtaken() {
+    let pool = Pool::with_factory(2, || ());
+    futures::executor::block_on(async {
+        let first = pool.get().await.unwrap();
+        let heavy = pool.lane("heavy").with_permits(2);
+        let mut waiting = Box::pin(heavy.get());
+        assert!(futures::poll!(waiting.as_mut()).is_pending());
+        assert!(pool.try_get().is_none());
+
+        pool.set_fair(false);
+        drop(pool.try_get().unwrap());
+
+        drop(first);
+        waiting.await.unwrap();
+    });
+}
+
+#[test]
+fn guards_give_mutable_access() {
+    let pool = Pool::with_factory(1, Vec::new);
+    futures::executor::block_on(async {
+        pool.get().await.unwrap().push(1);
+        pool.checkout().await.unwrap().push(2);
+        assert_eq!(*pool.get().await.unwrap(), [1, 2]);
+    });
+}
+
+#[test]
+fn idle_resources_can_be_visited() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let pool = Pool::with_factory(2, || AtomicUsize::new(0));
+    futures::executor::block_on(async {
+        pool.populate(2).await.unwrap();
+        let held = pool.get().await.unwrap();
+        pool.for_each_idle(|count| {
+            count.fetch_add(1, Ordering::Relaxed);
+        });
+        pool.for_each_idle_async(|count| async move {
+            count.fetch_add(1, Ordering::Relaxed);
+        })
+        .await;
+        assert_eq!(held.load(Ordering::Relaxed), 0);
+        assert_eq!(pool.available(), 1);
+        assert_eq!(pool.try_get().unwrap().load(Ordering::Relaxed), 2);
+    });
+}
@@ -0,0 +1,63 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Running [`rayon`](https://docs.rs/rayon) on the pool's threads, so
+//! that existing parallel iterator code works in the browser without
+//! a second mechanism for spawning workers.
+
+use futures::task::FutureObj;
+use rayon_core::{ThreadBuilder, ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+
+use crate::{Pool, pool::job, web_thread};
+
+impl Pool<web_thread::Thread> {
+    /// Build a Rayon thread pool of `num_threads` threads, each of
+    /// which runs on one of this pool's blocking threads (see
+    /// [`Pool::spawn_blocking`]).
+    ///
+    /// The blocking threads remain claimed until the Rayon thread pool
+    /// is dropped, and are not affected by [`Pool::abort_all`].
+    ///
+    /// # Errors
+    ///
+    /// If there are fewer than `num_threads` blocking threads
+    /// available.
+    pub fn build_rayon(&self, num_threads: usize) -> Result<ThreadPool, ThreadPoolBuildError> {
+        ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .spawn_handler(|thread| self.spawn_rayon_thread(thread))
+            .build()
+    }
+
+    /// Like [`Pool::build_rayon`], but installing the Rayon thread
+    /// pool as Rayon's global thread pool.
+    ///
+    /// # Errors
+    ///
+    /// If there are fewer than `num_threads` blocking threads
+    /// available, or Rayon's global thread pool has already been
+    /// initialized.
+    pub fn build_rayon_global(&self, num_threads: usize) -> Result<(), ThreadPoolBuildError> {
+        ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .spawn_handler(|thread| self.spawn_rayon_thread(thread))
+            .build_global()
+    }
+
+    fn spawn_rayon_thread(&self, thread: ThreadBuilder) -> std::io::Result<()> {
+        // Rayon expects its threads to start immediately, so we can't
+        // queue them to wait for a thread to become available
+        self.blocking_pool()
+            .try_spawn_job(job(FutureObj::new(Box::new(async move { thread.run() }))))
+            .map_err(|_| std::io::Error::other("no blocking threads available for Rayon"))
+    }
+}
+
+#[test]
+fn rayon_runs_on_blocking_threads() {
+    let pool = Pool::with_blocking_capacity(1, 2);
+    let rayon = pool.build_rayon(2).unwrap();
+    assert_eq!(rayon.install(|| rayon_core::join(|| 1, || 2)), (1, 2));
+    // all the blocking threads are in use
+    assert!(pool.build_rayon(1).is_err());
+}
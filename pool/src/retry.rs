@@ -0,0 +1,105 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{sync::Arc, time::Duration};
+
+use crate::{Error, Lane, Result, web_thread};
+
+/// When and how often to retry a failed task; see
+/// [`Pool::run_with_retry`](crate::Pool::run_with_retry).
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    backoff: Duration,
+    retry_on: Arc<dyn Fn(&Error) -> bool + Send + Sync>,
+}
+
+impl std::fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("backoff", &self.backoff)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RetryPolicy {
+    /// Run a task up to `max_attempts` times in total, retrying
+    /// immediately whenever its thread crashes.
+    #[must_use]
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            backoff: Duration::ZERO,
+            retry_on: Arc::new(|error| {
+                // a panic kills the worker on the Web, and is caught
+                // natively
+                matches!(
+                    error,
+                    Error::Thread(web_thread::Error::Killed | web_thread::Error::Panic(_))
+                )
+            }),
+        }
+    }
+
+    /// Wait `backoff` before the first retry, doubling the wait
+    /// before each retry after that.
+    #[must_use]
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Retry only errors for which `predicate` returns `true`.  By
+    /// default, only errors from the thread being killed or the task
+    /// panicking are retried: other [`Error::Thread`] errors, such as
+    /// failing to send the task's output, would just fail again.
+    #[must_use]
+    pub fn retry_on(mut self, predicate: impl Fn(&Error) -> bool + Send + Sync + 'static) -> Self {
+        self.retry_on = Arc::new(predicate);
+        self
+    }
+
+    /// Whether the policy retries `error`.
+    pub(crate) fn retries(&self, error: &Error) -> bool {
+        (self.retry_on)(error)
+    }
+
+    /// How long to wait before retrying after `attempt` attempts.
+    fn delay(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+    }
+}
+
+pub(crate) async fn run_with_retry<
+    Context: web_thread::Post + Clone,
+    F: Future<Output: web_thread::Post> + 'static,
+>(
+    lane: &Lane<'_, web_thread::Thread>,
+    policy: &RetryPolicy,
+    context: Context,
+    code: impl FnOnce(Context) -> F + Clone + Send + 'static,
+) -> Result<F::Output> {
+    let mut attempt = 1;
+    loop {
+        {
+            let mut task = std::pin::pin!(lane.run(context.clone(), code.clone()).await);
+            let error = match task.as_mut().await {
+                Ok(output) => return Ok(output),
+                Err(error) => error,
+            };
+            if attempt >= policy.max_attempts || !policy.retries(&error) {
+                return Err(error);
+            }
+
+            // the thread may have crashed, so replace it before it
+            // can be reused
+            if matches!(error, Error::Thread(_)) {
+                task.respawn_thread(lane.pool());
+            }
+        }
+        futures_timer::Delay::new(policy.delay(attempt)).await;
+        attempt += 1;
+    }
+}
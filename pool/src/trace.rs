@@ -0,0 +1,115 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Instrumentation of the pool with [`tracing`](https://docs.rs/tracing),
+//! compiled to nothing unless the `tracing` feature is enabled.
+
+use std::time::Duration;
+
+use crate::Result;
+#[cfg(feature = "tracing")]
+use crate::{Error, web_thread};
+
+#[cfg(feature = "tracing")]
+pub(crate) type Span = tracing::Span;
+
+#[cfg(not(feature = "tracing"))]
+#[derive(Clone)]
+pub(crate) struct Span;
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    pub(crate) fn none() -> Self {
+        Self
+    }
+}
+
+/// Create the span for a task that waited `queue_wait` in `lane` for
+/// the thread `thread`.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn task(lane: &str, thread: usize, queue_wait: Duration, name: &'static str) -> Span {
+    #[cfg(feature = "tracing")]
+    return tracing::info_span!(
+        "pool_task",
+        lane,
+        thread,
+        queue_wait_ms = queue_wait.as_secs_f64() * 1000.,
+        task = name,
+        outcome = tracing::field::Empty,
+    );
+    #[cfg(not(feature = "tracing"))]
+    Span
+}
+
+/// Record how a task ended: `ok`, `error`, or `aborted`.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn outcome<T>(span: &Span, output: &Result<T>) {
+    #[cfg(feature = "tracing")]
+    span.record(
+        "outcome",
+        match output {
+            Ok(_) => "ok",
+            Err(Error::Aborted | Error::Thread(web_thread::Error::Aborted)) => "aborted",
+            Err(_) => "error",
+        },
+    );
+}
+
+/// Run `f` inside `span`.
+pub(crate) fn in_span<R>(span: &Span, f: impl FnOnce() -> R) -> R {
+    #[cfg(feature = "tracing")]
+    return span.in_scope(f);
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = span;
+        f()
+    }
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn spawned(id: usize) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(id, "spawned pool thread");
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn retired(id: usize) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(id, "retired pool thread");
+}
+
+/// Note that a client had to queue in `lane` because every thread was
+/// busy.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn saturated(lane: &str, waiting: usize) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(lane, waiting, "pool saturated");
+}
+
+/// Note that more than the watermark of clients are queued.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn queue_watermark(waiting: usize) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(waiting, "pool queue above watermark");
+}
+
+/// Note that the task `name` from `lane` has held `thread` for
+/// `elapsed`, longer than the watchdog allows.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn long_task(lane: &str, thread: usize, name: &str, elapsed: Duration) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(
+        lane,
+        thread,
+        task = name,
+        elapsed_ms = elapsed.as_secs_f64() * 1000.,
+        "long-running pool task"
+    );
+}
+
+/// Note that a task started with `Pool::run_detached` failed.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn detached_failed(error: &(dyn std::error::Error + 'static)) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(error, "detached pool task failed");
+}
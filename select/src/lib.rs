@@ -7,15 +7,21 @@
 This crate allows selecting between `web-thread` and `web-thread-shim`
 using a feature flag.
 
-If the target is a `wasm32` architecture and the `web` feature flag is
+If the target is a Wasm architecture and the `web` feature flag is
 passed, we use the Web implementation of `web-thread`; otherwise, we
-transparently substitute in the shim.
+transparently substitute in the shim.  The `force-shim` feature flag
+substitutes in the shim regardless, for testing in environments such
+as Node.js that can't run module workers with shared memory; there the
+shim runs tasks on the current thread.  The choice is made by
+`web-thread-backend`, which `web-thread-pool` shares, so the two
+crates always use the same implementation.
+
+With the `pool` feature, the thread and resource pools of
+`web-thread-pool` are re-exported as [`pool`], using the same
+implementation.
 */
 
-cfg_if::cfg_if! {
-    if #[cfg(all(target_arch = "wasm32", feature = "web"))] {
-        pub use web_thread::*;
-    } else {
-        pub use web_thread_shim::*;
-    }
-}
+pub use web_thread_backend::*;
+
+#[cfg(feature = "pool")]
+pub use web_thread_pool::{self as pool, Pool};
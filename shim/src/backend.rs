@@ -0,0 +1,359 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Executors that run the tasks of a [`Thread`](crate::Thread).
+
+use std::sync::{
+    OnceLock,
+    atomic::{AtomicUsize, Ordering},
+};
+#[cfg(feature = "test-util")]
+use std::{
+    cell::RefCell,
+    sync::{Arc, Mutex},
+    task::{Poll, Waker},
+    time::Duration,
+};
+
+use futures::{
+    FutureExt as _, StreamExt as _,
+    channel::{mpsc, oneshot},
+    executor::LocalPool,
+    task::{LocalFutureObj, LocalSpawn as _},
+};
+
+/// A function creating the future that runs all a thread's tasks.
+/// The future completes when the [`Thread`](crate::Thread) is dropped.
+pub type ThreadMain = Box<dyn FnOnce() -> LocalFutureObj<'static, ()> + Send>;
+
+/// A way of running threads for the shim.
+///
+/// A backend must run the future returned by its [`ThreadMain`] to
+/// completion on a thread of its own, since the future isn't `Send`
+/// and may block.
+pub trait Backend {
+    /// Start a thread running the future created by `main`.
+    /// Backends that create threads of their own should create them
+    /// with `thread`, which carries the thread's name and stack size.
+    ///
+    /// # Errors
+    ///
+    /// If the thread could not be started.
+    fn spawn(&self, thread: std::thread::Builder, main: ThreadMain) -> std::io::Result<JoinHandle>;
+}
+
+/// A handle to a thread started by a [`Backend`].
+pub struct JoinHandle(Box<dyn FnOnce() + Send + Sync>);
+
+impl JoinHandle {
+    /// Create a handle from a function that waits for the thread to
+    /// finish.
+    pub fn new(join: impl FnOnce() + Send + Sync + 'static) -> Self {
+        Self(Box::new(join))
+    }
+
+    /// Wait for the thread to finish.
+    pub fn join(self) {
+        (self.0)();
+    }
+}
+
+/// The default backend, which runs each thread's tasks on a
+/// [`futures::executor::LocalPool`] on a new `std` thread.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Std;
+
+impl Backend for Std {
+    fn spawn(&self, thread: std::thread::Builder, main: ThreadMain) -> std::io::Result<JoinHandle> {
+        let thread = thread.spawn(|| LocalPool::new().run_until(main()))?;
+        Ok(JoinHandle::new(move || {
+            let _ = thread.join();
+        }))
+    }
+}
+
+/// A backend that runs each thread's tasks on a
+/// [`tokio::task::LocalSet`], so that they can use Tokio's IO types.
+///
+/// Inside a Tokio runtime each thread is one of the runtime's blocking
+/// threads, named and sized by the runtime; elsewhere it is a
+/// dedicated thread with a runtime of its own.
+#[cfg(feature = "tokio")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Tokio;
+
+#[cfg(feature = "tokio")]
+impl Backend for Tokio {
+    fn spawn(&self, thread: std::thread::Builder, main: ThreadMain) -> std::io::Result<JoinHandle> {
+        let run = |runtime: &tokio::runtime::Handle| {
+            runtime.block_on(tokio::task::LocalSet::new().run_until(main()));
+        };
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let thread = runtime.clone().spawn_blocking(move || run(&runtime));
+            Ok(JoinHandle::new(move || {
+                let _ = futures::executor::block_on(thread);
+            }))
+        } else {
+            // outside a runtime, the thread gets a runtime of its own
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            let thread = thread.spawn(move || run(runtime.handle()))?;
+            Ok(JoinHandle::new(move || {
+                let _ = thread.join();
+            }))
+        }
+    }
+}
+
+/// A backend that multiplexes threads onto a shared set of native
+/// threads, one per available core (see
+/// [`std::thread::available_parallelism`]), so that creating a
+/// [`Thread`](crate::Thread) is cheap and doesn't create a native
+/// thread of its own.
+///
+/// Threads sharing a native thread run their tasks concurrently, so a
+/// task that blocks holds up the tasks of other threads too.  Names
+/// and stack sizes are ignored.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Shared;
+
+impl Shared {
+    /// The senders to each shared native thread, which are created
+    /// on first use.
+    fn workers() -> &'static [mpsc::UnboundedSender<ThreadMain>] {
+        static WORKERS: OnceLock<Vec<mpsc::UnboundedSender<ThreadMain>>> = OnceLock::new();
+        WORKERS.get_or_init(|| {
+            let count = std::thread::available_parallelism().map_or(1, usize::from);
+            (0..count)
+                .map(|index| {
+                    let (sender, mut receiver) = mpsc::unbounded::<ThreadMain>();
+                    std::thread::Builder::new()
+                        .name(format!("web-thread-shim-{index}"))
+                        .spawn(move || {
+                            let mut executor = LocalPool::new();
+                            let spawner = executor.spawner();
+                            executor.run_until(async move {
+                                while let Some(main) = receiver.next().await {
+                                    spawner
+                                        .spawn_local_obj(main())
+                                        .expect("executor should exist until destroyed");
+                                }
+                            });
+                        })
+                        .expect("failed to spawn shared thread");
+                    sender
+                })
+                .collect()
+        })
+    }
+}
+
+impl Backend for Shared {
+    fn spawn(
+        &self,
+        _thread: std::thread::Builder,
+        main: ThreadMain,
+    ) -> std::io::Result<JoinHandle> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let workers = Self::workers();
+        let (done_sender, done) = oneshot::channel::<()>();
+        workers[NEXT.fetch_add(1, Ordering::Relaxed) % workers.len()]
+            .unbounded_send(Box::new(move || {
+                LocalFutureObj::new(Box::new(main().map(move |()| {
+                    let _ = done_sender.send(());
+                })))
+            }))
+            .map_err(|_| std::io::Error::other("shared thread has stopped"))?;
+        Ok(JoinHandle::new(move || {
+            let _ = futures::executor::block_on(done);
+        }))
+    }
+}
+
+/// A backend for deterministic tests, which runs threads' tasks on
+/// the current thread, one step at a time, only when asked to.
+///
+/// Time stands still for tasks that sleep on the backend's [`Clock`],
+/// or use the timers in [`time`](crate::time), until it is moved on
+/// with [`Manual::advance`], so that timeouts can be tested without
+/// real sleeps.
+#[cfg(feature = "test-util")]
+#[derive(Default)]
+pub struct Manual {
+    executor: RefCell<LocalPool>,
+    clock: Clock,
+}
+
+#[cfg(feature = "test-util")]
+thread_local! {
+    /// The clock of the [`Manual`] backend running tasks on this
+    /// thread, if any.
+    static CURRENT_CLOCK: RefCell<Option<Clock>> = const { RefCell::new(None) };
+}
+
+/// Restores the previous [`CURRENT_CLOCK`] when dropped.
+#[cfg(feature = "test-util")]
+struct Entered(Option<Clock>);
+
+#[cfg(feature = "test-util")]
+impl Drop for Entered {
+    fn drop(&mut self) {
+        CURRENT_CLOCK.set(self.0.take());
+    }
+}
+
+#[cfg(feature = "test-util")]
+impl Manual {
+    /// Create a backend with its clock at zero.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The backend's clock, for tasks to sleep on.
+    #[must_use]
+    pub fn clock(&self) -> Clock {
+        self.clock.clone()
+    }
+
+    /// Run every thread's tasks until none of them can make progress.
+    pub fn run_until_stalled(&self) {
+        let _entered = self.enter();
+        self.executor.borrow_mut().run_until_stalled();
+    }
+
+    /// Run every thread's tasks until `future` completes.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let _entered = self.enter();
+        self.executor.borrow_mut().run_until(future)
+    }
+
+    /// Make the backend's clock the one timers use on this thread
+    /// while it runs tasks.
+    fn enter(&self) -> Entered {
+        Entered(CURRENT_CLOCK.replace(Some(self.clock.clone())))
+    }
+
+    /// The clock of the backend running tasks on this thread, if any.
+    pub(crate) fn current_clock() -> Option<Clock> {
+        CURRENT_CLOCK.with_borrow(Clone::clone)
+    }
+
+    /// Move the clock on by `duration`, waking tasks whose sleeps
+    /// have ended, and run every thread's tasks until they stall.
+    pub fn advance(&self, duration: Duration) {
+        self.clock.advance(duration);
+        self.run_until_stalled();
+    }
+}
+
+#[cfg(feature = "test-util")]
+impl Backend for Manual {
+    fn spawn(
+        &self,
+        _thread: std::thread::Builder,
+        main: ThreadMain,
+    ) -> std::io::Result<JoinHandle> {
+        self.executor
+            .borrow()
+            .spawner()
+            .spawn_local_obj(main())
+            .map_err(std::io::Error::other)?;
+        Ok(JoinHandle::new(|| ()))
+    }
+}
+
+/// A clock that moves only when a [`Manual`] backend is advanced.
+#[cfg(feature = "test-util")]
+#[derive(Clone, Default)]
+pub struct Clock(Arc<Mutex<ClockState>>);
+
+#[cfg(feature = "test-util")]
+#[derive(Default)]
+struct ClockState {
+    now: Duration,
+    sleepers: Vec<Waker>,
+}
+
+#[cfg(feature = "test-util")]
+impl Clock {
+    /// The time since the clock was created.
+    #[must_use]
+    pub fn now(&self) -> Duration {
+        self.0.lock().unwrap().now
+    }
+
+    /// Wait until the clock has moved on by `duration`.
+    pub fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send + 'static {
+        let state = self.0.clone();
+        let deadline = self.now() + duration;
+        futures::future::poll_fn(move |context| {
+            let mut state = state.lock().unwrap();
+            if state.now >= deadline {
+                return Poll::Ready(());
+            }
+            state.sleepers.push(context.waker().clone());
+            Poll::Pending
+        })
+    }
+
+    fn advance(&self, duration: Duration) {
+        let sleepers = {
+            let mut state = self.0.lock().unwrap();
+            state.now += duration;
+            std::mem::take(&mut state.sleepers)
+        };
+        // sleepers that haven't yet reached their deadline will wait
+        // again when polled
+        for sleeper in sleepers {
+            sleeper.wake();
+        }
+    }
+}
+
+/// A backend that runs each thread's tasks on the current thread,
+/// for Wasm targets, which can't spawn native threads.
+#[cfg(target_family = "wasm")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Local;
+
+#[cfg(target_family = "wasm")]
+impl Backend for Local {
+    fn spawn(
+        &self,
+        _thread: std::thread::Builder,
+        main: ThreadMain,
+    ) -> std::io::Result<JoinHandle> {
+        wasm_bindgen_futures::spawn_local(main());
+        Ok(JoinHandle::new(|| ()))
+    }
+}
+
+/// The backend used by [`Thread::new`](crate::Thread::new): [`Local`]
+/// on Wasm targets, and otherwise [`Shared`] with the `shared`
+/// feature, [`Tokio`] with the `tokio` feature, or [`Std`].
+#[cfg(target_family = "wasm")]
+pub type DefaultBackend = Local;
+
+/// The backend used by [`Thread::new`](crate::Thread::new): [`Local`]
+/// on Wasm targets, and otherwise [`Shared`] with the `shared`
+/// feature, [`Tokio`] with the `tokio` feature, or [`Std`].
+#[cfg(all(not(target_family = "wasm"), feature = "shared"))]
+pub type DefaultBackend = Shared;
+
+/// The backend used by [`Thread::new`](crate::Thread::new): [`Local`]
+/// on Wasm targets, and otherwise [`Shared`] with the `shared`
+/// feature, [`Tokio`] with the `tokio` feature, or [`Std`].
+#[cfg(all(
+    not(target_family = "wasm"),
+    feature = "tokio",
+    not(feature = "shared")
+))]
+pub type DefaultBackend = Tokio;
+
+/// The backend used by [`Thread::new`](crate::Thread::new): [`Local`]
+/// on Wasm targets, and otherwise [`Shared`] with the `shared`
+/// feature, [`Tokio`] with the `tokio` feature, or [`Std`].
+#[cfg(not(any(target_family = "wasm", feature = "tokio", feature = "shared")))]
+pub type DefaultBackend = Std;
@@ -0,0 +1,166 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Typed broadcast channels, for sending values to every thread that
+//! listens on a channel of the same name.  The Web's channels reach
+//! other tabs too; these reach the current process.
+
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    marker::PhantomData,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+};
+
+use futures::{StreamExt as _, channel::mpsc};
+
+use super::{Post, Result, round_trip};
+
+/// The receivers listening on each channel, as `mpsc::UnboundedSender`s
+/// of the channel's type.
+static CHANNELS: Mutex<BTreeMap<String, Vec<Box<dyn Listener>>>> = Mutex::new(BTreeMap::new());
+
+/// A receiver listening on a channel, of any type.
+trait Listener: Send {
+    fn as_any(&self) -> &dyn Any;
+
+    /// Whether the receiver has been dropped.
+    fn is_closed(&self) -> bool;
+}
+
+impl<T: Send + 'static> Listener for mpsc::UnboundedSender<T> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn is_closed(&self) -> bool {
+        mpsc::UnboundedSender::is_closed(self)
+    }
+}
+
+/// Values that can be broadcast.  On the Web, these are the values
+/// posted as plain data, with `serde`; here every [`Post`] value that
+/// can be cloned can be.
+pub trait Broadcast: Post + Clone {}
+
+impl<T: Post + Clone> Broadcast for T {}
+
+/// Join the broadcast channel called `name`, returning a sender to it
+/// and a receiver of everything sent to it, including by the sender.
+///
+/// # Errors
+///
+/// Never: this mirrors `web-thread`, where the channel might not be
+/// created.
+pub fn broadcast<T: Broadcast>(name: &str) -> Result<(Sender<T>, Receiver<T>)> {
+    let (sender, receiver) = mpsc::unbounded::<T>();
+    CHANNELS
+        .lock()
+        .unwrap()
+        .entry(name.to_owned())
+        .or_default()
+        .push(Box::new(sender));
+    Ok((
+        Sender {
+            name: name.to_owned(),
+            _phantom: PhantomData,
+        },
+        Receiver {
+            name: name.to_owned(),
+            receiver,
+        },
+    ))
+}
+
+/// The sending half of a [`broadcast`] channel.
+pub struct Sender<T> {
+    name: String,
+    _phantom: PhantomData<fn(T)>,
+}
+
+impl<T: Broadcast> Sender<T> {
+    /// Send a copy of `value` to every receiver on the channel.
+    /// Receivers of another type on a channel of the same name are
+    /// skipped.
+    ///
+    /// # Errors
+    ///
+    /// With the `serde-check` feature, [`Error::Serialization`](crate::Error::Serialization)
+    /// if the value could not be serialized.
+    pub fn send(&self, value: &T) -> Result<()> {
+        let value = round_trip(value.clone())?;
+        let mut channels = CHANNELS.lock().unwrap();
+        let Some(receivers) = channels.get_mut(&self.name) else {
+            return Ok(());
+        };
+        receivers.retain(|receiver| {
+            receiver
+                .as_any()
+                .downcast_ref::<mpsc::UnboundedSender<T>>()
+                .is_none_or(|receiver| receiver.unbounded_send(value.clone()).is_ok())
+        });
+        if receivers.is_empty() {
+            channels.remove(&self.name);
+        }
+        Ok(())
+    }
+}
+
+/// The receiving half of a [`broadcast`] channel, which is a
+/// [`Stream`](futures::Stream) of the values sent to it.  It receives
+/// values sent after it was created, and never ends.
+pub struct Receiver<T> {
+    name: String,
+    receiver: mpsc::UnboundedReceiver<T>,
+}
+
+impl<T> futures::Stream for Receiver<T> {
+    type Item = Result<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Option<Result<T>>> {
+        self.receiver
+            .poll_next_unpin(context)
+            .map(|value| value.map(Ok))
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        // stop listening now, rather than on the channel's next send
+        self.receiver.close();
+        let mut channels = CHANNELS.lock().unwrap();
+        let Some(receivers) = channels.get_mut(&self.name) else {
+            return;
+        };
+        receivers.retain(|receiver| !receiver.is_closed());
+        if receivers.is_empty() {
+            channels.remove(&self.name);
+        }
+    }
+}
+
+#[test]
+fn every_receiver_gets_a_copy() {
+    let (sender, mut first) = broadcast::<u32>("every_receiver_gets_a_copy").unwrap();
+    let (_, mut second) = broadcast::<u32>("every_receiver_gets_a_copy").unwrap();
+    let (_, mut other) = broadcast::<u32>("another channel").unwrap();
+    sender.send(&7).unwrap();
+    futures::executor::block_on(async {
+        assert_eq!(first.next().await.unwrap().unwrap(), 7);
+        assert_eq!(second.next().await.unwrap().unwrap(), 7);
+    });
+    assert!(other.receiver.try_next().is_err());
+}
+
+#[test]
+fn dropped_receivers_stop_listening() {
+    let name = "dropped_receivers_stop_listening";
+    let (_, first) = broadcast::<u32>(name).unwrap();
+    let (_, second) = broadcast::<u32>(name).unwrap();
+    drop(first);
+    assert_eq!(CHANNELS.lock().unwrap()[name].len(), 1);
+    drop(second);
+    assert!(!CHANNELS.lock().unwrap().contains_key(name));
+}
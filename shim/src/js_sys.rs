@@ -0,0 +1,34 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Stand-ins for the `js_sys` types in `web-thread`'s API, so that
+//! code calling [`Post::transferables`](crate::Post::transferables)
+//! compiles against both crates.  Native threads share memory, so nothing is ever
+//! transferred and these types hold no values.
+
+/// A stand-in for [`js_sys::Array`](https://docs.rs/js-sys/latest/js_sys/struct.Array.html),
+/// used for lists of transferables.  It is always empty.
+#[derive(Clone, Debug, Default)]
+pub struct Array {
+    _private: (),
+}
+
+impl Array {
+    /// Create an empty array.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Concatenate two arrays.
+    #[must_use]
+    pub fn concat(&self, _other: &Array) -> Array {
+        Self::new()
+    }
+
+    /// The length of the array, which is always zero.
+    #[must_use]
+    pub fn length(&self) -> u32 {
+        0
+    }
+}
@@ -14,31 +14,57 @@ If you aren't using `web-thread`, you probably don't want this crate!
 Just use `std::thread`.
  */
 
+pub mod backend;
+pub mod broadcast;
+pub mod js_sys;
+pub mod time;
+
 use std::{
+    any::Any,
+    collections::VecDeque,
+    panic::AssertUnwindSafe,
     pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
     task::{Context, Poll},
 };
 
+use backend::Backend;
+pub use broadcast::broadcast;
 use futures::{
+    StreamExt as _,
     channel::{mpsc, oneshot},
-    future::FutureExt as _,
+    future::{self, FusedFuture, FutureExt as _},
+    stream::FuturesUnordered,
     task::LocalFutureObj,
 };
 
-/// The type of errors that may arise from operations in this crate.
-#[derive(Debug, thiserror::Error)]
-#[non_exhaustive]
-pub enum Error {
-    #[error("thread killed before task completed")]
-    Killed(#[from] oneshot::Canceled),
-}
-
-/// Convenience alias for `Result<T, Error>`.
-pub type Result<T, E = Error> = std::result::Result<T, E>;
+pub use watch::watch;
+pub use web_thread_core::{CancellationToken, Error, Result, sync, watch};
 
 /// A thread running a local future executor ([`futures::executor::LocalPool`]).
 pub struct Thread {
     sender: mpsc::UnboundedSender<Request>,
+    policy: DropPolicy,
+    // set to let in-flight tasks finish once the thread is dropped
+    drain: Arc<AtomicBool>,
+    // resolves when the thread has stopped running tasks
+    done: Option<oneshot::Receiver<()>>,
+    _join: backend::JoinHandle,
+}
+
+/// What happens to a [`Thread`]'s in-flight tasks when it is dropped.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DropPolicy {
+    /// Stop the thread immediately, so that in-flight tasks resolve
+    /// to [`Error::Killed`].
+    #[default]
+    Terminate,
+    /// Let in-flight tasks run to completion before the thread stops.
+    Detach,
 }
 
 type Request = Box<dyn FnOnce() -> LocalFutureObj<'static, ()> + Send>;
@@ -46,13 +72,80 @@ type Request = Box<dyn FnOnce() -> LocalFutureObj<'static, ()> + Send>;
 /// A task that's been spawned on a [`Thread`] that should eventually
 /// compute a `T`.
 pub struct Task<T> {
-    receiver: oneshot::Receiver<T>,
+    receiver: oneshot::Receiver<Result<T>>,
+    abort: AbortHandle,
+}
+
+impl<T> Task<T> {
+    /// A task that has already failed with `error`.
+    fn failed(error: Error) -> Self {
+        let (sender, receiver) = oneshot::channel();
+        let _ = sender.send(Err(error));
+        Self {
+            receiver,
+            abort: AbortHandle(future::AbortHandle::new_pair().0),
+        }
+    }
+
+    /// Abort the task, dropping its future on the thread.  The task
+    /// resolves to [`Error::Aborted`] if it hadn't yet completed.
+    pub fn abort(&self) {
+        self.abort.abort();
+    }
+
+    /// A handle that can abort the task from elsewhere.
+    #[must_use]
+    pub fn abort_handle(&self) -> AbortHandle {
+        self.abort.clone()
+    }
+}
+
+impl<T> FusedFuture for Task<T> {
+    fn is_terminated(&self) -> bool {
+        self.receiver.is_terminated()
+    }
+}
+
+/// A handle that aborts a [`Task`] (see [`Task::abort`]).
+#[derive(Clone, Debug)]
+pub struct AbortHandle(future::AbortHandle);
+
+impl AbortHandle {
+    /// Abort the task.
+    pub fn abort(&self) {
+        self.0.abort();
+    }
+
+    /// Whether the task has been aborted.
+    #[must_use]
+    pub fn is_aborted(&self) -> bool {
+        self.0.is_aborted()
+    }
 }
 
 /// A [`Task`] with a `Send` output.
 /// See [`Task::run_send`] for usage.
 pub struct SendTask<T>(Task<T>);
 
+impl<T> SendTask<T> {
+    /// Abort the task.  See [`Task::abort`].
+    pub fn abort(&self) {
+        self.0.abort();
+    }
+
+    /// A handle that can abort the task from elsewhere.
+    #[must_use]
+    pub fn abort_handle(&self) -> AbortHandle {
+        self.0.abort_handle()
+    }
+}
+
+impl<T: Send> FusedFuture for SendTask<T> {
+    fn is_terminated(&self) -> bool {
+        self.0.is_terminated()
+    }
+}
+
 impl<T: Send> Future for SendTask<T> {
     type Output = Result<T>;
 
@@ -67,28 +160,74 @@ impl<T> Future for Task<T> {
     fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
         self.receiver
             .poll_unpin(context)
-            .map(|ready| ready.map_err(Into::into))
+            .map(|ready| ready.map_err(|_| Error::Killed).and_then(|outcome| outcome))
     }
 }
 
 impl Thread {
     /// Create a new background thread to run tasks.
+    ///
+    /// # Panics
+    ///
+    /// If the thread could not be spawned.  See [`Thread::try_new`]
+    /// for a fallible version.
     #[must_use]
     pub fn new() -> Self {
-        let (sender, mut receiver) = mpsc::unbounded::<Request>();
-        std::thread::spawn(|| {
-            use futures::{StreamExt as _, executor::LocalPool, task::LocalSpawn as _};
-            let mut executor = LocalPool::new();
-            let spawner = executor.spawner();
-            executor.run_until(async move {
-                while let Some(task) = receiver.next().await {
-                    spawner
-                        .spawn_local_obj(task())
-                        .expect("executor should exist until destroyed");
-                }
-            });
-        });
-        Self { sender }
+        Self::try_new().expect("failed to spawn thread")
+    }
+
+    /// Create a new background thread to run tasks, using the
+    /// default backend ([`backend::DefaultBackend`]).
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Spawn`] if the thread could not be spawned.
+    pub fn try_new() -> Result<Self> {
+        Self::with_backend(&backend::DefaultBackend::default())
+    }
+
+    /// Create a new background thread to run tasks, using `backend`.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Spawn`] if the backend could not spawn the thread.
+    pub fn with_backend(backend: &impl Backend) -> Result<Self> {
+        Builder::new().spawn_with(backend)
+    }
+
+    /// Configure a thread before spawning it.
+    #[must_use]
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
+    /// Whether this thread has fallen back to running its tasks on
+    /// the current thread.  On the Web this happens when the page
+    /// can't share memory with workers; native threads always share
+    /// memory, so this is always false.
+    #[must_use]
+    pub fn is_fallback(&self) -> bool {
+        false
+    }
+
+    /// Set what happens to in-flight tasks when the thread is
+    /// dropped.  Defaults to [`DropPolicy::Terminate`].
+    pub fn set_drop_policy(&mut self, policy: DropPolicy) {
+        self.policy = policy;
+    }
+
+    /// Stop accepting tasks, and wait for the thread's in-flight tasks
+    /// to complete.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Killed`] if the thread stopped before its tasks
+    /// completed.
+    pub async fn close(mut self) -> Result<()> {
+        self.drain.store(true, Ordering::Release);
+        self.sender.close_channel();
+        let done = self.done.take().expect("thread is closed only once");
+        done.await.map_err(|_| Error::Killed)
     }
 
     /// Execute a function on a thread.
@@ -100,16 +239,23 @@ impl Thread {
         context: Context,
         code: impl FnOnce(Context) -> F + Send + 'static,
     ) -> Task<F::Output> {
-        let (sender, receiver) = oneshot::channel::<F::Output>();
-        self.sender
-            .unbounded_send(Box::new(move || {
-                Box::new(async move {
-                    let _ = sender.send(code(context).await);
-                })
-                .into()
-            }))
-            .unwrap_or_else(|_| panic!("worker shouldn't die unless dropped"));
-        Task { receiver }
+        match round_trip(context) {
+            Ok(context) => self.spawn(context, |context| code(context).map(round_trip)),
+            Err(error) => Task::failed(error),
+        }
+    }
+
+    /// Execute a synchronous function on a thread.
+    ///
+    /// The function runs to completion without yielding, so this
+    /// suits CPU-bound work or code that blocks, at the cost of
+    /// holding up the thread's other tasks until it is done.
+    pub fn run_blocking<Context: Post, T: Post + 'static>(
+        &self,
+        context: Context,
+        code: impl FnOnce(Context) -> T + Send + 'static,
+    ) -> Task<T> {
+        self.run(context, |context| future::ready(code(context)))
     }
 
     /// Like [`Thread::run`], but the output can be sent through Rust
@@ -121,7 +267,48 @@ impl Thread {
         context: Context,
         code: impl FnOnce(Context) -> F + Send + 'static,
     ) -> SendTask<F::Output> {
-        SendTask(self.run(context, code))
+        SendTask(match round_trip(context) {
+            Ok(context) => self.spawn(context, |context| code(context).map(Ok)),
+            Err(error) => Task::failed(error),
+        })
+    }
+
+    fn spawn<Context: Post, T: Send + 'static, F: Future<Output = Result<T>> + 'static>(
+        &self,
+        context: Context,
+        code: impl FnOnce(Context) -> F + Send + 'static,
+    ) -> Task<T> {
+        let (sender, receiver) = oneshot::channel::<Result<T>>();
+        let (abort, registration) = future::AbortHandle::new_pair();
+        self.sender
+            .unbounded_send(Box::new(move || {
+                Box::new(async move {
+                    let outcome = future::Abortable::new(
+                        AssertUnwindSafe(async move { code(context).await }).catch_unwind(),
+                        registration,
+                    )
+                    .await;
+                    let _ = sender.send(match outcome {
+                        Ok(Ok(output)) => output,
+                        Ok(Err(payload)) => Err(Error::Panic(panic_message(&*payload))),
+                        Err(future::Aborted) => Err(Error::Aborted),
+                    });
+                })
+                .into()
+            }))
+            .unwrap_or_else(|_| panic!("worker shouldn't die unless dropped"));
+        Task {
+            receiver,
+            abort: AbortHandle(abort),
+        }
+    }
+}
+
+impl Drop for Thread {
+    fn drop(&mut self) {
+        if self.policy == DropPolicy::Detach {
+            self.drain.store(true, Ordering::Release);
+        }
     }
 }
 
@@ -131,12 +318,233 @@ impl Default for Thread {
     }
 }
 
+/// Whether [`Thread`]s run their tasks on threads of their own.  On
+/// the Web this depends on the page; native threads are always
+/// available, so this is always true.
+#[must_use]
+pub fn is_supported() -> bool {
+    true
+}
+
+/// A configuration for a new [`Thread`], for naming it in logs and
+/// debuggers, changing its stack size, or limiting how many of its
+/// tasks run at once.
+#[derive(Debug)]
+pub struct Builder {
+    thread: std::thread::Builder,
+    max_concurrency: usize,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Builder {
+    /// A configuration with the default name and stack size, and no
+    /// limit on concurrent tasks.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            thread: std::thread::Builder::new(),
+            max_concurrency: usize::MAX,
+        }
+    }
+
+    /// Name the thread.
+    #[must_use]
+    pub fn name(self, name: String) -> Self {
+        Self {
+            thread: self.thread.name(name),
+            ..self
+        }
+    }
+
+    /// Set the size of the thread's stack, in bytes.
+    #[must_use]
+    pub fn stack_size(self, size: usize) -> Self {
+        Self {
+            thread: self.thread.stack_size(size),
+            ..self
+        }
+    }
+
+    /// Limit how many tasks the thread runs at once.  Tasks submitted
+    /// beyond the limit wait, in the order they were submitted, for a
+    /// running task to complete before they start.  By default all
+    /// tasks run concurrently.
+    ///
+    /// # Panics
+    ///
+    /// If `max_concurrency` is zero.
+    #[must_use]
+    pub fn max_concurrency(self, max_concurrency: usize) -> Self {
+        assert!(max_concurrency > 0, "a thread must be able to run a task");
+        Self {
+            max_concurrency,
+            ..self
+        }
+    }
+
+    /// Spawn the thread, using the default backend
+    /// ([`backend::DefaultBackend`]).
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Spawn`] if the thread could not be spawned.
+    pub fn spawn(self) -> Result<Thread> {
+        self.spawn_with(&backend::DefaultBackend::default())
+    }
+
+    /// Spawn the thread using `backend`.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Spawn`] if the backend could not spawn the thread.
+    pub fn spawn_with(self, backend: &impl Backend) -> Result<Thread> {
+        let (sender, mut receiver) = mpsc::unbounded::<Request>();
+        let (done_sender, done) = oneshot::channel();
+        let drain = Arc::new(AtomicBool::new(false));
+        let thread_drain = drain.clone();
+        let max_concurrency = self.max_concurrency;
+        let thread = backend
+            .spawn(
+                self.thread,
+                Box::new(move || {
+                    Box::new(async move {
+                        let mut tasks = FuturesUnordered::new();
+                        // tasks waiting for others to complete
+                        let mut queued = VecDeque::new();
+                        loop {
+                            futures::select! {
+                                request = receiver.next() => match request {
+                                    Some(request) if tasks.len() < max_concurrency => {
+                                        tasks.push(request());
+                                    }
+                                    Some(request) => queued.push_back(request),
+                                    // the thread has been dropped or closed
+                                    None => break,
+                                },
+                                () = tasks.select_next_some() => {
+                                    if let Some(request) = queued.pop_front() {
+                                        tasks.push(request());
+                                    }
+                                }
+                            }
+                        }
+                        if thread_drain.load(Ordering::Acquire) {
+                            while tasks.next().await.is_some() {
+                                if let Some(request) = queued.pop_front() {
+                                    tasks.push(request());
+                                }
+                            }
+                        }
+                        drop((tasks, queued));
+                        let _ = done_sender.send(());
+                    })
+                    .into()
+                }),
+            )
+            .map_err(|error| Error::Spawn(error.into()))?;
+        Ok(Thread {
+            sender,
+            policy: DropPolicy::default(),
+            drain,
+            done: Some(done),
+            _join: thread,
+        })
+    }
+}
+
+/// Spawning a future onto a [`Thread`] runs it to completion on the
+/// thread, discarding the [`Task`].
+impl futures::task::Spawn for Thread {
+    fn spawn_obj(
+        &self,
+        future: futures::task::FutureObj<'static, ()>,
+    ) -> Result<(), futures::task::SpawnError> {
+        self.sender
+            .unbounded_send(Box::new(move || {
+                // there's nobody to report a panic to, but it mustn't
+                // take the thread's other tasks down with it
+                Box::new(AssertUnwindSafe(future).catch_unwind().map(|_| ())).into()
+            }))
+            .map_err(|_| futures::task::SpawnError::shutdown())
+    }
+}
+
+/// Round-trip a value through serialization, as the Web backend does
+/// when sending it to another thread, so that values that can't be
+/// sent fail natively too.
+#[cfg(feature = "serde-check")]
+fn round_trip<T: RoundTrip>(value: T) -> Result<T> {
+    // While not syntactically consumed, the value is replaced by its
+    // copy, as it would be on the Web.
+    #![allow(clippy::needless_pass_by_value)]
+
+    serde_json::to_string(&value)
+        .and_then(|json| serde_json::from_str(&json))
+        .map_err(|error| Error::Serialization(error.to_string()))
+}
+
+/// Round-trip a value through serialization.  Without the
+/// `serde-check` feature, this does nothing.
+#[cfg(not(feature = "serde-check"))]
+#[expect(clippy::unnecessary_wraps)]
+fn round_trip<T>(value: T) -> Result<T> {
+    Ok(value)
+}
+
+/// The message a panic was raised with.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_owned()
+    }
+}
+
 /// Types that can be sent to another thread.  In this shim, this
-/// trait is just an alias for `Send + 'static`, but in `web-thread`
-/// some types can be sent only by performing an explicit transfer
-/// operation.
-pub trait Post: Send + 'static {}
-impl<T: Send + 'static> Post for T {}
+/// trait is implemented for every `Send + 'static` type, but in
+/// `web-thread` some types can be sent only by performing an explicit
+/// transfer operation.
+///
+/// The trait has `web-thread`'s `transferables` method, so that code
+/// calling it compiles for both crates.  Native threads share memory,
+/// so nothing is ever transferred.
+pub trait Post: Send + RoundTrip + 'static {
+    /// Get a list of the objects that must be transferred when
+    /// sending the value.
+    ///
+    /// The default implementation returns an empty array, and the shim
+    /// never calls this.
+    fn transferables(&self) -> js_sys::Array {
+        js_sys::Array::new()
+    }
+}
+
+/// Types that can be round-tripped through serialization: with the
+/// `serde-check` feature, types that are `Serialize` and
+/// `Deserialize`, as `Post` types must be on the Web.  Without the
+/// feature, every type.
+#[cfg(feature = "serde-check")]
+pub trait RoundTrip: serde::Serialize + serde::de::DeserializeOwned {}
+#[cfg(feature = "serde-check")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> RoundTrip for T {}
+
+/// Types that can be round-tripped through serialization: with the
+/// `serde-check` feature, types that are `Serialize` and
+/// `Deserialize`, as `Post` types must be on the Web.  Without the
+/// feature, every type.
+#[cfg(not(feature = "serde-check"))]
+pub trait RoundTrip {}
+#[cfg(not(feature = "serde-check"))]
+impl<T> RoundTrip for T {}
+
+impl<T: Send + RoundTrip + 'static> Post for T {}
 
 #[test]
 fn basic_functionality() {
@@ -144,10 +552,366 @@ fn basic_functionality() {
         8u8,
         futures::executor::LocalPool::new()
             .run_until(
-                Thread::new()
+                Thread::try_new()
                     .unwrap()
                     .run(3u8, |three| async move { three + 5 })
             )
             .unwrap(),
     );
 }
+
+#[test]
+fn threads_can_use_custom_backends() {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    };
+
+    struct Recording(Arc<AtomicBool>);
+
+    impl Backend for Recording {
+        fn spawn(
+            &self,
+            thread: std::thread::Builder,
+            main: backend::ThreadMain,
+        ) -> std::io::Result<backend::JoinHandle> {
+            self.0.store(true, Ordering::Relaxed);
+            backend::Std.spawn(thread, main)
+        }
+    }
+
+    let used = Arc::new(AtomicBool::new(false));
+    let thread = Thread::with_backend(&Recording(used.clone())).unwrap();
+    assert_eq!(
+        futures::executor::block_on(thread.run(3u8, |three| async move { three + 5 })).unwrap(),
+        8,
+    );
+    assert!(used.load(Ordering::Relaxed));
+}
+
+#[test]
+fn threads_can_be_named() {
+    let thread = Thread::builder()
+        .name("worker".into())
+        .stack_size(1 << 20)
+        .spawn_with(&backend::Std)
+        .unwrap();
+    let name = futures::executor::block_on(thread.run_send((), |()| async {
+        std::thread::current().name().map(str::to_owned)
+    }))
+    .unwrap();
+    assert_eq!(name.as_deref(), Some("worker"));
+}
+
+#[test]
+fn panics_leave_the_thread_usable() {
+    let thread = Thread::new();
+    futures::executor::block_on(async {
+        let error = thread
+            .run((), |()| async { panic!("oops") as u8 })
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::Panic(message) if message == "oops"));
+        assert_eq!(
+            thread.run(2u8, |two| async move { two * 2 }).await.unwrap(),
+            4
+        );
+    });
+}
+
+#[test]
+fn tasks_can_be_aborted() {
+    let thread = Thread::new();
+    futures::executor::block_on(async {
+        let mut task = thread.run((), |()| future::pending::<u8>());
+        let handle = task.abort_handle();
+        assert!(!task.is_terminated());
+        handle.abort();
+        assert!(handle.is_aborted());
+        assert!(matches!((&mut task).await, Err(Error::Aborted)));
+        assert!(task.is_terminated());
+    });
+}
+
+#[test]
+fn tasks_can_be_cancelled() {
+    let token = CancellationToken::new();
+    let thread = Thread::new();
+    let task = thread.run_send((), {
+        // tokens live in shared memory, so are moved rather than posted
+        let token = token.clone();
+        move |()| async move {
+            token.cancelled().await;
+            token.is_cancelled()
+        }
+    });
+    token.cancel();
+    assert!(futures::executor::block_on(task).unwrap());
+}
+
+#[test]
+fn blocking_functions_run_on_the_thread() {
+    let thread = Thread::new();
+    let task = thread.run_blocking(3u64, |n| (1..=n).product::<u64>());
+    assert_eq!(futures::executor::block_on(task).unwrap(), 6);
+}
+
+#[test]
+fn drop_policies_decide_the_fate_of_tasks() {
+    futures::executor::block_on(async {
+        let (sender, receiver) = oneshot::channel::<()>();
+        let thread = Thread::new();
+        let task = thread.run_send((), |()| receiver);
+        drop(thread);
+        assert!(matches!(task.await, Err(Error::Killed)));
+        drop(sender);
+
+        let (sender, receiver) = oneshot::channel();
+        let mut thread = Thread::new();
+        thread.set_drop_policy(DropPolicy::Detach);
+        let task = thread.run_send((), |()| receiver);
+        drop(thread);
+        sender.send(5u8).unwrap();
+        assert_eq!(task.await.unwrap().unwrap(), 5);
+
+        let (sender, receiver) = oneshot::channel();
+        let thread = Thread::new();
+        let task = thread.run_send((), |()| receiver);
+        let closed = thread.close();
+        sender.send(8u8).unwrap();
+        closed.await.unwrap();
+        assert_eq!(task.await.unwrap().unwrap(), 8);
+    });
+}
+
+#[test]
+fn send_types_are_post() {
+    #[cfg_attr(feature = "serde-check", derive(serde::Serialize, serde::Deserialize))]
+    struct Context(u8);
+
+    assert_eq!(Context(3).transferables().length(), 0);
+    let thread = Thread::new();
+    assert_eq!(
+        futures::executor::block_on(thread.run(Context(3), |context| async move { context.0 }))
+            .unwrap(),
+        3,
+    );
+}
+
+#[cfg(feature = "serde-check")]
+#[test]
+fn unserializable_values_fail_to_send() {
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Ratio(f64);
+
+    let thread = Thread::new();
+    futures::executor::block_on(async {
+        // JSON represents NaN as `null`, which isn't a valid `f64`
+        let sent = thread.run(Ratio(f64::NAN), |_| async {}).await;
+        assert!(matches!(sent, Err(Error::Serialization(_))));
+        let returned = thread.run((), |()| async { Ratio(f64::NAN) }).await;
+        assert!(matches!(returned, Err(Error::Serialization(_))));
+        assert_eq!(thread.run(1u8, |one| async move { one }).await.unwrap(), 1);
+    });
+}
+
+#[test]
+fn concurrency_can_be_limited() {
+    use std::sync::Mutex;
+
+    let thread = Thread::builder().max_concurrency(1).spawn().unwrap();
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let (sender, receiver) = oneshot::channel::<()>();
+    let first = thread.run_send((), {
+        let order = order.clone();
+        move |()| async move {
+            let _ = receiver.await;
+            order.lock().unwrap().push(1);
+        }
+    });
+    let second = thread.run_send((), {
+        let order = order.clone();
+        move |()| async move { order.lock().unwrap().push(2) }
+    });
+    // the second task can't overtake the first
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    assert!(order.lock().unwrap().is_empty());
+    sender.send(()).unwrap();
+    futures::executor::block_on(async {
+        first.await.unwrap();
+        second.await.unwrap();
+    });
+    assert_eq!(*order.lock().unwrap(), [1, 2]);
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn time_can_be_controlled() {
+    use std::time::Duration;
+
+    let backend = backend::Manual::new();
+    let thread = Thread::with_backend(&backend).unwrap();
+    let clock = backend.clock();
+    let mut task = thread.run((), move |()| async move {
+        clock.sleep(Duration::from_secs(10)).await;
+        clock.now().as_secs()
+    });
+    backend.run_until_stalled();
+    assert!((&mut task).now_or_never().is_none());
+    backend.advance(Duration::from_secs(5));
+    assert!((&mut task).now_or_never().is_none());
+    backend.advance(Duration::from_secs(5));
+    assert_eq!(backend.block_on(task).unwrap(), 10);
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn timers_follow_the_manual_clock() {
+    use std::time::Duration;
+
+    let backend = backend::Manual::new();
+    let thread = Thread::with_backend(&backend).unwrap();
+    let mut task = thread.run_send((), |()| async {
+        time::sleep(Duration::from_secs(10)).await;
+        let ticks = time::interval(Duration::from_secs(1)).take(3).count().await;
+        let late = time::timeout(future::pending::<()>(), Duration::from_secs(5)).await;
+        (ticks, matches!(late, Err(Error::Timeout)))
+    });
+    backend.run_until_stalled();
+    backend.advance(Duration::from_secs(9));
+    assert!((&mut task).now_or_never().is_none());
+    backend.advance(Duration::from_secs(1));
+    for _ in 0..3 {
+        assert!((&mut task).now_or_never().is_none());
+        backend.advance(Duration::from_secs(1));
+    }
+    backend.advance(Duration::from_secs(4));
+    assert!((&mut task).now_or_never().is_none());
+    backend.advance(Duration::from_secs(1));
+    assert_eq!(backend.block_on(task).unwrap(), (3, true));
+}
+
+#[test]
+fn shared_threads_are_multiplexed() {
+    let threads = (0..64)
+        .map(|_| Thread::with_backend(&backend::Shared).unwrap())
+        .collect::<Vec<_>>();
+    let sum = futures::executor::block_on(future::join_all(
+        threads
+            .iter()
+            .zip(0u64..)
+            .map(|(thread, n)| thread.run(n, |n| async move { n })),
+    ))
+    .into_iter()
+    .map(Result::unwrap)
+    .sum::<u64>();
+    assert_eq!(sum, (0..64).sum::<u64>());
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn tasks_can_use_tokio() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let thread = runtime
+        .block_on(async { Thread::with_backend(&backend::Tokio) })
+        .unwrap();
+    let address = runtime
+        .block_on(thread.run_send((), |()| async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap()
+        }))
+        .unwrap();
+    assert!(address.ip().is_loopback());
+}
+
+#[test]
+fn watchers_see_the_latest_value() {
+    let (sender, mut receiver) = watch(0_u32);
+    let thread = Thread::new();
+    let watching = thread.run_send((), move |()| async move {
+        while *receiver.borrow_and_update().await < 3 {
+            receiver.changed().await.unwrap();
+        }
+        *receiver.borrow().await
+    });
+    for value in 1..=3 {
+        futures::executor::block_on(sender.send(value));
+    }
+    assert_eq!(futures::executor::block_on(watching).unwrap(), 3);
+}
+
+#[test]
+fn mutexes_exclude_other_threads() {
+    let counter = Arc::new(sync::Mutex::new(0_u32));
+    let threads: Vec<_> = (0..4).map(|_| Thread::new()).collect();
+    let tasks = threads.iter().map(|thread| {
+        let counter = counter.clone();
+        thread.run_send((), move |()| async move {
+            for _ in 0..1000 {
+                let mut count = counter.lock().await;
+                let seen = *count;
+                std::thread::yield_now();
+                *count = seen + 1;
+            }
+        })
+    });
+    futures::executor::block_on(future::try_join_all(tasks)).unwrap();
+    assert_eq!(*futures::executor::block_on(counter.lock()), 4000);
+}
+
+#[test]
+fn barriers_and_once_cells_synchronize_threads() {
+    let barrier = Arc::new(sync::Barrier::new(3));
+    let cell = Arc::new(sync::OnceCell::new());
+    let initialized = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let threads: Vec<_> = (0..3).map(|_| Thread::new()).collect();
+    let tasks = threads.iter().map(|thread| {
+        let (barrier, cell, initialized) = (barrier.clone(), cell.clone(), initialized.clone());
+        thread.run_send((), move |()| async move {
+            let value = *cell
+                .get_or_init(|| async {
+                    initialized.fetch_add(1, Ordering::Relaxed);
+                    7
+                })
+                .await;
+            (barrier.wait().await.is_leader(), value)
+        })
+    });
+    let outcomes = futures::executor::block_on(future::try_join_all(tasks)).unwrap();
+    assert_eq!(outcomes.iter().filter(|(leader, _)| *leader).count(), 1);
+    assert!(outcomes.iter().all(|(_, value)| *value == 7));
+    assert_eq!(initialized.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn timers_fire_on_threads() {
+    use std::time::Duration;
+
+    let thread = Thread::new();
+    let task = thread.run_send((), |()| async {
+        time::sleep(Duration::from_millis(10)).await;
+        let ticks = time::interval(Duration::from_millis(10))
+            .take(3)
+            .count()
+            .await;
+        let early = time::timeout(async { 7 }, Duration::from_secs(10)).await;
+        let late = time::timeout(future::pending::<()>(), Duration::from_millis(10)).await;
+        (ticks, early.unwrap(), matches!(late, Err(Error::Timeout)))
+    });
+    assert_eq!(futures::executor::block_on(task).unwrap(), (3, 7, true));
+}
+
+#[test]
+fn intervals_skip_ticks_missed() {
+    use std::time::{Duration, Instant};
+
+    let start = Instant::now();
+    let mut ticks = time::interval(Duration::from_millis(40));
+    futures::executor::block_on(ticks.next());
+    // busy past the ticks at 80ms and 120ms
+    std::thread::sleep(Duration::from_millis(130));
+    // the late tick at 80ms, then the next one on the grid, at 200ms
+    futures::executor::block_on(ticks.next());
+    futures::executor::block_on(ticks.next());
+    assert!(start.elapsed() >= Duration::from_millis(190));
+}
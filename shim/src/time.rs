@@ -0,0 +1,141 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Timers, as in `web-thread`, running on a helper thread shared by
+//! the whole process (see [`futures_timer`]), so they work under any
+//! [backend](crate::backend).  Timers created by tasks run by a
+//! [`Manual`](crate::backend::Manual) backend follow its clock
+//! instead, so tests can move time on deterministically.
+
+use std::{
+    pin::Pin,
+    sync::OnceLock,
+    task::{Context, Poll, ready},
+    time::Duration,
+};
+
+use futures::{FutureExt as _, future};
+use futures_timer::Delay;
+// `std`'s clock panics on `wasm32-unknown-unknown`
+use web_time::Instant;
+
+use super::{Error, Result};
+
+/// Where timers get the time from.
+#[derive(Clone)]
+enum Clock {
+    /// The real clock.
+    Real,
+    /// The clock of the [`Manual`](crate::backend::Manual) backend
+    /// running the current task.
+    #[cfg(feature = "test-util")]
+    Manual(crate::backend::Clock),
+}
+
+impl Clock {
+    fn current() -> Self {
+        #[cfg(feature = "test-util")]
+        if let Some(clock) = crate::backend::Manual::current_clock() {
+            return Self::Manual(clock);
+        }
+        Self::Real
+    }
+
+    /// The time since an arbitrary fixed point.
+    fn now(&self) -> Duration {
+        match self {
+            Self::Real => {
+                static EPOCH: OnceLock<Instant> = OnceLock::new();
+                EPOCH.get_or_init(Instant::now).elapsed()
+            }
+            #[cfg(feature = "test-util")]
+            Self::Manual(clock) => clock.now(),
+        }
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        match self {
+            Self::Real => Sleep(Delay::new(duration).boxed()),
+            #[cfg(feature = "test-util")]
+            Self::Manual(clock) => Sleep(clock.sleep(duration).boxed()),
+        }
+    }
+}
+
+/// A future that completes once a timer has fired.
+#[must_use = "futures do nothing unless polled"]
+pub struct Sleep(future::BoxFuture<'static, ()>);
+
+/// Wait for `duration`.
+pub fn sleep(duration: Duration) -> Sleep {
+    Clock::current().sleep(duration)
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<()> {
+        self.0.poll_unpin(context)
+    }
+}
+
+/// A [`Stream`](futures::Stream) that yields every `period`.
+///
+/// Ticks are kept to a grid of `period`s from the time the interval
+/// was created, rather than scheduled from the previous tick, so they
+/// don't drift.  Ticks missed while the thread was busy are skipped
+/// rather than bunched up, and the next tick is back on the grid.
+#[must_use = "streams do nothing unless polled"]
+pub struct Interval {
+    clock: Clock,
+    period: Duration,
+    deadline: Duration,
+    sleep: Sleep,
+}
+
+/// Yield every `period`, starting a `period` from now.
+///
+/// # Panics
+///
+/// If `period` is zero.
+pub fn interval(period: Duration) -> Interval {
+    assert!(!period.is_zero(), "an interval must have a period");
+    let clock = Clock::current();
+    Interval {
+        deadline: clock.now() + period,
+        sleep: clock.sleep(period),
+        clock,
+        period,
+    }
+}
+
+impl futures::Stream for Interval {
+    type Item = ();
+
+    fn poll_next(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Option<()>> {
+        ready!(self.sleep.poll_unpin(context));
+        let period = self.period;
+        let now = self.clock.now();
+        self.deadline += period;
+        if self.deadline <= now {
+            // skip the ticks missed, to the next one on the grid
+            let missed = (now - self.deadline).as_nanos() / period.as_nanos() + 1;
+            self.deadline += period * u32::try_from(missed).unwrap_or(u32::MAX);
+        }
+        self.sleep = self.clock.sleep(self.deadline - now);
+        Poll::Ready(Some(()))
+    }
+}
+
+/// Wait for `future` to complete, for at most `duration`.
+///
+/// # Errors
+///
+/// [`Error::Timeout`] if `future` didn't complete in time, in which
+/// case it is dropped.
+pub async fn timeout<F: Future>(future: F, duration: Duration) -> Result<F::Output> {
+    match future::select(std::pin::pin!(future), sleep(duration)).await {
+        future::Either::Left((output, _)) => Ok(output),
+        future::Either::Right(((), _)) => Err(Error::Timeout),
+    }
+}
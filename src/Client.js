@@ -8,19 +8,135 @@
 // `wasm-bindgen` doesn't qualify imports
 // (https://github.com/wasm-bindgen/wasm-bindgen/issues/2798).
 
+// Trusted Types policies by name, since a policy can only be created
+// once.
+const policies = new Map();
+
+// Wrap `url` for a worker on pages that require Trusted Types.  The
+// policy passes URLs through unchanged, which is safe since it is only
+// given URLs of our own and never leaves this module.
+function scriptUrl(policyName, url) {
+  if (policyName === undefined || self.trustedTypes === undefined) return url;
+  let policy = policies.get(policyName);
+  if (policy === undefined) {
+    policy = self.trustedTypes.createPolicy(policyName, { createScriptURL: url => url });
+    policies.set(policyName, policy);
+  }
+  return policy.createScriptURL(String(url));
+}
+
+// An error for a worker that couldn't be created, which the Rust side
+// reports as `Error::Spawn`.
+function spawnError(message) {
+  const error = new Error(message);
+  error.name = 'web_thread$SpawnError';
+  return error;
+}
+
+// The URL `web-thread:wasm-shim` is mapped to by the page's import
+// map, which workers can't see, or `undefined` if it isn't mapped
+// (as when a bundler resolves it instead).
+function resolveShim() {
+  try {
+    return import.meta.resolve('web-thread:wasm-shim');
+  } catch {
+    return undefined;
+  }
+}
+
 export class web_thread$Client {
-  constructor(module, memory) {
+  constructor(module, memory, type, moduleUrl, source, policyName, priority, idleTimeout, mainPort) {
     this.nextId = 0;
+    this.priority = priority;
+    this.idleTimeout = idleTimeout;
+    // tasks waiting for the current thread to be idle
+    this.queue = [];
+    // tasks submitted in the current tick, sent together at its end
+    this.batch = [];
+    this.batchTransfer = [];
     this.promises = new Map();
-    this.worker = new Worker(
-      new URL('./worker.js', import.meta.url),
-      { type: 'module' },
-    );
-    this.ready = new Promise(resolve => {
+    moduleUrl ??= resolveShim();
+    this.ready = new Promise((resolve, reject) => {
       this.setReady = resolve;
+      this.setFailed = reject;
     });
-    this.worker.onmessage = event => this.handleResponse(event);
-    this.worker.postMessage({ type: 'init', module, memory });
+    // failures are reported to tasks, not as unhandled rejections
+    this.ready.catch(() => {});
+    this.stopWatching = () => {};
+    const nodeWorkers = typeof Worker === 'undefined'
+      ? globalThis.process?.getBuiltinModule?.('node:worker_threads')
+      : undefined;
+    if (nodeWorkers !== undefined) {
+      // Node.js, whose workers are always modules, and have neither
+      // content security policies nor DOM events
+      this.worker = new nodeWorkers.Worker(new URL('./worker.js', import.meta.url));
+      this.worker.on('error', error => this.setFailed(spawnError(error.message)));
+      this.worker.on('message', data => this.handleResponse({ data }));
+    } else {
+      this.createWorker(type, source, policyName);
+      if (this.revokeUrl !== undefined && moduleUrl !== undefined) {
+        // relative URLs can't be resolved from a `Blob` URL, so
+        // resolve them here, next to `worker.js`
+        moduleUrl = new URL(moduleUrl, import.meta.url).href;
+      }
+      // a worker blocked by the content security policy only reports
+      // an anonymous error, so find out which directive blocked it
+      const onViolation = event => {
+        if (event.effectiveDirective === 'worker-src')
+          this.violation = event.violatedDirective;
+      };
+      self.addEventListener('securitypolicyviolation', onViolation);
+      this.stopWatching = () => self.removeEventListener('securitypolicyviolation', onViolation);
+      this.worker.onerror = event => {
+        // Deno takes unhandled worker errors down with the process
+        event.preventDefault();
+        // Deno explains missing permissions, such as read access to
+        // the worker's script, in the message; browsers give none
+        let reason = event.message ? `: ${event.message}` : '';
+        if (self.crossOriginIsolated)
+          reason += ' (under cross-origin isolation, cross-origin scripts must be served with CORS headers)';
+        // the violation may be reported after the error
+        setTimeout(() => {
+          this.stopWatching();
+          this.setFailed(spawnError(this.violation === undefined
+            ? `the worker failed to load${reason}`
+            : `the worker was blocked by the content security policy directive \`${this.violation}\``));
+        });
+      };
+      this.worker.onmessage = event => this.handleResponse(event);
+    }
+    this.worker.postMessage({ type: 'init', module, memory, moduleUrl, mainPort }, [mainPort]);
+  }
+
+  createWorker(type, source, policyName) {
+    // Deno supports only module workers
+    if (globalThis.Deno !== undefined) type = 'module';
+    try {
+      if (source !== undefined) {
+        const url = URL.createObjectURL(new Blob([source], { type: 'text/javascript' }));
+        this.worker = new Worker(scriptUrl(policyName, url), { type });
+        this.revokeUrl = () => URL.revokeObjectURL(url);
+      } else if (policyName !== undefined) {
+        this.worker = new Worker(
+          scriptUrl(policyName, new URL('./worker.js', import.meta.url)),
+          { type },
+        );
+      } else {
+        // the options must be literal for bundlers to recognize the worker
+        this.worker = type === 'classic'
+          ? new Worker(new URL('./worker.js', import.meta.url))
+          : new Worker(
+            new URL('./worker.js', import.meta.url),
+            { type: 'module' },
+          );
+      }
+    } catch (error) {
+      if (error instanceof TypeError && policyName === undefined && self.trustedTypes !== undefined)
+        throw spawnError(
+          `${error.message} (the page requires Trusted Types, so a policy must be named for workers)`,
+        );
+      throw error;
+    }
   }
 
   async run(code, context, transfer) {
@@ -28,26 +144,73 @@ export class web_thread$Client {
     return await new Promise((resolve, reject) => {
       const id = this.nextId++;
       if (id === Number.MAX_SAFE_INTEGER) this.nextId = 0;
-      this.worker.postMessage({ type: 'run', id, code, context }, transfer);
       this.promises.set(id, { resolve, reject });
+      this.batch.push({ type: 'run', id, code, context });
+      this.batchTransfer.push(...transfer);
+      if (this.batch.length === 1) queueMicrotask(() => this.flushBatch());
     });
   }
 
+  flushBatch() {
+    if (this.batch.length === 0) return;
+    const runs = this.batch.splice(0);
+    const transfer = this.batchTransfer.splice(0);
+    this.dispatch(runs.length === 1 ? runs[0] : { type: 'batch', runs }, transfer);
+  }
+
+  dispatch(message, transfer) {
+    if (this.idleTimeout === undefined || globalThis.requestIdleCallback === undefined) {
+      this.worker.postMessage(message, transfer);
+      return;
+    }
+    this.queue.push({ message, transfer });
+    if (this.queue.length === 1) this.flushWhenIdle();
+  }
+
+  flushWhenIdle() {
+    requestIdleCallback(deadline => {
+      // post at least one task, so that timing out makes progress
+      do {
+        const { message, transfer } = this.queue.shift();
+        this.worker.postMessage(message, transfer);
+      } while (this.queue.length > 0 && deadline.timeRemaining() > 0);
+      if (this.queue.length > 0) this.flushWhenIdle();
+    }, { timeout: this.idleTimeout });
+  }
+
   destroy() {
+    // tasks submitted before the thread was destroyed start, as if
+    // they had been sent straight away
+    this.flushBatch();
+    // tasks that were never submitted fail as if they had started
+    for (const { message } of this.queue.splice(0)) {
+      for (const { id } of message.type === 'batch' ? message.runs : [message]) {
+        this.promises.get(id).reject(new Error('thread destroyed during execution'));
+        this.promises.delete(id);
+      }
+    }
     this.worker.postMessage({ type: 'destroy' });
   }
 
   handleResponse(event) {
-    if (event.data.type === 'ready')
+    if (event.data.type === 'ready') {
+      // the worker has loaded, so its script is no longer needed
+      this.revokeUrl?.();
+      this.stopWatching();
+      if (this.worker.onerror !== undefined) this.worker.onerror = null;
       this.setReady(null);
+    }
     else if (event.data.type === 'response') {
       let id = event.data.id;
       let { resolve, reject } = this.promises.get(id);
       this.promises.delete(id);
-      if ('result' in event.data)
-        resolve(event.data.result);
-      else if ('error' in event.data)
-        reject(event.data.error);
+      const settle = 'result' in event.data
+        ? () => resolve(event.data.result)
+        : () => reject(event.data.error);
+      if (this.priority !== undefined && globalThis.scheduler?.postTask !== undefined)
+        globalThis.scheduler.postTask(settle, { priority: this.priority });
+      else
+        settle();
     } else {
       console.error('[web-thread] malformed response', event.data);
     }
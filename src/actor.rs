@@ -0,0 +1,203 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Actors: state kept on a thread of its own, changed only by handling
+//! the messages sent to it, one at a time, for long-lived background
+//! services such as search indices or sync engines.
+//!
+//! ```no_run
+//! # async fn f() -> web_thread::Result<()> {
+//! let counter = web_thread::actor::spawn_actor(
+//!     || 0,
+//!     async |count: &mut u32, increment: u32| {
+//!         *count += increment;
+//!         *count
+//!     },
+//! )?;
+//! let mut addr = counter.addr();
+//! addr.tell(1).await?;
+//! assert_eq!(addr.ask(2).await?, 3);
+//! counter.stop().await?;
+//! # Ok(()) }
+//! ```
+
+use futures::{SinkExt as _, StreamExt as _};
+
+use super::{AsJs, JsValue, Post, Result, Task, Thread, channel, js_sys, oneshot, post};
+
+/// How many messages each [`Addr`] can have waiting before sending
+/// waits.
+const MAILBOX_CAPACITY: usize = 32;
+
+/// A message to an actor.
+enum Envelope<M, R> {
+    Tell(M),
+    Ask(M, oneshot::Sender<R>),
+    Stop,
+}
+
+impl<M: Post + 'static, R: Post + 'static> AsJs for Envelope<M, R> {
+    fn to_js(&self) -> Result<JsValue, JsValue> {
+        let object = js_sys::Object::new();
+        let (message, reply) = match self {
+            Self::Tell(message) => (Some(message), None),
+            Self::Ask(message, reply) => (Some(message), Some(reply)),
+            Self::Stop => (None, None),
+        };
+        if let Some(message) = message {
+            js_sys::Reflect::set(&object, &"message".into(), &post::to_js(message)?)?;
+        }
+        if let Some(reply) = reply {
+            js_sys::Reflect::set(&object, &"reply".into(), &reply.to_js()?)?;
+        }
+        Ok(object.into())
+    }
+
+    fn from_js(value: JsValue) -> Result<Self, JsValue> {
+        let get = |key: &str| {
+            js_sys::Reflect::get(&value, &key.into())
+                .map(|value| Some(value).filter(|value| !value.is_undefined()))
+        };
+        Ok(match (get("message")?, get("reply")?) {
+            (Some(message), None) => Self::Tell(post::from_js(message)?),
+            (Some(message), Some(reply)) => {
+                Self::Ask(post::from_js(message)?, oneshot::Sender::from_js(reply)?)
+            }
+            (None, _) => Self::Stop,
+        })
+    }
+}
+
+impl<M: Post + 'static, R: Post + 'static> Post for Envelope<M, R> {
+    fn transferables(&self) -> js_sys::Array {
+        match self {
+            Self::Tell(message) => message.transferables(),
+            Self::Ask(message, reply) => message.transferables().concat(&reply.transferables()),
+            Self::Stop => js_sys::Array::new(),
+        }
+    }
+}
+
+/// The address of an actor, for sending it messages of type `M` and
+/// receiving replies of type `R`.
+///
+/// Addresses are [`Post`], so they can be handed to tasks on other
+/// threads.  Messages from one address are handled in the order they
+/// were sent.
+pub struct Addr<M, R = ()>(channel::Sender<Envelope<M, R>>);
+
+impl<M, R> Clone for Addr<M, R> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<M: Post + 'static, R: Post + 'static> Addr<M, R> {
+    /// Send the actor `message`, without waiting for it to be
+    /// handled.  This waits if the actor's mailbox is full.
+    ///
+    /// # Errors
+    ///
+    /// If the message could not be sent.
+    pub async fn tell(&mut self, message: M) -> Result<()> {
+        self.0.send(Envelope::Tell(message)).await
+    }
+
+    /// Send the actor `message`, and wait for its reply.
+    ///
+    /// # Errors
+    ///
+    /// If the message could not be sent, or [`Error::Killed`](crate::Error::Killed)
+    /// if the actor stopped before replying.
+    pub async fn ask(&mut self, message: M) -> Result<R> {
+        let (reply, replied) = oneshot::oneshot()?;
+        self.0.send(Envelope::Ask(message, reply)).await?;
+        replied.await
+    }
+}
+
+impl<M: Post + 'static, R: Post + 'static> AsJs for Addr<M, R> {
+    fn to_js(&self) -> Result<JsValue, JsValue> {
+        self.0.to_js()
+    }
+
+    fn from_js(value: JsValue) -> Result<Self, JsValue> {
+        channel::Sender::from_js(value).map(Self)
+    }
+}
+
+impl<M: Post + 'static, R: Post + 'static> Post for Addr<M, R> {
+    fn transferables(&self) -> js_sys::Array {
+        self.0.transferables()
+    }
+}
+
+/// An actor running on a thread of its own, which is stopped when
+/// this is dropped.  See [`spawn_actor`].
+pub struct Actor<M, R = ()> {
+    addr: Addr<M, R>,
+    task: Task<()>,
+    // dropped last, destroying the worker
+    _thread: Thread,
+}
+
+impl<M: Post + 'static, R: Post + 'static> Actor<M, R> {
+    /// The actor's address.
+    #[must_use]
+    pub fn addr(&self) -> Addr<M, R> {
+        self.addr.clone()
+    }
+
+    /// Stop the actor once it has handled the messages sent before,
+    /// from this actor's address.
+    ///
+    /// # Errors
+    ///
+    /// If the actor couldn't be told to stop, or its loop failed, for
+    /// example because a handler panicked.
+    pub async fn stop(mut self) -> Result<()> {
+        self.addr.0.send(Envelope::Stop).await?;
+        (&mut self.task).await
+    }
+}
+
+/// Spawn an actor on a new thread, whose state is created by `init`
+/// and which handles each message with `handler`, replying with its
+/// output to messages sent with [`Addr::ask`].
+///
+/// The actor runs until it is stopped with [`Actor::stop`], the
+/// [`Actor`] is dropped, or every [`Addr`] of it has been dropped.
+///
+/// # Errors
+///
+/// If the thread or the actor's mailbox could not be created.
+pub fn spawn_actor<S, M: Post + 'static, R: Post + 'static>(
+    init: impl FnOnce() -> S + Send + 'static,
+    mut handler: impl AsyncFnMut(&mut S, M) -> R + Send + 'static,
+) -> Result<Actor<M, R>> {
+    let thread = Thread::try_new()?;
+    let (sender, receiver) = channel::channel::<Envelope<M, R>>(MAILBOX_CAPACITY)?;
+    let task = thread.run(receiver, move |mut receiver| async move {
+        let mut state = init();
+        while let Some(envelope) = receiver.next().await {
+            match envelope {
+                Ok(Envelope::Tell(message)) => {
+                    handler(&mut state, message).await;
+                }
+                Ok(Envelope::Ask(message, reply)) => {
+                    // the asker may have stopped waiting
+                    let _ = reply.send(handler(&mut state, message).await);
+                }
+                Ok(Envelope::Stop) => break,
+                Err(error) => web_sys::console::error_1(
+                    &format!("[web-thread] actor received a malformed message: {error}").into(),
+                ),
+            }
+        }
+    });
+    Ok(Actor {
+        addr: Addr(sender),
+        task,
+        _thread: thread,
+    })
+}
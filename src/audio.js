@@ -0,0 +1,8 @@
+// @ts-check
+
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+export function web_thread$processorUrl() {
+  return new URL('./processor.js', import.meta.url).href;
+}
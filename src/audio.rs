@@ -0,0 +1,204 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Running Rust code in an
+//! [`AudioWorkletProcessor`](https://developer.mozilla.org/en-US/docs/Web/API/AudioWorkletProcessor),
+//! for real-time audio that can't wait on a worker.
+//!
+//! Like workers, the audio worklet is given the Wasm module and
+//! shared memory, so it requires the same [cross-origin
+//! isolation](crate#requirements).  Bundlers generally don't follow
+//! `audioWorklet.addModule`, so `processor.js` must be bundled as an
+//! entry point of its own with `web-thread:wasm-shim` resolved, as
+//! for `worker.js`.  Audio worklets lack `TextDecoder` and
+//! `TextEncoder`, so a processor that passes strings between Rust and
+//! JavaScript needs them polyfilled.
+
+use wasm_bindgen_futures::JsFuture;
+
+use super::{AsJs as _, JsValue, Post, PostExt as _, Result, error, js_sys, pointer, wasm_bindgen};
+
+#[wasm_bindgen(module = "/src/audio.js")]
+extern "C" {
+    #[wasm_bindgen(js_name = "web_thread$processorUrl")]
+    fn processor_url() -> String;
+}
+
+#[wasm_bindgen(module = "/src/processor.js")]
+extern "C" {
+    // This is here just to ensure `/src/processor.js` makes it into
+    // the bundle produced by `wasm-bindgen`.
+    fn _non_existent_function();
+}
+
+/// Rust code that processes audio in an audio worklet.
+pub trait Processor: Send + 'static {
+    /// The messages that can be sent to the processor with
+    /// [`Node::post`], for example to update its parameters.
+    type Message: Post;
+
+    /// Process a block of audio, as
+    /// [`AudioWorkletProcessor.process`](https://developer.mozilla.org/en-US/docs/Web/API/AudioWorkletProcessor/process).
+    /// Returning `false` lets the browser destroy the processor once
+    /// it has no more inputs, after which it is dropped.
+    fn process(
+        &mut self,
+        inputs: &js_sys::Array,
+        outputs: &js_sys::Array,
+        parameters: &js_sys::Object,
+    ) -> bool;
+
+    /// Handle a message sent with [`Node::post`].
+    ///
+    /// The default implementation ignores the message.
+    fn receive(&mut self, message: Self::Message) {
+        let _ = message;
+    }
+}
+
+/// A [`Processor`] with its message type erased, so it can be called
+/// from JavaScript.
+trait ErasedProcessor: Send {
+    fn process(
+        &mut self,
+        inputs: &js_sys::Array,
+        outputs: &js_sys::Array,
+        parameters: &js_sys::Object,
+    ) -> bool;
+
+    fn receive(&mut self, message: JsValue) -> Result<(), JsValue>;
+}
+
+impl<P: Processor> ErasedProcessor for P {
+    fn process(
+        &mut self,
+        inputs: &js_sys::Array,
+        outputs: &js_sys::Array,
+        parameters: &js_sys::Object,
+    ) -> bool {
+        Processor::process(self, inputs, outputs, parameters)
+    }
+
+    fn receive(&mut self, message: JsValue) -> Result<(), JsValue> {
+        Processor::receive(self, P::Message::from_js(message)?);
+        Ok(())
+    }
+}
+
+// The second box makes this a thin pointer, which can be passed
+// through JavaScript (see `pointer`).
+type BoxedProcessor = Box<Box<dyn ErasedProcessor>>;
+
+/// An
+/// [`AudioWorkletNode`](https://developer.mozilla.org/en-US/docs/Web/API/AudioWorkletNode)
+/// whose audio is processed by a [`Processor`] in the audio worklet.
+pub struct Node<M> {
+    node: web_sys::AudioWorkletNode,
+    _phantom: std::marker::PhantomData<fn(M)>,
+}
+
+impl<M: Post> Node<M> {
+    /// Create a node in `context` that processes its audio with
+    /// `processor`, which is moved to the audio worklet.
+    ///
+    /// The processor's module is added to the context's audio worklet
+    /// on first use.  Any `processorOptions` in `options` are
+    /// replaced.
+    ///
+    /// # Errors
+    ///
+    /// If the page can't share memory with the audio worklet, or the
+    /// processor's module couldn't be loaded or the node created.
+    pub async fn new<P: Processor<Message = M>>(
+        context: &web_sys::BaseAudioContext,
+        options: &web_sys::AudioWorkletNodeOptions,
+        processor: P,
+    ) -> Result<Self> {
+        if !super::Support::detect().shared_array_buffer {
+            return Err(error::spawn(
+                js_sys::Error::new("the page isn't cross-origin isolated").into(),
+            ));
+        }
+
+        JsFuture::from(
+            context
+                .audio_worklet()
+                .and_then(|worklet| worklet.add_module(&processor_url()))
+                .map_err(error::spawn)?,
+        )
+        .await
+        .map_err(error::spawn)?;
+
+        let module = super::module()?;
+        let processor: BoxedProcessor = Box::new(Box::new(processor));
+        let processor = Box::into_raw(processor);
+        options.set_processor_options(Some(&js_sys::Array::of3(
+            &module,
+            &wasm_bindgen::memory(),
+            &pointer::to_js(processor),
+        )));
+        let node = web_sys::AudioWorkletNode::new_with_options(context, "web-thread", options)
+            .map_err(|error| {
+                // the processor never made it to the worklet
+                drop(unsafe { Box::from_raw(processor) });
+                error::spawn(error)
+            })?;
+
+        Ok(Self {
+            node,
+            _phantom: std::marker::PhantomData,
+        })
+    }
+
+    /// The node, for connecting to the audio graph.
+    #[must_use]
+    pub fn node(&self) -> &web_sys::AudioWorkletNode {
+        &self.node
+    }
+
+    /// Send a message to the processor, to be handled by
+    /// [`Processor::receive`].
+    ///
+    /// # Errors
+    ///
+    /// If the message could not be sent.
+    pub fn post(&self, message: M) -> Result<()> {
+        // While not syntactically consumed, the use of `postMessage`
+        // here may leave `message` in an invalid state (setting
+        // transferred JavaScript values to `undefined`).
+        #![allow(clippy::needless_pass_by_value)]
+
+        self.node
+            .port()
+            .map_err(error::other)?
+            .post(message)
+            .map_err(error::serialization)
+    }
+}
+
+#[doc(hidden)]
+#[wasm_bindgen]
+#[must_use]
+pub unsafe fn __web_thread_audio_process(
+    processor: &JsValue,
+    inputs: &js_sys::Array,
+    outputs: &js_sys::Array,
+    parameters: &js_sys::Object,
+) -> bool {
+    let processor = pointer::from_js::<BoxedProcessor>(processor);
+    let keep_alive = unsafe { &mut *processor }.process(inputs, outputs, parameters);
+    if !keep_alive {
+        // `processor.js` stops using the processor once it's done
+        drop(unsafe { Box::from_raw(processor) });
+    }
+    keep_alive
+}
+
+#[doc(hidden)]
+#[wasm_bindgen]
+pub unsafe fn __web_thread_audio_receive(
+    processor: &JsValue,
+    message: JsValue,
+) -> Result<(), JsValue> {
+    unsafe { &mut *pointer::from_js::<BoxedProcessor>(processor) }.receive(message)
+}
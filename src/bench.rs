@@ -0,0 +1,202 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Micro-benchmarks of the costs of threads, for noticing performance
+//! regressions in spawning, posting and scheduling.
+//!
+//! ```no_run
+//! # async fn f() -> web_thread::Result<()> {
+//! // logs a table of the results to the console
+//! let report = web_thread::bench::run().await?;
+//! # Ok(()) }
+//! ```
+
+use std::time::Duration;
+
+use futures::future;
+
+use super::{JsValue, Result, SharedStr, Thread, js_sys, wasm_bindgen::JsCast as _};
+
+/// The payload sizes that posting is measured with, in bytes.
+const PAYLOAD_SIZES: [usize; 4] = [16, 1 << 10, 64 << 10, 1 << 20];
+
+/// The numbers of tasks in flight that saturation is measured with.
+const IN_FLIGHT: [usize; 6] = [1, 2, 4, 8, 16, 32];
+
+/// How many times each measurement is repeated, to average it.
+const ROUNDS: u32 = 20;
+
+/// The results of [`run`].  Its `Display` implementation formats them
+/// as a table.
+#[derive(Clone, Debug)]
+pub struct Report {
+    /// The mean time to spawn a thread and have it run a first task.
+    pub spawn: Duration,
+    /// The mean time for a trivial task to go to a running thread and
+    /// back.
+    pub round_trip: Duration,
+    /// The mean time to post a payload to a thread and back, by kind
+    /// of payload and size in bytes.
+    pub posting: Vec<Posting>,
+    /// The time for a number of threads to complete a fixed amount of
+    /// work, split into tasks, by the number of tasks in flight.
+    pub saturation: Vec<(usize, Duration)>,
+    /// The number of threads used to measure saturation.
+    pub threads: usize,
+}
+
+/// The cost of posting a payload.  See [`Report::posting`].
+#[derive(Clone, Debug)]
+pub struct Posting {
+    /// The payload type.
+    pub kind: &'static str,
+    /// The payload size, in bytes.
+    pub size: usize,
+    /// The mean time to post the payload to a thread and back.
+    pub time: Duration,
+}
+
+/// Run the benchmarks, logging the report to the console.
+///
+/// This spawns and destroys threads, and takes a few seconds.
+///
+/// # Errors
+///
+/// If a thread could not be spawned or a task failed.
+pub async fn run() -> Result<Report> {
+    let threads = hardware_concurrency();
+    let report = Report {
+        spawn: spawn().await?,
+        round_trip: round_trip().await?,
+        posting: posting().await?,
+        saturation: saturation(threads).await?,
+        threads,
+    };
+    web_sys::console::log_1(&report.to_string().into());
+    Ok(report)
+}
+
+/// Milliseconds since an arbitrary point, with the best resolution
+/// available.
+fn now() -> f64 {
+    js_sys::Reflect::get(&js_sys::global(), &"performance".into())
+        .ok()
+        .filter(JsValue::is_object)
+        .map_or_else(js_sys::Date::now, |performance| {
+            performance.unchecked_into::<web_sys::Performance>().now()
+        })
+}
+
+/// Time `f`, returning the mean time over `rounds` rounds.
+async fn time<T, F: Future<Output = Result<T>>>(
+    rounds: u32,
+    mut f: impl FnMut() -> F,
+) -> Result<Duration> {
+    let start = now();
+    for _ in 0..rounds {
+        f().await?;
+    }
+    Ok(Duration::from_secs_f64((now() - start) / 1000.) / rounds)
+}
+
+/// The number of logical processors, as reported by the browser.
+fn hardware_concurrency() -> usize {
+    // The count is a small positive integer.
+    #![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+
+    js_sys::Reflect::get(&js_sys::global(), &"navigator".into())
+        .and_then(|navigator| js_sys::Reflect::get(&navigator, &"hardwareConcurrency".into()))
+        .ok()
+        .and_then(|count| count.as_f64())
+        .map_or(1, |count| count.max(1.) as usize)
+}
+
+async fn spawn() -> Result<Duration> {
+    time(ROUNDS / 4, || async {
+        Thread::try_new()?.run((), |()| async {}).await
+    })
+    .await
+}
+
+async fn round_trip() -> Result<Duration> {
+    let thread = Thread::try_new()?;
+    // the first task waits for the thread to start
+    thread.run((), |()| async {}).await?;
+    time(ROUNDS * 5, || thread.run((), |()| async {})).await
+}
+
+async fn posting() -> Result<Vec<Posting>> {
+    let thread = Thread::try_new()?;
+    thread.run((), |()| async {}).await?;
+    let mut posting = Vec::new();
+    for size in PAYLOAD_SIZES {
+        let string = "x".repeat(size);
+        posting.push(Posting {
+            kind: "String",
+            size,
+            time: time(ROUNDS, || thread.run(string.clone(), future::ready)).await?,
+        });
+        let shared = SharedStr::from(string);
+        posting.push(Posting {
+            kind: "SharedStr",
+            size,
+            time: time(ROUNDS, || thread.run(shared.clone(), future::ready)).await?,
+        });
+        let bytes = vec![0_u8; size];
+        posting.push(Posting {
+            kind: "Vec<u8>",
+            size,
+            time: time(ROUNDS, || thread.run(bytes.clone(), future::ready)).await?,
+        });
+    }
+    Ok(posting)
+}
+
+async fn saturation(threads: usize) -> Result<Vec<(usize, Duration)>> {
+    /// The total work, in iterations of a busy loop.
+    const WORK: u32 = 1 << 24;
+
+    fn work(iterations: u32) -> u32 {
+        (0..iterations).fold(0_u32, |sum, i| std::hint::black_box(sum.wrapping_add(i)))
+    }
+
+    let threads = (0..threads)
+        .map(|_| Thread::try_new())
+        .collect::<Result<Vec<_>>>()?;
+    future::try_join_all(threads.iter().map(|thread| thread.run((), |()| async {}))).await?;
+    let mut saturation = Vec::new();
+    for in_flight in IN_FLIGHT {
+        let share = WORK / u32::try_from(in_flight).unwrap_or(u32::MAX);
+        let elapsed = time(1, || async {
+            future::try_join_all(
+                (0..in_flight).map(|task| threads[task % threads.len()].run_blocking(share, work)),
+            )
+            .await
+        })
+        .await?;
+        saturation.push((in_flight, elapsed));
+    }
+    Ok(saturation)
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "spawn: {:?}", self.spawn)?;
+        writeln!(f, "round trip: {:?}", self.round_trip)?;
+        writeln!(f, "posting:")?;
+        for Posting { kind, size, time } in &self.posting {
+            // The throughput is only displayed.
+            #[allow(clippy::cast_precision_loss)]
+            let throughput = *size as f64 / time.as_secs_f64() / f64::from(1 << 20);
+            writeln!(
+                f,
+                "  {kind:>9} {size:>8} B: {time:>12?} ({throughput:.1} MiB/s)"
+            )?;
+        }
+        writeln!(f, "saturation of {} threads:", self.threads)?;
+        for (in_flight, time) in &self.saturation {
+            writeln!(f, "  {in_flight:>2} tasks in flight: {time:?}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Typed
+//! [`BroadcastChannel`](https://developer.mozilla.org/en-US/docs/Web/API/BroadcastChannel)s,
+//! for sending values to every thread, and every tab of the same
+//! origin, that listens on a channel of the same name.
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    marker::PhantomData,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+use wasm_bindgen::closure::Closure;
+
+use super::{Post, Result, error, post, wasm_bindgen, wasm_bindgen::JsCast as _};
+
+/// Values that can be broadcast: those posted as plain data, which
+/// the structured clone algorithm copies whole to every receiver.
+///
+/// Values with [`Post::transferables`] can be received only once, and
+/// values posted as handles into the Wasm module's memory, such as
+/// [`SharedStr`](crate::SharedStr) and
+/// [`SharedCell`](crate::SharedCell), can be taken by only one
+/// receiver, and mean nothing to other tabs, which have memories of
+/// their own.  So only values posted with `serde` can be broadcast.
+pub trait Broadcast: Post + Clone {}
+
+impl<T: Post + Clone + serde::Serialize + serde::de::DeserializeOwned> Broadcast for T {}
+
+/// Join the broadcast channel called `name`, returning a sender to it
+/// and a receiver of everything sent to it, including by the sender.
+///
+/// Receivers in other tabs decode the values with their own Wasm
+/// module, so every tab on the channel must agree on its type.
+///
+/// # Errors
+///
+/// If the `BroadcastChannel` could not be created.
+pub fn broadcast<T: Broadcast + 'static>(name: &str) -> Result<(Sender<T>, Receiver<T>)> {
+    Ok((Sender::new(name)?, Receiver::new(name)?))
+}
+
+/// The sending half of a [`broadcast`] channel.
+pub struct Sender<T> {
+    channel: web_sys::BroadcastChannel,
+    _phantom: PhantomData<fn(T)>,
+}
+
+impl<T: Broadcast + 'static> Sender<T> {
+    fn new(name: &str) -> Result<Self> {
+        Ok(Self {
+            channel: web_sys::BroadcastChannel::new(name).map_err(error::other)?,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Send a copy of `value` to every receiver on the channel.
+    ///
+    /// # Errors
+    ///
+    /// If the value could not be sent.
+    pub fn send(&self, value: &T) -> Result<()> {
+        self.channel
+            .post_message(&post::to_js(value).map_err(error::serialization)?)
+            .map_err(error::serialization)
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.channel.close();
+    }
+}
+
+#[derive(Default)]
+struct State {
+    messages: VecDeque<super::JsValue>,
+    waker: Option<Waker>,
+}
+
+/// The receiving half of a [`broadcast`] channel, which is a
+/// [`Stream`](futures::Stream) of the values sent to it.  It receives
+/// values sent after it was created, and never ends.
+pub struct Receiver<T> {
+    channel: web_sys::BroadcastChannel,
+    state: Rc<RefCell<State>>,
+    _listener: Closure<dyn FnMut(web_sys::MessageEvent)>,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> Receiver<T> {
+    fn new(name: &str) -> Result<Self> {
+        let channel = web_sys::BroadcastChannel::new(name).map_err(error::other)?;
+        let state = Rc::new(RefCell::new(State::default()));
+        let listener = Closure::<dyn FnMut(_)>::new({
+            let state = state.clone();
+            move |event: web_sys::MessageEvent| {
+                let mut state = state.borrow_mut();
+                state.messages.push_back(event.data());
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            }
+        });
+        channel.set_onmessage(Some(listener.as_ref().unchecked_ref()));
+        Ok(Self {
+            channel,
+            state,
+            _listener: listener,
+            _phantom: PhantomData,
+        })
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.channel.set_onmessage(None);
+        self.channel.close();
+    }
+}
+
+impl<T: Broadcast + 'static> futures::Stream for Receiver<T> {
+    type Item = Result<T>;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Option<Result<T>>> {
+        let mut state = self.state.borrow_mut();
+        if let Some(message) = state.messages.pop_front() {
+            return Poll::Ready(Some(post::from_js(message).map_err(error::serialization)));
+        }
+        state.waker = Some(context.waker().clone());
+        Poll::Pending
+    }
+}
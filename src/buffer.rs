@@ -0,0 +1,119 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Reusing transferred `ArrayBuffer`s rather than allocating a fresh
+//! one for every message.
+
+use std::{cell::RefCell, rc::Rc};
+
+use super::{AsJs, JsValue, Post, js_sys, wasm_bindgen::JsCast as _};
+
+/// An `ArrayBuffer` that is
+/// [transferred](https://developer.mozilla.org/en-US/docs/Web/API/Web_Workers_API/Transferable_objects)
+/// rather than copied when it is posted, as a context or output.
+#[derive(Clone, Debug)]
+pub struct Buffer(js_sys::ArrayBuffer);
+
+impl Buffer {
+    /// Allocate a buffer of `size` bytes.
+    #[must_use]
+    pub fn new(size: u32) -> Self {
+        Self(js_sys::ArrayBuffer::new(size))
+    }
+
+    /// The buffer.
+    #[must_use]
+    pub fn array_buffer(&self) -> &js_sys::ArrayBuffer {
+        &self.0
+    }
+
+    /// A view of the buffer's bytes.
+    #[must_use]
+    pub fn bytes(&self) -> js_sys::Uint8Array {
+        js_sys::Uint8Array::new(&self.0)
+    }
+
+    /// Take the buffer.
+    #[must_use]
+    pub fn into_inner(self) -> js_sys::ArrayBuffer {
+        self.0
+    }
+}
+
+impl From<js_sys::ArrayBuffer> for Buffer {
+    fn from(buffer: js_sys::ArrayBuffer) -> Self {
+        Self(buffer)
+    }
+}
+
+impl AsJs for Buffer {
+    fn to_js(&self) -> Result<JsValue, JsValue> {
+        Ok(self.0.clone().into())
+    }
+
+    fn from_js(value: JsValue) -> Result<Self, JsValue> {
+        value
+            .dyn_into()
+            .map(Self)
+            .map_err(|_| js_sys::TypeError::new("expected an `ArrayBuffer`").into())
+    }
+}
+
+impl Post for Buffer {
+    fn transferables(&self) -> js_sys::Array {
+        std::iter::once(&self.0).collect()
+    }
+}
+
+/// A pool of [`Buffer`]s of one size, for pipelines that move frames
+/// of a fixed size between threads.
+///
+/// Transferring a buffer leaves it empty on the sending thread, so
+/// buffers are reused by sending them back once their contents have
+/// been consumed, for example as part of a task's output, and giving
+/// them back to the pool.  Clones of a pool share its buffers.
+#[derive(Clone, Debug)]
+pub struct BufferPool {
+    size: u32,
+    max_idle: usize,
+    idle: Rc<RefCell<Vec<js_sys::ArrayBuffer>>>,
+}
+
+impl BufferPool {
+    /// Create a pool of buffers of `size` bytes, keeping at most
+    /// `max_idle` buffers that aren't in use.
+    #[must_use]
+    pub fn new(size: u32, max_idle: usize) -> Self {
+        Self {
+            size,
+            max_idle,
+            idle: Rc::default(),
+        }
+    }
+
+    /// The size of the pool's buffers, in bytes.
+    #[must_use]
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Take a buffer from the pool, allocating one if none is idle.
+    /// Its contents are left over from its last use.
+    #[must_use]
+    pub fn take(&self) -> Buffer {
+        self.idle
+            .borrow_mut()
+            .pop()
+            .map_or_else(|| Buffer::new(self.size), Buffer)
+    }
+
+    /// Give a buffer back to the pool to be reused.  Buffers of
+    /// another size, including those that have been transferred away,
+    /// and buffers beyond the pool's idle limit are dropped.
+    pub fn give_back(&self, buffer: Buffer) {
+        let mut idle = self.idle.borrow_mut();
+        if buffer.0.byte_length() == self.size && idle.len() < self.max_idle {
+            idle.push(buffer.0);
+        }
+    }
+}
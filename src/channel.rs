@@ -0,0 +1,343 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Typed channels between threads, over `MessagePort`s.
+//!
+//! The [`Sender`] and [`Receiver`] of a channel are themselves
+//! [`Post`], so either end can be passed to a task on another thread
+//! as (part of) its context, or returned from one.
+//!
+//! ```no_run
+//! # async fn f(thread: &web_thread::Thread) -> web_thread::Result<()> {
+//! use futures::{SinkExt as _, StreamExt as _};
+//!
+//! let (mut sender, mut receiver) = web_thread::channel::<u32>(16)?;
+//! let task = thread.run(sender, |mut sender| async move {
+//!     for i in 0..100 {
+//!         sender.send(i).await.unwrap();
+//!     }
+//! });
+//! while let Some(i) = receiver.next().await {
+//!     let i = i?;
+//! }
+//! task.await?;
+//! # Ok(()) }
+//! ```
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, VecDeque},
+    marker::PhantomData,
+    pin::Pin,
+    rc::{Rc, Weak},
+    task::{Context, Poll, Waker},
+};
+
+use wasm_bindgen::closure::Closure;
+
+use super::{
+    AsJs, Error, JsValue, Post, Result, error, js_sys, post, wasm_bindgen,
+    wasm_bindgen::JsCast as _,
+};
+
+/// Create a channel of `T`s, each of whose senders may have up to
+/// `capacity` items that the receiver hasn't yet taken before it must
+/// wait.
+///
+/// # Errors
+///
+/// If the channel's `MessageChannel` could not be created.
+///
+/// # Panics
+///
+/// If `capacity` is zero.
+pub fn channel<T>(capacity: usize) -> Result<(Sender<T>, Receiver<T>)> {
+    assert!(capacity > 0, "a channel must hold at least one item");
+    let channel = web_sys::MessageChannel::new().map_err(error::other)?;
+    Ok((
+        Sender::new(channel.port1(), capacity),
+        Receiver::new([channel.port2()]),
+    ))
+}
+
+/// A JavaScript object with the single property `key`, as the
+/// senders' messages to the receiver.
+pub(crate) fn message(key: &str, value: &JsValue) -> JsValue {
+    let object = js_sys::Object::new();
+    // setting properties on a fresh object can't fail
+    let _ = js_sys::Reflect::set(&object, &key.into(), value);
+    object.into()
+}
+
+pub(crate) fn get(object: &JsValue, key: &str) -> Option<JsValue> {
+    js_sys::Reflect::get(object, &key.into())
+        .ok()
+        .filter(|value| !value.is_undefined())
+}
+
+#[derive(Default)]
+struct SenderState {
+    /// The number of items sent that the receiver hasn't taken.
+    in_flight: Cell<usize>,
+    waker: RefCell<Option<Waker>>,
+}
+
+/// The sending end of a [`channel`], which is a
+/// [`Sink`](futures::Sink).  Clones of a sender send to the same
+/// receiver, and the receiver's stream ends once all of them are
+/// dropped.
+///
+/// Items sent after the receiver is dropped are lost.
+pub struct Sender<T> {
+    port: web_sys::MessagePort,
+    capacity: usize,
+    state: Rc<SenderState>,
+    // called when the receiver takes an item
+    _on_taken: Closure<dyn FnMut()>,
+    _phantom: PhantomData<fn(T)>,
+}
+
+impl<T> Sender<T> {
+    fn new(port: web_sys::MessagePort, capacity: usize) -> Self {
+        let state = Rc::new(SenderState::default());
+        let on_taken = Closure::<dyn FnMut()>::new({
+            let state = state.clone();
+            move || {
+                // items sent before the sender was posted are taken
+                // after it has forgotten them
+                state.in_flight.set(state.in_flight.get().saturating_sub(1));
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            }
+        });
+        port.set_onmessage(Some(on_taken.as_ref().unchecked_ref()));
+        Self {
+            port,
+            capacity,
+            state,
+            _on_taken: on_taken,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        // Creating a `MessageChannel` only fails where there are no
+        // `MessagePort`s, so there couldn't be a sender either.
+        #![allow(clippy::missing_panics_doc)]
+
+        let channel = web_sys::MessageChannel::new().expect("failed to create a `MessageChannel`");
+        let port = channel.port2();
+        // the receiver listens to the new port once it's told of it
+        let _ = self.port.post_message_with_transferable(
+            &message("port", &port),
+            &std::iter::once(&port).collect::<js_sys::Array>(),
+        );
+        Self::new(channel.port1(), self.capacity)
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // a sender that has been posted has nothing left to close
+        let _ = self.port.post_message(&message("close", &JsValue::TRUE));
+        self.port.set_onmessage(None);
+        self.port.close();
+    }
+}
+
+impl<T: Post + 'static> futures::Sink<T> for Sender<T> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.state.in_flight.get() < self.capacity {
+            return Poll::Ready(Ok(()));
+        }
+        self.state.waker.replace(Some(context.waker().clone()));
+        Poll::Pending
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<()> {
+        let value = post::to_js(&item).map_err(error::serialization)?;
+        self.port
+            .post_message_with_transferable(&message("item", &value), &item.transferables())
+            .map_err(error::serialization)?;
+        self.state.in_flight.set(self.state.in_flight.get() + 1);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _context: &mut Context<'_>) -> Poll<Result<()>> {
+        // posted items are on their way
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _context: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T> AsJs for Sender<T> {
+    fn to_js(&self) -> Result<JsValue, JsValue> {
+        let object = message("port", &self.port);
+        js_sys::Reflect::set(&object, &"capacity".into(), &self.capacity.into())?;
+        Ok(object)
+    }
+
+    fn from_js(value: JsValue) -> Result<Self, JsValue> {
+        // The capacity came from a `usize`.
+        #![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+
+        let port = get(&value, "port").and_then(|port| port.dyn_into().ok());
+        let capacity = get(&value, "capacity").and_then(|capacity| capacity.as_f64());
+        match (port, capacity) {
+            (Some(port), Some(capacity)) => Ok(Self::new(port, capacity as usize)),
+            _ => Err(js_sys::TypeError::new("expected a posted `Sender`").into()),
+        }
+    }
+}
+
+impl<T> Post for Sender<T> {
+    fn transferables(&self) -> js_sys::Array {
+        std::iter::once(&self.port).collect()
+    }
+}
+
+type Listener = Closure<dyn FnMut(web_sys::MessageEvent)>;
+
+#[derive(Default)]
+struct ReceiverState {
+    /// Items that haven't been taken, with the ids of the ports they
+    /// came from.
+    items: VecDeque<(u32, JsValue)>,
+    /// The ports of the senders that haven't been dropped, by id.
+    ports: BTreeMap<u32, (web_sys::MessagePort, Listener)>,
+    next_port: u32,
+    /// Listeners of closed ports, which can't be dropped while they
+    /// are running.
+    closed: Vec<Listener>,
+    waker: Option<Waker>,
+}
+
+/// Listen for messages from the sender at `port`.
+fn listen(state: &Rc<RefCell<ReceiverState>>, port: web_sys::MessagePort) {
+    let id = {
+        let mut state = state.borrow_mut();
+        state.next_port += 1;
+        state.next_port
+    };
+    let weak = Rc::downgrade(state);
+    let listener = Listener::new(move |event: web_sys::MessageEvent| {
+        let Some(state) = Weak::upgrade(&weak) else {
+            return;
+        };
+        let data = event.data();
+        if let Some(item) = get(&data, "item") {
+            state.borrow_mut().items.push_back((id, item));
+        } else if let Some(port) = get(&data, "port") {
+            listen(&state, port.unchecked_into());
+        } else {
+            let mut state = state.borrow_mut();
+            if let Some((port, listener)) = state.ports.remove(&id) {
+                port.set_onmessage(None);
+                port.close();
+                state.closed.push(listener);
+            }
+        }
+        if let Some(waker) = state.borrow_mut().waker.take() {
+            waker.wake();
+        }
+    });
+    port.set_onmessage(Some(listener.as_ref().unchecked_ref()));
+    state.borrow_mut().ports.insert(id, (port, listener));
+}
+
+/// The receiving end of a [`channel`], which is a
+/// [`Stream`](futures::Stream) of the items sent, in the order each
+/// sender sent them, that ends once all the senders are dropped.
+pub struct Receiver<T> {
+    state: Rc<RefCell<ReceiverState>>,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> Receiver<T> {
+    fn new(ports: impl IntoIterator<Item = web_sys::MessagePort>) -> Self {
+        let state = Rc::default();
+        for port in ports {
+            listen(&state, port);
+        }
+        Self {
+            state,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        for (port, _) in self.state.borrow().ports.values() {
+            port.set_onmessage(None);
+            port.close();
+        }
+    }
+}
+
+impl<T: Post + 'static> futures::Stream for Receiver<T> {
+    type Item = Result<T>;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Option<Result<T>>> {
+        let mut state = self.state.borrow_mut();
+        state.closed.clear();
+        if let Some((id, value)) = state.items.pop_front() {
+            // let the sender send another
+            if let Some((port, _)) = state.ports.get(&id) {
+                let _ = port.post_message(&JsValue::NULL);
+            }
+            return Poll::Ready(Some(post::from_js(value).map_err(error::serialization)));
+        }
+        if state.ports.is_empty() {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(context.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> AsJs for Receiver<T> {
+    /// A receiver can only be posted while it holds no items, as
+    /// items it has already received can't be sent on.
+    fn to_js(&self) -> Result<JsValue, JsValue> {
+        let state = self.state.borrow();
+        if !state.items.is_empty() {
+            return Err(js_sys::Error::new(
+                "a `Receiver` holding items that haven't been taken can't be posted",
+            )
+            .into());
+        }
+        Ok(state
+            .ports
+            .values()
+            .map(|(port, _)| port)
+            .collect::<js_sys::Array>()
+            .into())
+    }
+
+    fn from_js(value: JsValue) -> Result<Self, JsValue> {
+        let ports: js_sys::Array = value
+            .dyn_into()
+            .map_err(|_| js_sys::TypeError::new("expected a posted `Receiver`"))?;
+        Ok(Self::new(ports.iter().map(JsValue::unchecked_into)))
+    }
+}
+
+impl<T> Post for Receiver<T> {
+    fn transferables(&self) -> js_sys::Array {
+        self.state
+            .borrow()
+            .ports
+            .values()
+            .map(|(port, _)| port)
+            .collect()
+    }
+}
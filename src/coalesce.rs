@@ -0,0 +1,143 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Posting many small messages as fewer large ones.
+
+use std::{
+    cell::{Cell, RefCell},
+    rc::{Rc, Weak},
+    time::Duration,
+};
+
+use super::{AsJs, JsValue, Post, PostExt, time::sleep};
+
+struct State<P, T> {
+    target: P,
+    items: RefCell<Vec<T>>,
+    max_items: usize,
+    max_delay: Duration,
+    /// Whether a flush is scheduled for when the oldest buffered item
+    /// has waited `max_delay`.
+    scheduled: Cell<bool>,
+    /// The error from the last scheduled flush, to report from the
+    /// next call.
+    error: RefCell<Option<JsValue>>,
+}
+
+impl<P: PostExt, T: Post> State<P, T>
+where
+    Vec<T>: AsJs,
+{
+    fn flush(&self) -> Result<(), JsValue> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        let items = self.items.take();
+        if items.is_empty() {
+            return Ok(());
+        }
+        self.target.post(items)
+    }
+}
+
+/// A sender of items to a [`PostExt`] target, such as a
+/// `MessagePort`, that buffers them and posts them together as a
+/// single array, for streams of items at rates at which posting each
+/// on its own would be wasteful.
+///
+/// Items are posted once `max_items` are buffered or the oldest has
+/// waited for `max_delay`, whichever comes first, or when
+/// [`Coalescer::flush`] is called or the `Coalescer` is dropped.  The
+/// receiver is sent arrays of items, in order.
+pub struct Coalescer<P: PostExt + 'static, T: Post + 'static>
+where
+    Vec<T>: AsJs,
+{
+    state: Rc<State<P, T>>,
+}
+
+impl<P: PostExt + 'static, T: Post + 'static> Coalescer<P, T>
+where
+    Vec<T>: AsJs,
+{
+    /// Buffer items for `target`, posting at most `max_items` at once
+    /// and delaying each by at most `max_delay`.
+    ///
+    /// # Panics
+    ///
+    /// If `max_items` is zero.
+    pub fn new(target: P, max_items: usize, max_delay: Duration) -> Self {
+        assert!(max_items > 0, "a `Coalescer` must post at least one item");
+        Self {
+            state: Rc::new(State {
+                target,
+                items: RefCell::new(Vec::with_capacity(max_items)),
+                max_items,
+                max_delay,
+                scheduled: Cell::new(false),
+                error: RefCell::new(None),
+            }),
+        }
+    }
+
+    /// Buffer `item` to be posted.
+    ///
+    /// # Errors
+    ///
+    /// If the items could not be posted, whether now or when posted
+    /// since the last call.
+    pub fn push(&self, item: T) -> Result<(), JsValue> {
+        let state = &self.state;
+        let len = {
+            let mut items = state.items.borrow_mut();
+            items.push(item);
+            items.len()
+        };
+        if len >= state.max_items {
+            return state.flush();
+        }
+        if !state.scheduled.replace(true) {
+            let delay = state.max_delay;
+            let state = Rc::downgrade(state);
+            wasm_bindgen_futures::spawn_local(async move {
+                sleep(delay).await;
+                // the buffer was flushed when the `Coalescer` was dropped
+                let Some(state) = Weak::upgrade(&state) else {
+                    return;
+                };
+                state.scheduled.set(false);
+                if let Err(error) = state.flush() {
+                    state.error.replace(Some(error));
+                }
+            });
+        }
+        state.error.take().map_or(Ok(()), Err)
+    }
+
+    /// Post the buffered items now, for moments when latency matters
+    /// more than throughput.
+    ///
+    /// # Errors
+    ///
+    /// If the items could not be posted, whether now or when posted
+    /// since the last call.
+    pub fn flush(&self) -> Result<(), JsValue> {
+        self.state.flush()
+    }
+
+    /// The target the items are posted to.
+    #[must_use]
+    pub fn target(&self) -> &P {
+        &self.state.target
+    }
+}
+
+impl<P: PostExt + 'static, T: Post + 'static> Drop for Coalescer<P, T>
+where
+    Vec<T>: AsJs,
+{
+    fn drop(&mut self) {
+        // there is no one left to report an error to
+        let _ = self.state.flush();
+    }
+}
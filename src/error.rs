@@ -1,30 +1,35 @@
 // Copyright (c) Zefchain Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use super::{JsValue, js_sys, wasm_bindgen::JsCast as _};
+use super::{Error, JsValue, js_sys, wasm_bindgen::JsCast as _};
+
+/// The message of the error the worker reports when it is destroyed
+/// with tasks in flight (see `worker.js`), or finds that a task's
+/// code has gone with its thread.
+pub const DESTROYED: &str = "thread destroyed during execution";
 
 #[derive(Debug)]
-pub struct Error {
+pub struct JsError {
     description: String,
-    source: Option<Box<Error>>,
+    source: Option<Box<JsError>>,
 }
 
-impl std::fmt::Display for Error {
+impl std::fmt::Display for JsError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{}", &self.description)
     }
 }
 
-impl std::error::Error for Error {
+impl std::error::Error for JsError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         self.source.as_ref().map(|error| error.as_ref() as _)
     }
 }
 
-impl From<JsValue> for Error {
+impl From<JsValue> for JsError {
     fn from(value: JsValue) -> Self {
         let Some(error) = value.dyn_ref::<js_sys::Error>() else {
-            return Error {
+            return JsError {
                 description: format!(
                     "could not cast value of type {:?} to `Error`",
                     value.js_typeof()
@@ -33,11 +38,52 @@ impl From<JsValue> for Error {
             };
         };
 
-        Error {
+        JsError {
             description: error.message().into(),
             source: Some(error.cause())
                 .filter(JsValue::is_undefined)
-                .map(|x| Box::new(Error::from(x))),
+                .map(|x| Box::new(JsError::from(x))),
         }
     }
 }
+
+/// The name of the error `Client.js` reports for a worker that
+/// couldn't be created.
+const SPAWN_ERROR: &str = "web_thread$SpawnError";
+
+/// Classify an exception thrown while running a task.
+pub fn from_task(value: JsValue) -> Error {
+    if value
+        .dyn_ref::<js_sys::Error>()
+        .is_some_and(|error| error.name() == SPAWN_ERROR)
+    {
+        return spawn(value);
+    }
+
+    // a panic traps, which surfaces as a `RuntimeError`
+    let panicked = value.is_instance_of::<js_sys::WebAssembly::RuntimeError>();
+    let error = JsError::from(value);
+    if error.description == DESTROYED {
+        Error::Killed
+    } else if panicked {
+        Error::Panic(error.description)
+    } else {
+        Error::Other(Box::new(error))
+    }
+}
+
+/// The error for a value that couldn't be converted to or from
+/// JavaScript.
+pub fn serialization(value: JsValue) -> Error {
+    Error::Serialization(JsError::from(value).to_string())
+}
+
+/// The error for a worker that couldn't be created.
+pub fn spawn(value: JsValue) -> Error {
+    Error::Spawn(Box::new(JsError::from(value)))
+}
+
+/// The error for any other exception.
+pub fn other(value: JsValue) -> Error {
+    Error::Other(Box::new(JsError::from(value)))
+}
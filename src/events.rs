@@ -0,0 +1,471 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Forwarding DOM events from the main thread to tasks on other
+//! threads.
+//!
+//! Workers can't listen to the DOM, so a [`Bridge`] listens on the
+//! main thread, converts each event into a [`Post`] value with
+//! [`FromEvent`], and sends it over a [channel](crate::channel).  The
+//! receiving end is a [`Stream`](futures::Stream) that can be passed
+//! to a task as its context, as for a renderer that follows the
+//! pointer:
+//!
+//! ```no_run
+//! # async fn f(thread: &web_thread::Thread, canvas: &web_sys::EventTarget)
+//! # -> web_thread::Result<()> {
+//! use std::time::Duration;
+//! use futures::StreamExt as _;
+//! use web_thread::events::{Bridge, Pointer};
+//!
+//! let (subscription, pointer) = Bridge::new(canvas)
+//!     .on("pointerdown")
+//!     .on("pointermove")
+//!     .coalesce(true)
+//!     .throttle(Duration::from_millis(16))
+//!     .forward::<Pointer>()?;
+//! let task = thread.run(pointer, |mut pointer| async move {
+//!     while let Some(Ok(pointer)) = pointer.next().await {
+//!         // draw at `pointer.offset_x`, `pointer.offset_y`
+//!     }
+//! });
+//! // events are forwarded until the subscription is dropped
+//! drop(subscription);
+//! task.await?;
+//! # Ok(()) }
+//! ```
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use futures::SinkExt as _;
+use wasm_bindgen::closure::Closure;
+
+use super::{
+    AsJs, JsValue, Post, Result, channel, error, js_sys, time::sleep, wasm_bindgen,
+    wasm_bindgen::JsCast as _,
+};
+
+/// Values that can be made from DOM events, to be forwarded by a
+/// [`Bridge`].
+pub trait FromEvent: Post + Sized + 'static {
+    /// Convert `event`, or return `None` to skip it.
+    fn from_event(event: &web_sys::Event) -> Option<Self>;
+}
+
+/// The properties of events, as they are posted.
+trait Field: Sized {
+    fn to_js(&self) -> JsValue;
+    fn from_js(value: &JsValue) -> Option<Self>;
+}
+
+impl Field for f64 {
+    fn to_js(&self) -> JsValue {
+        (*self).into()
+    }
+
+    fn from_js(value: &JsValue) -> Option<Self> {
+        value.as_f64()
+    }
+}
+
+impl Field for i32 {
+    fn to_js(&self) -> JsValue {
+        (*self).into()
+    }
+
+    fn from_js(value: &JsValue) -> Option<Self> {
+        // Casting saturates, and the properties read this way are
+        // integers in range.
+        #![allow(clippy::cast_possible_truncation)]
+
+        value.as_f64().map(|number| number as Self)
+    }
+}
+
+impl Field for u16 {
+    fn to_js(&self) -> JsValue {
+        (*self).into()
+    }
+
+    fn from_js(value: &JsValue) -> Option<Self> {
+        // Casting saturates, and the properties read this way are
+        // integers in range.
+        #![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+
+        value.as_f64().map(|number| number as Self)
+    }
+}
+
+impl Field for bool {
+    fn to_js(&self) -> JsValue {
+        (*self).into()
+    }
+
+    fn from_js(value: &JsValue) -> Option<Self> {
+        value.as_bool()
+    }
+}
+
+impl Field for String {
+    fn to_js(&self) -> JsValue {
+        self.into()
+    }
+
+    fn from_js(value: &JsValue) -> Option<Self> {
+        value.as_string()
+    }
+}
+
+/// Define a struct of event properties, each read from and posted as
+/// the JavaScript property of the given name.
+macro_rules! event {
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident {
+            $($(#[$field_meta:meta])* $field:ident: $type:ty = $js:literal,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Debug, Default, PartialEq)]
+        pub struct $name {
+            $($(#[$field_meta])* pub $field: $type,)*
+        }
+
+        impl $name {
+            /// Read the properties from `object`, defaulting those it
+            /// lacks.
+            fn read(object: &JsValue) -> Self {
+                Self {
+                    $($field: js_sys::Reflect::get(object, &$js.into())
+                        .ok()
+                        .and_then(|value| Field::from_js(&value))
+                        .unwrap_or_default(),)*
+                }
+            }
+        }
+
+        impl AsJs for $name {
+            fn to_js(&self) -> Result<JsValue, JsValue> {
+                let object = js_sys::Object::new();
+                $(js_sys::Reflect::set(&object, &$js.into(), &Field::to_js(&self.$field))?;)*
+                Ok(object.into())
+            }
+
+            fn from_js(value: JsValue) -> Result<Self, JsValue> {
+                Ok(Self {
+                    $($field: js_sys::Reflect::get(&value, &$js.into())
+                        .ok()
+                        .and_then(|value| Field::from_js(&value))
+                        .ok_or_else(|| js_sys::TypeError::new(
+                            concat!("expected `", $js, "` of type `", stringify!($type), "`")
+                        ))?,)*
+                })
+            }
+        }
+
+        impl Post for $name {}
+    };
+}
+
+event! {
+    /// A pointer or mouse event, such as `pointermove` or `click`.
+    pub struct Pointer {
+        /// The event's type, such as `pointermove`.
+        kind: String = "type",
+        /// The pointer's id, or zero for mouse events.
+        pointer_id: i32 = "pointerId",
+        /// The kind of pointer, such as `mouse`, `pen` or `touch`, or
+        /// empty for mouse events.
+        pointer_type: String = "pointerType",
+        /// The horizontal position in the viewport, in CSS pixels.
+        client_x: f64 = "clientX",
+        /// The vertical position in the viewport, in CSS pixels.
+        client_y: f64 = "clientY",
+        /// The horizontal position relative to the target, in CSS
+        /// pixels.
+        offset_x: f64 = "offsetX",
+        /// The vertical position relative to the target, in CSS
+        /// pixels.
+        offset_y: f64 = "offsetY",
+        /// The buttons held, as a bit mask.
+        buttons: u16 = "buttons",
+        /// The pressure applied, from zero to one.
+        pressure: f64 = "pressure",
+        /// Whether the Alt key was held.
+        alt_key: bool = "altKey",
+        /// Whether the Control key was held.
+        ctrl_key: bool = "ctrlKey",
+        /// Whether the Meta key was held.
+        meta_key: bool = "metaKey",
+        /// Whether the Shift key was held.
+        shift_key: bool = "shiftKey",
+    }
+}
+
+impl FromEvent for Pointer {
+    fn from_event(event: &web_sys::Event) -> Option<Self> {
+        Some(Self::read(event))
+    }
+}
+
+event! {
+    /// A keyboard event, such as `keydown`.
+    pub struct Key {
+        /// The event's type, such as `keydown`.
+        kind: String = "type",
+        /// The key's value, such as `a` or `Enter`.
+        key: String = "key",
+        /// The physical key, such as `KeyA`.
+        code: String = "code",
+        /// Whether the key is held down and repeating.
+        repeat: bool = "repeat",
+        /// Whether the Alt key was held.
+        alt_key: bool = "altKey",
+        /// Whether the Control key was held.
+        ctrl_key: bool = "ctrlKey",
+        /// Whether the Meta key was held.
+        meta_key: bool = "metaKey",
+        /// Whether the Shift key was held.
+        shift_key: bool = "shiftKey",
+    }
+}
+
+impl FromEvent for Key {
+    fn from_event(event: &web_sys::Event) -> Option<Self> {
+        Some(Self::read(event))
+    }
+}
+
+event! {
+    /// The size of the window after a `resize` event on it.
+    pub struct Resize {
+        /// The viewport's width, in CSS pixels.
+        width: f64 = "innerWidth",
+        /// The viewport's height, in CSS pixels.
+        height: f64 = "innerHeight",
+        /// The number of device pixels per CSS pixel.
+        device_pixel_ratio: f64 = "devicePixelRatio",
+    }
+}
+
+impl FromEvent for Resize {
+    fn from_event(event: &web_sys::Event) -> Option<Self> {
+        event.target().map(|target| Self::read(&target))
+    }
+}
+
+/// A configuration for forwarding DOM events.
+#[derive(Clone, Debug)]
+pub struct Bridge {
+    target: web_sys::EventTarget,
+    types: Vec<String>,
+    coalesce: bool,
+    throttle: Option<Duration>,
+    capacity: usize,
+}
+
+impl Bridge {
+    /// Forward events on `target`, of the types added with
+    /// [`Bridge::on`].
+    #[must_use]
+    pub fn new(target: &web_sys::EventTarget) -> Self {
+        Self {
+            target: target.clone(),
+            types: Vec::new(),
+            coalesce: false,
+            throttle: None,
+            capacity: 16,
+        }
+    }
+
+    /// Forward events of type `event_type`, such as `pointermove`.
+    #[must_use]
+    pub fn on(mut self, event_type: impl Into<String>) -> Self {
+        self.types.push(event_type.into());
+        self
+    }
+
+    /// Keep only the latest of consecutive events of the same type
+    /// while they wait to be sent, so that a busy receiver sees where
+    /// the pointer is rather than everywhere it has been.  Events of
+    /// different types, such as the `pointerdown` between two
+    /// `pointermove`s, are never merged.
+    #[must_use]
+    pub fn coalesce(self, coalesce: bool) -> Self {
+        Self { coalesce, ..self }
+    }
+
+    /// Send at most one event every `interval`, holding the rest
+    /// back (and [coalescing](Bridge::coalesce) them, if enabled).
+    #[must_use]
+    pub fn throttle(self, interval: Duration) -> Self {
+        Self {
+            throttle: Some(interval),
+            ..self
+        }
+    }
+
+    /// Let up to `capacity` events be in flight to the receiver
+    /// before holding the rest back.  The default is 16.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero.
+    #[must_use]
+    pub fn capacity(self, capacity: usize) -> Self {
+        assert!(capacity > 0, "a bridge must send at least one event");
+        Self { capacity, ..self }
+    }
+
+    /// Start forwarding events as `E`s, until the returned
+    /// [`Subscription`] is dropped.
+    ///
+    /// # Errors
+    ///
+    /// If the channel could not be created or a listener could not be
+    /// added.
+    pub fn forward<E: FromEvent>(self) -> Result<(Subscription, channel::Receiver<E>)> {
+        let (sender, receiver) = channel::channel(self.capacity)?;
+        let state = Rc::new(RefCell::new(State {
+            pending: VecDeque::new(),
+            coalesce: self.coalesce,
+            closed: false,
+            waker: None,
+        }));
+
+        let mut subscription = Subscription {
+            target: self.target,
+            listeners: Vec::new(),
+            state: state.clone(),
+        };
+        for (index, event_type) in self.types.into_iter().enumerate() {
+            let state = state.clone();
+            let listener = Listener::new(move |event| {
+                state.borrow_mut().push(index, &event);
+            });
+            subscription
+                .target
+                .add_event_listener_with_callback(&event_type, listener.as_ref().unchecked_ref())
+                .map_err(error::other)?;
+            subscription.listeners.push((event_type, listener));
+        }
+
+        wasm_bindgen_futures::spawn_local(pump(state, sender, self.throttle));
+        Ok((subscription, receiver))
+    }
+}
+
+/// Forward events of type `event_type` on `target` as `E`s, with the
+/// default configuration.  See [`Bridge`] for more.
+///
+/// # Errors
+///
+/// If the channel could not be created or the listener could not be
+/// added.
+pub fn forward<E: FromEvent>(
+    target: &web_sys::EventTarget,
+    event_type: &str,
+) -> Result<(Subscription, channel::Receiver<E>)> {
+    Bridge::new(target).on(event_type).forward()
+}
+
+type Listener = Closure<dyn FnMut(web_sys::Event)>;
+
+/// The events waiting to be sent, each with the index of its type.
+struct State<E> {
+    pending: VecDeque<(usize, E)>,
+    coalesce: bool,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl<E: FromEvent> State<E> {
+    fn push(&mut self, index: usize, event: &web_sys::Event) {
+        if self.closed {
+            return;
+        }
+        let Some(event) = E::from_event(event) else {
+            return;
+        };
+        if self.coalesce && self.pending.back().is_some_and(|(last, _)| *last == index) {
+            self.pending.pop_back();
+        }
+        self.pending.push_back((index, event));
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn poll_next(&mut self, context: &Context) -> Poll<Option<E>> {
+        if let Some((_, event)) = self.pending.pop_front() {
+            Poll::Ready(Some(event))
+        } else if self.closed {
+            Poll::Ready(None)
+        } else {
+            self.waker = Some(context.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// Send the pending events until the subscription is dropped or the
+/// receiver is gone.
+async fn pump<E: FromEvent>(
+    state: Rc<RefCell<State<E>>>,
+    mut sender: channel::Sender<E>,
+    throttle: Option<Duration>,
+) {
+    while let Some(event) =
+        futures::future::poll_fn(|context| state.borrow_mut().poll_next(context)).await
+    {
+        if sender.send(event).await.is_err() {
+            break;
+        }
+        if let Some(interval) = throttle {
+            sleep(interval).await;
+        }
+    }
+    state.borrow_mut().closed = true;
+}
+
+/// The listeners of a [`Bridge`], which forward events until this is
+/// dropped.  Events already received are still sent, after which the
+/// receiver's stream ends.
+pub struct Subscription {
+    target: web_sys::EventTarget,
+    listeners: Vec<(String, Listener)>,
+    state: Rc<dyn Close>,
+}
+
+/// Closing a [`State`] of any event type.
+trait Close {
+    fn close(&self);
+}
+
+impl<E> Close for RefCell<State<E>> {
+    fn close(&self) {
+        let mut state = self.borrow_mut();
+        state.closed = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        for (event_type, listener) in &self.listeners {
+            // there is no one to report an error to
+            let _ = self
+                .target
+                .remove_event_listener_with_callback(event_type, listener.as_ref().unchecked_ref());
+        }
+        self.state.close();
+    }
+}
@@ -0,0 +1,40 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::Write as _;
+
+/// The
+/// [import map](https://developer.mozilla.org/en-US/docs/Web/HTML/Reference/Elements/script/type/importmap)
+/// that maps `web-thread:wasm-shim` to `shim_url`, the URL of the
+/// `wasm-bindgen` shim script, for a page to include in a
+/// `<script type="importmap">` element.  Pages with import maps of
+/// their own should merge in its entry instead.
+#[must_use]
+pub fn import_map(shim_url: &str) -> String {
+    let mut url = String::with_capacity(shim_url.len());
+    for character in shim_url.chars() {
+        match character {
+            '"' => url.push_str("\\\""),
+            '\\' => url.push_str("\\\\"),
+            // `<` would let the URL close the `<script>` element
+            '<' => url.push_str("\\u003c"),
+            character if character.is_control() => {
+                let _ = write!(url, "\\u{:04x}", u32::from(character));
+            }
+            character => url.push(character),
+        }
+    }
+    format!(r#"{{"imports":{{"web-thread:wasm-shim":"{url}"}}}}"#)
+}
+
+#[test]
+fn urls_are_escaped() {
+    assert_eq!(
+        import_map("/wasm/my-library.js"),
+        r#"{"imports":{"web-thread:wasm-shim":"/wasm/my-library.js"}}"#,
+    );
+    assert_eq!(
+        import_map("/a\"b\\</script>\n"),
+        r#"{"imports":{"web-thread:wasm-shim":"/a\"b\\\u003c/script>\u000a"}}"#,
+    );
+}
@@ -64,6 +64,23 @@ using a source-transformation tool such as
 }
 ```
 
+Unbundled modules can instead map it with an [import
+map](https://developer.mozilla.org/en-US/docs/Web/HTML/Reference/Elements/script/type/importmap),
+such as [`import_map`] generates:
+
+```html
+<script type="importmap">
+{"imports":{"web-thread:wasm-shim":"/wasm/my-library.js"}}
+</script>
+```
+
+Workers don't see the page's import map, so the identifier is
+resolved on the page and the result passed on to them.
+
+Otherwise, the URL of the shim script can be given at runtime with [`Thread::with_module_url`] or [`Builder::module_url`].
+With [`Builder::embed_worker`], `worker.js` needn't be served
+either.
+
 Turbopack is currently not supported due to an open issue when
 processing cyclic dependencies.  See the following discussions for
 more information:
@@ -72,31 +89,168 @@ more information:
 * [Next.js v15.2.2 Turbopack Dev server stuck in compiling + extreme CPU/memory usage (#77102)](https://github.com/vercel/next.js/discussions/77102)
 * [Eliminate the circular dependency between the main loader and the worker (#20580)](https://github.com/emscripten-core/emscripten/issues/20580)
 
+## Node.js
+
+With the `node` feature, threads also run under Node.js 20.16 or
+later, on
+[`worker_threads`](https://nodejs.org/api/worker_threads.html)
+sharing the same memory.  Node.js must load `worker.js` as an ES
+module, for example by marking the package containing the
+`wasm-bindgen` output with `"type": "module"`, and the shim script is
+best given with [`Thread::with_module_url`].  Without the feature,
+threads under Node.js [fall back](Thread::is_fallback) to running
+their tasks on the current thread.
+
+## Deno
+
+Threads run on Deno's workers, which are always module workers, so
+[`WorkerType::Classic`] is ignored there.  Deno gives workers the
+permissions of the main module, which must include read access to
+`worker.js` and the shim script (or network access, if they are
+served remotely); a worker denied them fails with an
+[`Error::Spawn`] naming the missing permission.  The shim script can
+be aliased with the `imports` of `deno.json` or given with
+[`Thread::with_module_url`].
+
+## Electron
+
+Electron renderers run threads as browsers do, whatever their
+`contextIsolation` and `sandbox` settings, but need shared memory:
+either serve the app from a [privileged
+scheme](https://www.electronjs.org/docs/latest/api/protocol#protocolregisterschemesasprivilegedcustomschemes)
+whose responses carry the cross-origin isolation headers above, or
+enable `SharedArrayBuffer` with
+`app.commandLine.appendSwitch('enable-features', 'SharedArrayBuffer')`.
+Pages loaded from `file://` URLs can't create module workers, so use
+a custom scheme in any case.
+
+Memory can't be shared between processes, so a renderer can't run
+threads in a utility process.  Code running in a utility process can
+run threads of its own there, as under [Node.js](#nodejs).
+
+## Shared workers
+
+Threads can't run in a
+[`SharedWorker`](https://developer.mozilla.org/en-US/docs/Web/API/SharedWorker).
+A shared worker lives outside the agent cluster of the pages connected
+to it, so browsers refuse to send it their `SharedArrayBuffer`s, and a
+task is sent to its thread as a pointer into the sending page's
+memory.  To share work between tabs, give each tab threads of its own
+and coordinate them through a
+[`BroadcastChannel`](https://developer.mozilla.org/en-US/docs/Web/API/BroadcastChannel).
+
 */
 
+// Without these target features, memory can't be shared with workers,
+// which fails only at runtime and obscurely, so fail the build instead.
+// The `unchecked-target` feature turns the check off for builds that
+// compile this crate without running it, such as `web-thread-select`
+// with `force-shim`.
+#[cfg(all(
+    target_family = "wasm",
+    not(target_feature = "atomics"),
+    not(feature = "unchecked-target")
+))]
+compile_error!(
+    "`web-thread` requires the `atomics` target feature: add \
+     `-C target-feature=+atomics,+bulk-memory,+mutable-globals` to the \
+     rustflags for `wasm32-unknown-unknown` (see the crate documentation)"
+);
+#[cfg(all(
+    target_family = "wasm",
+    not(target_feature = "bulk-memory"),
+    not(feature = "unchecked-target")
+))]
+compile_error!(
+    "`web-thread` requires the `bulk-memory` target feature: add \
+     `-C target-feature=+atomics,+bulk-memory,+mutable-globals` to the \
+     rustflags for `wasm32-unknown-unknown` (see the crate documentation)"
+);
+#[cfg(all(
+    target_family = "wasm",
+    not(target_feature = "mutable-globals"),
+    not(feature = "unchecked-target")
+))]
+compile_error!(
+    "`web-thread` requires the `mutable-globals` target feature: add \
+     `-C target-feature=+atomics,+bulk-memory,+mutable-globals` to the \
+     rustflags for `wasm32-unknown-unknown` (see the crate documentation)"
+);
+
+pub mod actor;
+#[cfg(feature = "audio-worklet")]
+pub mod audio;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod broadcast;
+mod buffer;
+pub mod channel;
+mod coalesce;
 mod error;
-
+pub mod events;
+
+mod import_map;
+// `src/main.rs` would be taken for a binary
+#[path = "main_thread.rs"]
+pub mod main;
+mod memory;
+pub mod oneshot;
+pub mod pipeline;
+#[cfg(feature = "audio-worklet")]
+mod pointer;
 mod post;
+mod registry;
+pub mod ring;
+pub mod rpc;
+mod shared_cell;
+mod shared_str;
+mod standby;
+mod support;
+pub mod time;
 use std::{
     pin::Pin,
     task::{Context, Poll, ready},
 };
 
-use futures::{FutureExt as _, TryFutureExt as _, channel::oneshot, future};
+pub use broadcast::broadcast;
+pub use buffer::{Buffer, BufferPool};
+pub use channel::channel;
+pub use coalesce::Coalescer;
+use futures::{FutureExt as _, TryFutureExt as _, future};
+pub use import_map::import_map;
+pub use memory::{check_memory_growth, on_memory_growth};
+pub use oneshot::oneshot;
 use post::Postable;
 pub use post::{AsJs, Post, PostExt};
+pub use shared_cell::SharedCell;
+pub use shared_str::SharedStr;
+pub use standby::keep_standby;
+pub use support::{EmbedderPolicy, Support, is_supported};
 use wasm_bindgen::prelude::{JsValue, wasm_bindgen};
 use wasm_bindgen_futures::JsFuture;
-use web_sys::{js_sys, wasm_bindgen};
+pub use watch::watch;
+#[doc(no_inline)]
+pub use web_sys::js_sys;
+use web_sys::wasm_bindgen;
 
-pub type Result<T, E = Error> = std::result::Result<T, E>;
+pub use web_thread_core::{CancellationToken, Result, sync, watch};
 
 #[wasm_bindgen(module = "/src/Client.js")]
 extern "C" {
     #[wasm_bindgen(js_name = "web_thread$Client")]
     type Client;
-    #[wasm_bindgen(constructor, js_class = "web_thread$Client")]
-    fn new(module: JsValue, memory: JsValue) -> Client;
+    #[wasm_bindgen(constructor, js_class = "web_thread$Client", catch)]
+    fn new(
+        module: JsValue,
+        memory: JsValue,
+        worker_type: &str,
+        module_url: Option<&str>,
+        source: Option<&str>,
+        policy_name: Option<&str>,
+        priority: Option<&str>,
+        idle_timeout: Option<f64>,
+        main_port: web_sys::MessagePort,
+    ) -> Result<Client, JsValue>;
 
     #[wasm_bindgen(js_class = "web_thread$Client", method)]
     fn run(
@@ -111,7 +265,40 @@ extern "C" {
 }
 
 /// A representation of a JavaScript thread (Web worker with shared memory).
-pub struct Thread(Client);
+pub struct Thread(Backend);
+
+enum Backend {
+    Worker {
+        client: Client,
+        // the thread's id in the registry of code waiting to run
+        id: u32,
+    },
+    // there are no workers that can share memory with us, so tasks
+    // run on the current thread instead
+    Local,
+}
+
+/// The compiled Wasm module, to send to workers.  Browsers share the
+/// compiled code of a module sent to workers rather than compiling it
+/// again, so spawning a thread costs only an instantiation.
+fn module() -> Result<JsValue> {
+    thread_local! {
+        static MODULE: JsValue = wasm_bindgen::module();
+    }
+    MODULE.with(|module| {
+        if wasm_bindgen::JsCast::is_instance_of::<js_sys::WebAssembly::Module>(module) {
+            Ok(module.clone())
+        } else {
+            // without it, workers would have to fetch and compile the
+            // module themselves
+            Err(Error::Spawn(
+                "the compiled Wasm module isn't available to send to workers; \
+                 build with `wasm-bindgen --target web`"
+                    .into(),
+            ))
+        }
+    })
+}
 
 pin_project_lite::pin_project! {
     /// A task that's been spawned on a [`Thread`].
@@ -123,15 +310,38 @@ pin_project_lite::pin_project! {
             future::MapErr<JsFuture, fn(JsValue) -> Error>,
             future::Ready<Result<JsValue>>,
         >,
-        _phantom: std::marker::PhantomData<T>,
+        // chosen where `T` is known to be `'static`, for the fast
+        // paths of `post::from_js`
+        decode: fn(JsValue) -> Result<T, JsValue>,
+    }
+}
+
+impl<T> Task<T> {
+    /// Complete with the task's output as it arrived from the worker,
+    /// without converting it, for outputs that are only needed later
+    /// or conditionally, or that are passed straight on to JavaScript.
+    /// The output can be converted when needed with
+    /// [`AsJs::from_js`].
+    ///
+    /// An output that is never needed needn't be waited for: dropping
+    /// the task skips converting it altogether.
+    #[must_use]
+    pub fn into_js(self) -> Task<JsValue> {
+        Task {
+            result: self.result,
+            decode: Ok,
+        }
     }
 }
 
-impl<T: Post> Future for Task<T> {
+impl<T> Future for Task<T> {
     type Output = Result<T>;
 
     fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
-        Poll::Ready(Ok(T::from_js(ready!(self.result.poll_unpin(context))?)?))
+        let result = ready!(self.result.poll_unpin(context));
+        // the task may have grown the memory
+        memory::check_memory_growth();
+        Poll::Ready((self.decode)(result?).map_err(error::serialization))
     }
 }
 
@@ -139,8 +349,8 @@ pin_project_lite::pin_project! {
     /// A [`Task`] with a `Send` output.
     /// See [`Thread::run_send`] for usage.
     pub struct SendTask<T> {
-        task: Task<()>,
-        receiver: oneshot::Receiver<T>,
+        task: future::Fuse<Task<()>>,
+        receiver: futures::channel::oneshot::Receiver<T>,
     }
 }
 
@@ -148,24 +358,127 @@ impl<T: Send> Future for SendTask<T> {
     type Output = Result<T>;
 
     fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
-        ready!(self.task.poll_unpin(context))?;
-        Poll::Ready(Ok(
-            ready!(self.receiver.poll_unpin(context)).expect("task already completed successfully")
-        ))
+        // The output is sent through shared memory, which wakes us
+        // with `Atomics.notify`, so it arrives ahead of the message
+        // completing the task.
+        if let Poll::Ready(outcome) = self.receiver.poll_unpin(context) {
+            return Poll::Ready(match outcome {
+                Ok(output) => Ok(output),
+                // the code was dropped without an output, so the task
+                // failed
+                Err(futures::channel::oneshot::Canceled) => {
+                    Err(ready!(self.task.poll_unpin(context))
+                        .err()
+                        .unwrap_or(Error::Killed))
+                }
+            });
+        }
+        // a failed task sends no output
+        if let Poll::Ready(Err(error)) = self.task.poll_unpin(context) {
+            return Poll::Ready(Err(error));
+        }
+        Poll::Pending
     }
 }
 
 impl Thread {
     /// Spawn a new thread.
+    ///
+    /// # Panics
+    ///
+    /// If the worker could not be created.  See [`Thread::try_new`]
+    /// for a fallible version.
     #[must_use]
     pub fn new() -> Self {
-        Self(Client::new(wasm_bindgen::module(), wasm_bindgen::memory()))
+        Self::try_new().expect("failed to spawn thread")
+    }
+
+    /// Spawn a new thread.
+    ///
+    /// If the page isn't [cross-origin
+    /// isolated](https://developer.mozilla.org/en-US/docs/Web/API/Window/crossOriginIsolated),
+    /// it can't share memory with workers, and from a service worker
+    /// no workers can be created at all, so rather than failing this
+    /// falls back to a thread that runs its tasks on the current
+    /// thread.  This is reported with a console warning, and can be
+    /// checked with [`Thread::is_fallback`].
+    ///
+    /// # Errors
+    ///
+    /// If the worker could not be created, for example because the
+    /// page's content security policy forbids workers.
+    pub fn try_new() -> Result<Self> {
+        Builder::new().spawn()
+    }
+
+    /// Spawn a new thread that loads the `wasm-bindgen` shim script
+    /// from `url`, rather than from `web-thread:wasm-shim`.  See
+    /// [`Builder::module_url`].
+    ///
+    /// # Errors
+    ///
+    /// If the worker could not be created.
+    pub fn with_module_url(url: impl Into<String>) -> Result<Self> {
+        Builder::new().module_url(url).spawn()
+    }
+
+    /// Configure a thread before spawning it.
+    #[must_use]
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
+    fn spawn(builder: &Builder) -> Result<Self> {
+        let support = Support::detect();
+        let reason = if !support.workers {
+            Some("workers can't be created from this context")
+        } else if !support.shared_array_buffer {
+            Some(if support.cross_origin_isolated {
+                "`SharedArrayBuffer` isn't available"
+            } else {
+                "the page isn't cross-origin isolated"
+            })
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            static WARNED: std::sync::Once = std::sync::Once::new();
+            WARNED.call_once(|| {
+                web_sys::console::warn_1(
+                    &format!("[web-thread] {reason}, so tasks will run on the current thread")
+                        .into(),
+                );
+            });
+            return Ok(Self(Backend::Local));
+        }
+
+        // standby workers are spawned with the default configuration
+        let standby = (*builder == Builder::default())
+            .then(standby::take)
+            .flatten();
+        Ok(Self(Backend::Worker {
+            client: match standby {
+                Some(client) => client,
+                None => builder.client()?,
+            },
+            id: registry::thread_id(),
+        }))
+    }
+
+    /// Whether this thread has fallen back to running its tasks on
+    /// the current thread, because workers can't be created or can't
+    /// share memory with the page.  See [`Thread::try_new`].
+    #[must_use]
+    pub fn is_fallback(&self) -> bool {
+        matches!(self.0, Backend::Local)
     }
 
     /// Execute a function on a thread.
     ///
     /// The function will begin executing immediately.  The resulting
-    /// [`Task`] can be awaited to retrieve the result.
+    /// [`Task`] can be awaited to retrieve the result.  Tasks run in
+    /// the same tick are sent to the worker together in a single
+    /// message, so fanning out many small tasks is cheap.
     ///
     /// # Arguments
     ///
@@ -194,109 +507,326 @@ impl Thread {
         #![allow(clippy::needless_pass_by_value)]
 
         let transfer = context.transferables();
+        self.submit(context.to_js(), transfer, Code::new(code))
+    }
+
+    /// Send `code` to the thread to be run with `context`.
+    fn submit<T: Post + 'static>(
+        &self,
+        context: Result<JsValue, JsValue>,
+        transfer: js_sys::Array,
+        code: Code,
+    ) -> Task<T> {
         Task {
-            _phantom: std::marker::PhantomData,
-            result: match context.to_js() {
-                Ok(context) => future::Either::Left(
-                    JsFuture::from(self.0.run(Code::new(code).into(), context, transfer))
-                        .map_err(Into::into),
+            decode: post::from_js,
+            result: match context.and_then(|context| post::detach(context, transfer)) {
+                Ok((context, transfer)) => future::Either::Left(
+                    JsFuture::from(match &self.0 {
+                        Backend::Worker { client, id } => {
+                            client.run(registry::insert(*id, code).into(), context, transfer)
+                        }
+                        Backend::Local => wasm_bindgen_futures::future_to_promise(async move {
+                            Ok(code.call_once(context).await?.into_message())
+                        }),
+                    })
+                    .map_err(error::from_task as _),
                 ),
-                Err(error) => future::Either::Right(future::ready(Err(error.into()))),
+                Err(error) => {
+                    future::Either::Right(future::ready(Err(error::serialization(error))))
+                }
             },
         }
     }
 
+    /// Execute a synchronous function on a thread.
+    ///
+    /// The function runs to completion without yielding, so this
+    /// suits CPU-bound work or code that blocks, at the cost of
+    /// holding up the thread's other tasks until it is done.
+    pub fn run_blocking<Context: Post, T: Post + 'static>(
+        &self,
+        context: Context,
+        code: impl FnOnce(Context) -> T + Send + 'static,
+    ) -> Task<T> {
+        self.run(context, |context| future::ready(code(context)))
+    }
+
     /// Like [`Thread::run`], but the output can be sent through Rust
     /// memory without `Post`ing.
+    ///
+    /// The output is delivered as soon as the code completes, without
+    /// waiting for the worker to report the task complete with
+    /// `postMessage`, which makes this the quicker way to run small
+    /// tasks.
     pub fn run_send<Context: Post, F: Future<Output: Send> + 'static>(
         &self,
         context: Context,
         code: impl FnOnce(Context) -> F + Send + 'static,
     ) -> SendTask<F::Output> {
-        let (sender, receiver) = oneshot::channel();
+        let (sender, receiver) = futures::channel::oneshot::channel();
         SendTask {
-            task: self.run(context, |context| {
-                code(context).map(|outcome| {
-                    let _ = sender.send(outcome);
+            task: self
+                .run(context, |context| {
+                    code(context).map(|outcome| {
+                        let _ = sender.send(outcome);
+                    })
                 })
-            }),
+                .fuse(),
             receiver,
         }
     }
 }
 
-impl Default for Thread {
-    fn default() -> Self {
-        Self::new()
+/// A configuration for a new [`Thread`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Builder {
+    worker_type: WorkerType,
+    module_url: Option<String>,
+    embed_worker: bool,
+    trusted_types_policy: Option<String>,
+    priority: Option<Priority>,
+    idle_timeout: Option<std::time::Duration>,
+}
+
+/// The kind of script a [`Thread`]'s worker runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WorkerType {
+    /// A module worker, which loads `web-thread:wasm-shim` with an
+    /// `import`.
+    #[default]
+    Module,
+    /// A classic worker, for browsers and test runners that can't
+    /// load module workers.  Classic workers can't `import`, so this
+    /// requires a bundler that bundles `worker.js` and the shim
+    /// script into a single classic script, such as Vite with its
+    /// default `worker.format` of `'iife'`.
+    Classic,
+}
+
+impl WorkerType {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Module => "module",
+            Self::Classic => "classic",
+        }
     }
 }
 
-impl Drop for Thread {
-    fn drop(&mut self) {
-        self.0.destroy();
+/// A [priority](https://developer.mozilla.org/en-US/docs/Web/API/Prioritized_Task_Scheduling_API#task_priorities)
+/// of the Prioritized Task Scheduling API.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    /// For work the user is waiting on, ahead of rendering.
+    UserBlocking,
+    /// For work the user will see, alongside rendering.
+    UserVisible,
+    /// For work the user needn't wait on, after rendering and input.
+    Background,
+}
+
+impl Priority {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::UserBlocking => "user-blocking",
+            Self::UserVisible => "user-visible",
+            Self::Background => "background",
+        }
     }
 }
 
-/// The type of errors that can be thrown in the course of executing a thread.
-pub type Error = error::Error;
+impl Builder {
+    /// The default configuration, which spawns a module worker.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-type JsTask = std::pin::Pin<Box<dyn Future<Output = Result<Postable, JsValue>>>>;
-type RemoteTask = Box<dyn FnOnce(JsValue) -> JsTask + Send>;
+    /// Set the kind of script the worker runs.
+    #[must_use]
+    pub fn worker_type(self, worker_type: WorkerType) -> Self {
+        Self {
+            worker_type,
+            ..self
+        }
+    }
 
-struct Code {
-    // The second box allows us to represent this as a thin pointer
-    // (Wasm: u32) which, unlike fat pointers (Wasm: u64) is within
-    // the [JavaScript safe integer
-    // range](https://developer.mozilla.org/en-US/docs/Web/JavaScript/Reference/Global_Objects/Number/isSafeInteger).
-    code: Option<Box<RemoteTask>>,
-}
+    /// Load the `wasm-bindgen` shim script from `url` rather than
+    /// from the module identifier `web-thread:wasm-shim`, for
+    /// deployments that can't alias module identifiers.  Relative
+    /// URLs are resolved against the location of `worker.js`.
+    ///
+    /// Only a URL can be given, since the worker must import the
+    /// script itself: module namespace objects can't be sent to it.
+    #[must_use]
+    pub fn module_url(self, url: impl Into<String>) -> Self {
+        Self {
+            module_url: Some(url.into()),
+            ..self
+        }
+    }
 
-impl Code {
-    fn new<F: Future<Output: Post> + 'static, Context: Post>(
-        code: impl FnOnce(Context) -> F + Send + 'static,
-    ) -> Self {
+    /// Create the worker from a copy of `worker.js` embedded in the
+    /// binary, through a `Blob` URL, rather than from the `worker.js`
+    /// that `wasm-bindgen` writes out, so that nothing but the shim
+    /// script and Wasm binary need be served.  The shim script must
+    /// then be given with [`Builder::module_url`], and the page's
+    /// content security policy must allow `blob:` workers.
+    #[must_use]
+    pub fn embed_worker(self, embed_worker: bool) -> Self {
         Self {
-            code: Some(Box::new(Box::new(|context| {
-                Box::pin(async move { Postable::new(code(Context::from_js(context)?).await) })
-            }))),
+            embed_worker,
+            ..self
         }
     }
 
-    async fn call_once(mut self, context: JsValue) -> Result<Postable, JsValue> {
-        (*self.code.take().expect("code called more than once"))(context).await
+    /// Create the worker's script URL with a [Trusted
+    /// Types](https://developer.mozilla.org/en-US/docs/Web/API/Trusted_Types_API)
+    /// policy called `name`, for pages that require Trusted Types
+    /// for scripts.  The name must be allowed by the page's
+    /// `trusted-types` directive.
+    ///
+    /// Under a strict `worker-src` directive, serve `worker.js` from
+    /// the page's origin and don't [embed](Builder::embed_worker) it,
+    /// since `blob:` URLs are usually blocked.  Workers blocked by
+    /// the content security policy fail with an [`Error::Spawn`]
+    /// naming the directive when their first task is run.
+    #[must_use]
+    pub fn trusted_types_policy(self, name: impl Into<String>) -> Self {
+        Self {
+            trusted_types_policy: Some(name.into()),
+            ..self
+        }
     }
 
-    /// # Safety
+    /// Deliver the results of the thread's tasks, waking whatever is
+    /// waiting on them, as tasks of `priority` posted with
+    /// [`scheduler.postTask`](https://developer.mozilla.org/en-US/docs/Web/API/Scheduler/postTask),
+    /// so that, for example, the results of bulk work in the
+    /// [background](Priority::Background) don't hold up rendering.
     ///
-    /// Must only be called on `JsValue`s created with the
-    /// `Into<JsValue>` implementation.
-    unsafe fn from_js_value(js_value: &JsValue) -> Self {
-        // We know this doesn't truncate or lose sign as the `f64` is
-        // a representation of a 32-bit pointer.
-        #![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    /// By default, and in browsers without `scheduler.postTask`,
+    /// results are delivered as soon as they arrive.
+    #[must_use]
+    pub fn priority(self, priority: Priority) -> Self {
+        Self {
+            priority: Some(priority),
+            ..self
+        }
+    }
 
+    /// Submit tasks to the worker only while the current thread is
+    /// idle, as reported by
+    /// [`requestIdleCallback`](https://developer.mozilla.org/en-US/docs/Web/API/Window/requestIdleCallback),
+    /// so that posting the tasks of background work doesn't compete
+    /// with handling input.  Tasks waiting longer than `timeout` are
+    /// submitted anyway.
+    ///
+    /// In browsers and workers without `requestIdleCallback`, tasks
+    /// are submitted straight away.
+    #[must_use]
+    pub fn dispatch_when_idle(self, timeout: std::time::Duration) -> Self {
         Self {
-            code: Some(unsafe { Box::from_raw(js_value.as_f64().unwrap() as u32 as _) }),
+            idle_timeout: Some(timeout),
+            ..self
         }
     }
+
+    /// Spawn the thread.  See [`Thread::try_new`] for when this falls
+    /// back to running tasks on the current thread.
+    ///
+    /// # Errors
+    ///
+    /// If the worker could not be created.
+    pub fn spawn(self) -> Result<Thread> {
+        Thread::spawn(&self)
+    }
+
+    fn client(&self) -> Result<Client> {
+        if self.embed_worker && self.module_url.is_none() {
+            return Err(Error::Spawn(
+                "an embedded worker script needs the shim script's URL".into(),
+            ));
+        }
+
+        Client::new(
+            module()?,
+            wasm_bindgen::memory(),
+            self.worker_type.as_str(),
+            self.module_url.as_deref(),
+            self.embed_worker.then_some(WORKER_SCRIPT),
+            self.trusted_types_policy.as_deref(),
+            self.priority.map(Priority::as_str),
+            self.idle_timeout
+                .map(|timeout| timeout.as_secs_f64() * 1000.),
+            main::worker_port().map_err(error::spawn)?,
+        )
+        .map_err(error::spawn)
+    }
 }
 
-impl From<Code> for JsValue {
-    fn from(code: Code) -> Self {
-        (Box::into_raw(code.code.expect("serializing consumed code")) as u32).into()
+impl Default for Thread {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Thread {
+    fn drop(&mut self) {
+        if let Backend::Worker { client, id } = &self.0 {
+            client.destroy();
+            registry::remove_thread(*id);
+        }
+    }
+}
+
+/// Spawning a future onto a [`Thread`] runs it to completion on the
+/// thread, discarding the [`Task`].
+impl futures::task::Spawn for Thread {
+    fn spawn_obj(
+        &self,
+        future: futures::task::FutureObj<'static, ()>,
+    ) -> Result<(), futures::task::SpawnError> {
+        drop(self.run((), |()| future));
+        Ok(())
+    }
+}
+
+pub use web_thread_core::Error;
+
+type JsTask = std::pin::Pin<Box<dyn Future<Output = Result<Postable, JsValue>>>>;
+type RemoteTask = Box<dyn FnOnce(JsValue) -> JsTask + Send>;
+
+struct Code(RemoteTask);
+
+impl Code {
+    fn new<F: Future<Output: Post> + 'static, Context: Post>(
+        code: impl FnOnce(Context) -> F + Send + 'static,
+    ) -> Self {
+        Self(Box::new(|context| {
+            Box::pin(async move { Postable::new(code(Context::from_js(context)?).await) })
+        }))
+    }
+
+    async fn call_once(self, context: JsValue) -> Result<Postable, JsValue> {
+        (self.0)(context).await
     }
 }
 
 #[doc(hidden)]
 #[wasm_bindgen]
-pub async unsafe fn __web_thread_worker_entry_point(
-    code: JsValue,
+pub async fn __web_thread_worker_entry_point(
+    code: u32,
     context: JsValue,
 ) -> Result<JsValue, JsValue> {
-    let code = unsafe { Code::from_js_value(&code) };
-    serde_wasm_bindgen::to_value(&code.call_once(context).await?).map_err(Into::into)
+    memory::check_memory_growth();
+    // the code is gone if its thread has been dropped
+    let code = registry::take(code).ok_or_else(|| js_sys::Error::new(error::DESTROYED))?;
+    Ok(code.call_once(context).await?.into_js())
 }
 
+/// The source of `worker.js`, for [`Builder::embed_worker`].
+const WORKER_SCRIPT: &str = include_str!("worker.js");
+
 #[wasm_bindgen(module = "/src/worker.js")]
 extern "C" {
     // This is here just to ensure `/src/worker.js` makes it into the
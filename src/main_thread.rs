@@ -0,0 +1,181 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Running code on the main thread from any thread, for what only the
+//! main thread can do, such as touching the DOM, reading the
+//! clipboard or calling `URL.createObjectURL` for a page.
+//!
+//! Every thread spawned by this crate, however deeply nested, is
+//! given a `MessagePort` to the main thread as it starts, so no setup
+//! is needed:
+//!
+//! ```no_run
+//! # async fn f(thread: &web_thread::Thread) -> web_thread::Result<()> {
+//! use web_thread::js_sys::{Reflect, global};
+//!
+//! let title: String = thread
+//!     .run((), |()| async {
+//!         web_thread::main::spawn(|| async {
+//!             Reflect::get(&global(), &"document".into())
+//!                 .and_then(|document| Reflect::get(&document, &"title".into()))
+//!                 .ok()
+//!                 .and_then(|title| title.as_string())
+//!                 .unwrap_or_default()
+//!         })
+//!         .await
+//!         .unwrap_or_default()
+//!     })
+//!     .await?;
+//! # Ok(()) }
+//! ```
+
+use std::{cell::RefCell, sync::OnceLock};
+
+use futures::{TryFutureExt as _, future};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen_futures::JsFuture;
+
+use super::{
+    Code, JsValue, Post, Task, channel, error, js_sys, post, registry, wasm_bindgen,
+    wasm_bindgen::JsCast as _,
+};
+
+thread_local! {
+    /// This thread's port to the main thread, or `None` on the main
+    /// thread.
+    static PORT: RefCell<Option<web_sys::MessagePort>> = const { RefCell::new(None) };
+}
+
+/// The id under which code to run on the main thread is registered.
+fn thread_id() -> u32 {
+    static ID: OnceLock<u32> = OnceLock::new();
+    *ID.get_or_init(registry::thread_id)
+}
+
+/// Run `code` on the main thread, from any thread.  The output is
+/// sent back with `postMessage`, like that of [`Thread::run`](crate::Thread::run).
+///
+/// On the main thread, or on a thread not spawned by this crate, the
+/// code runs on the current thread.
+pub fn spawn<T: Post + 'static, F: Future<Output = T> + 'static>(
+    code: impl FnOnce() -> F + Send + 'static,
+) -> Task<T> {
+    let code = Code::new(move |()| code());
+    let promise = PORT.with_borrow(|port| match port {
+        Some(port) => send(port, code),
+        None => Ok(wasm_bindgen_futures::future_to_promise(async move {
+            Ok(code.call_once(JsValue::UNDEFINED).await?.into_message())
+        })),
+    });
+    Task {
+        decode: post::from_js,
+        result: match promise {
+            Ok(promise) => {
+                future::Either::Left(JsFuture::from(promise).map_err(error::from_task as _))
+            }
+            Err(error) => future::Either::Right(future::ready(Err(error::other(error)))),
+        },
+    }
+}
+
+/// Ask the main thread to run `code`, returning a promise of its
+/// output.
+fn send(port: &web_sys::MessagePort, code: Code) -> Result<js_sys::Promise, JsValue> {
+    let reply = web_sys::MessageChannel::new()?;
+    let promise = js_sys::Promise::new(&mut |resolve, reject| {
+        let port = reply.port1();
+        let on_message = Closure::once_into_js(move |event: web_sys::MessageEvent| {
+            port.close();
+            let data = event.data();
+            // calling the promise's own functions can't fail
+            let _ = match channel::get(&data, "error") {
+                Some(error) => reject.call1(&JsValue::UNDEFINED, &error),
+                None => resolve.call1(
+                    &JsValue::UNDEFINED,
+                    &js_sys::Reflect::get(&data, &"result".into()).unwrap_or_default(),
+                ),
+            };
+        });
+        reply
+            .port1()
+            .set_onmessage(Some(on_message.unchecked_ref()));
+    });
+
+    let id = registry::insert(thread_id(), code);
+    let message = js_sys::Object::new();
+    // setting properties on a fresh object can't fail
+    let _ = js_sys::Reflect::set(&message, &"code".into(), &id.into());
+    let _ = js_sys::Reflect::set(&message, &"reply".into(), &reply.port2());
+    port.post_message_with_transferable(&message, &js_sys::Array::of1(&reply.port2()))
+        .inspect_err(|_| drop(registry::take(id)))?;
+    Ok(promise)
+}
+
+/// A port to the main thread for a new worker, which the main thread
+/// listens to.
+pub(crate) fn worker_port() -> Result<web_sys::MessagePort, JsValue> {
+    let channel = web_sys::MessageChannel::new()?;
+    PORT.with_borrow(|port| {
+        if let Some(port) = port {
+            port.post_message_with_transferable(
+                &channel::message("port", &channel.port1()),
+                &js_sys::Array::of1(&channel.port1()),
+            )
+        } else {
+            listen(&channel.port1());
+            Ok(())
+        }
+    })?;
+    Ok(channel.port2())
+}
+
+/// Run the code sent on `port`, on the main thread.
+fn listen(port: &web_sys::MessagePort) {
+    thread_local! {
+        static ON_MESSAGE: JsValue =
+            Closure::<dyn Fn(web_sys::MessageEvent)>::new(|event| receive(&event))
+                .into_js_value();
+    }
+    ON_MESSAGE.with(|on_message| port.set_onmessage(Some(on_message.unchecked_ref())));
+}
+
+fn receive(event: &web_sys::MessageEvent) {
+    // Casting saturates, and ids are `u32`s.
+    #![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+
+    let data = event.data();
+    if let Some(port) = channel::get(&data, "port") {
+        // a new worker's port, sent by its parent
+        listen(port.unchecked_ref());
+        return;
+    }
+    let (Some(id), Some(reply)) = (
+        channel::get(&data, "code").and_then(|id| id.as_f64()),
+        channel::get(&data, "reply"),
+    ) else {
+        web_sys::console::error_2(&"[web-thread] malformed request".into(), &data);
+        return;
+    };
+    let reply = web_sys::MessagePort::from(reply);
+    wasm_bindgen_futures::spawn_local(async move {
+        let output = match registry::take(id as u32) {
+            Some(code) => code.call_once(JsValue::UNDEFINED).await,
+            None => Err(js_sys::Error::new(error::DESTROYED).into()),
+        };
+        let posted = output.and_then(|output| {
+            let (output, transfer) = output.into_parts();
+            reply.post_message_with_transferable(&channel::message("result", &output), &transfer)
+        });
+        if let Err(error) = posted {
+            // there is no one else to report an error to
+            let _ = reply.post_message(&channel::message("error", &error));
+        }
+        reply.close();
+    });
+}
+
+#[doc(hidden)]
+#[wasm_bindgen]
+pub fn __web_thread_set_main_port(port: web_sys::MessagePort) {
+    PORT.set(Some(port));
+}
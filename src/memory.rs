@@ -0,0 +1,66 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Noticing when the shared memory grows.
+//!
+//! Growing a `WebAssembly.Memory` leaves the JavaScript typed-array
+//! views of it too short, on every thread, but JavaScript has no event
+//! for growth.  Threads check for it whenever they start or finish a
+//! task, and code holding views can ask to be told so that it can
+//! re-create them.
+
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use super::{js_sys, wasm_bindgen};
+
+type Callback = Rc<dyn Fn(usize)>;
+
+thread_local! {
+    static SEEN: Cell<usize> = Cell::new(size());
+    static CALLBACKS: RefCell<Vec<Callback>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The size of the shared memory, in bytes.
+fn size() -> usize {
+    let memory: js_sys::WebAssembly::Memory = wasm_bindgen::memory().into();
+    js_sys::Reflect::get(&memory.buffer(), &"byteLength".into())
+        .ok()
+        .and_then(|length| length.as_f64())
+        // We know this doesn't truncate or lose sign as the `f64` is
+        // the length of a buffer.
+        .map_or(0, |length| {
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let length = length as usize;
+            length
+        })
+}
+
+/// Call `callback` with the memory's new size, in bytes, whenever the
+/// current thread notices that the shared memory has grown.
+///
+/// Growth by any thread is noticed when the current thread next
+/// starts or finishes a task, or calls [`check_memory_growth`].
+pub fn on_memory_growth(callback: impl Fn(usize) + 'static) {
+    CALLBACKS.with_borrow_mut(|callbacks| callbacks.push(Rc::new(callback)));
+}
+
+/// Check whether the shared memory has grown since the current thread
+/// last checked, and if so call the callbacks registered with
+/// [`on_memory_growth`].
+///
+/// Code holding views of the memory can call this before using them,
+/// for example at the start of each animation frame.
+pub fn check_memory_growth() {
+    let size = size();
+    if size <= SEEN.replace(size) {
+        return;
+    }
+    // callbacks may register more callbacks
+    let callbacks = CALLBACKS.with_borrow(Clone::clone);
+    for callback in callbacks {
+        callback(size);
+    }
+}
@@ -0,0 +1,209 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Channels for sending a single value between threads, over a
+//! `MessagePort`.
+//!
+//! As with [`channel`](crate::channel), both halves are [`Post`], so
+//! a task can deliver its result to a consumer other than its
+//! spawner, or wait for an input that is only available later.
+//!
+//! ```no_run
+//! # async fn f(thread: &web_thread::Thread) -> web_thread::Result<()> {
+//! let (sender, receiver) = web_thread::oneshot::<String>()?;
+//! let task = thread.run(receiver, |receiver| async move {
+//!     receiver.await.unwrap().to_uppercase()
+//! });
+//! sender.send("available later".into())?;
+//! task.await?;
+//! # Ok(()) }
+//! ```
+
+use std::{
+    cell::RefCell,
+    marker::PhantomData,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+use wasm_bindgen::closure::Closure;
+
+use super::{
+    AsJs, Error, JsValue, Post, Result, error, js_sys, post, wasm_bindgen,
+    wasm_bindgen::JsCast as _,
+};
+
+/// Create a channel for sending a single `T`.
+///
+/// # Errors
+///
+/// If the channel's `MessageChannel` could not be created.
+pub fn oneshot<T>() -> Result<(Sender<T>, Receiver<T>)> {
+    let channel = web_sys::MessageChannel::new().map_err(error::other)?;
+    Ok((
+        Sender {
+            port: channel.port1(),
+            _phantom: PhantomData,
+        },
+        Receiver::new(channel.port2()),
+    ))
+}
+
+/// The sending half of a [`oneshot`] channel.
+pub struct Sender<T> {
+    port: web_sys::MessagePort,
+    _phantom: PhantomData<fn(T)>,
+}
+
+impl<T: Post + 'static> Sender<T> {
+    /// Send `value` to the receiver.
+    ///
+    /// # Errors
+    ///
+    /// If the value could not be sent.
+    pub fn send(self, value: T) -> Result<()> {
+        // While not syntactically consumed, the use of `postMessage`
+        // here may leave `value` in an invalid state (setting
+        // transferred JavaScript values to `undefined`).
+        #![allow(clippy::needless_pass_by_value)]
+
+        let object = js_sys::Object::new();
+        let value_js = post::to_js(&value).map_err(error::serialization)?;
+        // setting properties on a fresh object can't fail
+        let _ = js_sys::Reflect::set(&object, &"value".into(), &value_js);
+        self.port
+            .post_message_with_transferable(&object, &value.transferables())
+            .map_err(error::serialization)?;
+        // the value is on its way, so dropping mustn't cancel it
+        self.port.close();
+        Ok(())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // Posting on a closed or posted port does nothing, so this
+        // only tells the receiver when nothing was sent.
+        let _ = self.port.post_message(&JsValue::NULL);
+        self.port.close();
+    }
+}
+
+impl<T> AsJs for Sender<T> {
+    fn to_js(&self) -> Result<JsValue, JsValue> {
+        Ok(self.port.clone().into())
+    }
+
+    fn from_js(value: JsValue) -> Result<Self, JsValue> {
+        Ok(Self {
+            port: value
+                .dyn_into()
+                .map_err(|_| js_sys::TypeError::new("expected a posted `Sender`"))?,
+            _phantom: PhantomData,
+        })
+    }
+}
+
+impl<T> Post for Sender<T> {
+    fn transferables(&self) -> js_sys::Array {
+        std::iter::once(&self.port).collect()
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// The message from the sender, once it has arrived.
+    message: Option<JsValue>,
+    waker: Option<Waker>,
+}
+
+/// The receiving half of a [`oneshot`] channel, which is a future of
+/// the value sent.  It fails with [`Error::Killed`] if the sender is
+/// dropped without sending, as when the thread holding it stops.
+pub struct Receiver<T> {
+    port: web_sys::MessagePort,
+    state: Rc<RefCell<State>>,
+    _listener: Closure<dyn FnMut(web_sys::MessageEvent)>,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> Receiver<T> {
+    fn new(port: web_sys::MessagePort) -> Self {
+        let state = Rc::new(RefCell::new(State::default()));
+        let listener = Closure::<dyn FnMut(_)>::new({
+            let state = state.clone();
+            move |event: web_sys::MessageEvent| {
+                let mut state = state.borrow_mut();
+                // only the first message counts, in case the sender
+                // sent a value and was then dropped
+                if state.message.is_none() {
+                    state.message = Some(event.data());
+                }
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            }
+        });
+        port.set_onmessage(Some(listener.as_ref().unchecked_ref()));
+        Self {
+            port,
+            state,
+            _listener: listener,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.port.set_onmessage(None);
+        self.port.close();
+    }
+}
+
+impl<T: Post + 'static> Future for Receiver<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Result<T>> {
+        let mut state = self.state.borrow_mut();
+        let Some(message) = state.message.clone() else {
+            state.waker = Some(context.waker().clone());
+            return Poll::Pending;
+        };
+        Poll::Ready(
+            match js_sys::Reflect::get(&message, &"value".into())
+                .ok()
+                .filter(|_| message.is_object())
+            {
+                Some(value) => post::from_js(value).map_err(error::serialization),
+                None => Err(Error::Killed),
+            },
+        )
+    }
+}
+
+impl<T> AsJs for Receiver<T> {
+    /// A receiver can only be posted before its value arrives, as a
+    /// value it has already received can't be sent on.
+    fn to_js(&self) -> Result<JsValue, JsValue> {
+        if self.state.borrow().message.is_some() {
+            return Err(
+                js_sys::Error::new("a `Receiver` whose value has arrived can't be posted").into(),
+            );
+        }
+        Ok(self.port.clone().into())
+    }
+
+    fn from_js(value: JsValue) -> Result<Self, JsValue> {
+        Ok(Self::new(value.dyn_into().map_err(|_| {
+            js_sys::TypeError::new("expected a posted `Receiver`")
+        })?))
+    }
+}
+
+impl<T> Post for Receiver<T> {
+    fn transferables(&self) -> js_sys::Array {
+        std::iter::once(&self.port).collect()
+    }
+}
@@ -0,0 +1,219 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Pipelines of stages, each running on a thread of its own and
+//! connected to the next by a bounded [`channel`].
+//!
+//! A stage waits when the next stage's channel is full, so a slow
+//! stage holds up the stages before it rather than letting items pile
+//! up.  When the source's [`Sender`](channel::Sender)s are dropped,
+//! each stage finishes its items and then ends, in turn.
+//!
+//! ```no_run
+//! # async fn f() -> web_thread::Result<()> {
+//! use futures::{SinkExt as _, StreamExt as _};
+//!
+//! let (mut source, pipeline) = web_thread::pipeline::pipeline::<u32>(16)?;
+//! let mut squares = pipeline
+//!     .filter(async |x: &u32| x % 2 == 0)?
+//!     .map(async |x: u32| x * x)?;
+//! for x in 0..10 {
+//!     source.send(x).await?;
+//! }
+//! drop(source);
+//! while let Some(square) = squares.next().await {
+//!     let square = square?;
+//! }
+//! # Ok(()) }
+//! ```
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{FutureExt as _, SinkExt as _, StreamExt as _, future};
+
+use super::{AsJs, JsValue, Post, Result, Task, Thread, channel, js_sys};
+
+/// Start a pipeline, returning the sender that feeds it and the
+/// pipeline, whose channels hold up to `capacity` items.
+///
+/// # Errors
+///
+/// If the source's channel could not be created.
+///
+/// # Panics
+///
+/// If `capacity` is zero.
+pub fn pipeline<T: Post + 'static>(capacity: usize) -> Result<(channel::Sender<T>, Pipeline<T>)> {
+    let (sender, output) = channel::channel(capacity)?;
+    Ok((
+        sender,
+        Pipeline {
+            threads: Vec::new(),
+            tasks: Vec::new(),
+            output,
+            capacity,
+        },
+    ))
+}
+
+/// A pipeline whose last stage outputs `T`s, which is a
+/// [`Stream`](futures::Stream) of them.  Its threads are destroyed
+/// when it is dropped.
+///
+/// The stream fails if a stage fails, for example by panicking.
+pub struct Pipeline<T> {
+    threads: Vec<Thread>,
+    tasks: Vec<future::Fuse<Task<()>>>,
+    output: channel::Receiver<T>,
+    capacity: usize,
+}
+
+/// The channel ends of a stage.
+struct Ends<I, O> {
+    input: channel::Receiver<I>,
+    output: channel::Sender<O>,
+}
+
+impl<I, O> AsJs for Ends<I, O> {
+    fn to_js(&self) -> Result<JsValue, JsValue> {
+        Ok(js_sys::Array::of2(&self.input.to_js()?, &self.output.to_js()?).into())
+    }
+
+    fn from_js(value: JsValue) -> Result<Self, JsValue> {
+        let ends = js_sys::Array::from(&value);
+        Ok(Self {
+            input: channel::Receiver::from_js(ends.get(0))?,
+            output: channel::Sender::from_js(ends.get(1))?,
+        })
+    }
+}
+
+impl<I, O> Post for Ends<I, O> {
+    fn transferables(&self) -> js_sys::Array {
+        self.input
+            .transferables()
+            .concat(&self.output.transferables())
+    }
+}
+
+impl<T: Post + 'static> Pipeline<T> {
+    /// Add a stage that passes on the outputs of `f` for each item
+    /// that are `Some`.
+    ///
+    /// # Errors
+    ///
+    /// If the stage's thread or channel could not be created.
+    pub fn filter_map<U: Post + 'static>(
+        self,
+        mut f: impl AsyncFnMut(T) -> Option<U> + Send + 'static,
+    ) -> Result<Pipeline<U>> {
+        let Self {
+            mut threads,
+            mut tasks,
+            output: input,
+            capacity,
+        } = self;
+        let thread = Thread::try_new()?;
+        let (output, next) = channel::channel(capacity)?;
+        let task = thread.run(
+            Ends { input, output },
+            move |Ends {
+                      mut input,
+                      mut output,
+                  }| async move {
+                while let Some(item) = input.next().await {
+                    let item = match item {
+                        Ok(item) => item,
+                        Err(error) => {
+                            web_sys::console::error_1(
+                                &format!(
+                                    "[web-thread] pipeline stage received a malformed item: {error}"
+                                )
+                                .into(),
+                            );
+                            continue;
+                        }
+                    };
+                    if let Some(item) = f(item).await
+                        && output.send(item).await.is_err()
+                    {
+                        break;
+                    }
+                }
+            },
+        );
+        threads.push(thread);
+        tasks.push(task.fuse());
+        Ok(Pipeline {
+            threads,
+            tasks,
+            output: next,
+            capacity,
+        })
+    }
+
+    /// Add a stage that passes on the output of `f` for each item.
+    ///
+    /// # Errors
+    ///
+    /// If the stage's thread or channel could not be created.
+    pub fn map<U: Post + 'static>(
+        self,
+        mut f: impl AsyncFnMut(T) -> U + Send + 'static,
+    ) -> Result<Pipeline<U>> {
+        self.filter_map(async move |item| Some(f(item).await))
+    }
+
+    /// Add a stage that passes on the items for which `predicate` is
+    /// true.
+    ///
+    /// # Errors
+    ///
+    /// If the stage's thread or channel could not be created.
+    pub fn filter(
+        self,
+        mut predicate: impl AsyncFnMut(&T) -> bool + Send + 'static,
+    ) -> Result<Pipeline<T>> {
+        self.filter_map(async move |item| predicate(&item).await.then_some(item))
+    }
+
+    /// Add a final stage that consumes each item with `f`, and wait for
+    /// the pipeline to end.
+    ///
+    /// # Errors
+    ///
+    /// If the stage's thread or channel could not be created, or a
+    /// stage failed.
+    pub async fn sink(self, mut f: impl AsyncFnMut(T) + Send + 'static) -> Result<()> {
+        let mut done = self.filter_map(async move |item| {
+            f(item).await;
+            None::<()>
+        })?;
+        while let Some(outcome) = done.next().await {
+            outcome?;
+        }
+        Ok(())
+    }
+
+    /// The number of stages.
+    #[must_use]
+    pub fn stages(&self) -> usize {
+        self.threads.len()
+    }
+}
+
+impl<T: Post + 'static> futures::Stream for Pipeline<T> {
+    type Item = Result<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Option<Result<T>>> {
+        for task in &mut self.tasks {
+            if let Poll::Ready(Err(error)) = task.poll_unpin(context) {
+                return Poll::Ready(Some(Err(error)));
+            }
+        }
+        self.output.poll_next_unpin(context)
+    }
+}
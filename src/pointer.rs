@@ -0,0 +1,45 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Pointers passed through JavaScript, which holds integers exactly
+//! only up to 2^53 as numbers.  Thin pointers on `wasm32` fit in a
+//! number; on `wasm64` (memory64) they are passed as `BigInt`s.
+
+use super::JsValue;
+
+/// Represent a thin pointer in JavaScript.
+pub fn to_js<T>(pointer: *mut T) -> JsValue {
+    let address = pointer.expose_provenance();
+    #[cfg(target_pointer_width = "64")]
+    {
+        JsValue::from(address as u64)
+    }
+    #[cfg(not(target_pointer_width = "64"))]
+    {
+        JsValue::from(u32::try_from(address).expect("pointers should fit in 32 bits"))
+    }
+}
+
+/// Recover a pointer represented with [`to_js`].
+///
+/// # Panics
+///
+/// If `value` isn't a pointer represented with [`to_js`].
+pub fn from_js<T>(value: &JsValue) -> *mut T {
+    std::ptr::with_exposed_provenance_mut(address_from_js(value))
+}
+
+#[cfg(target_pointer_width = "64")]
+fn address_from_js(value: &JsValue) -> usize {
+    usize::try_from(u64::try_from(value.clone()).expect("pointers should be `BigInt`s"))
+        .expect("pointers should fit in `usize`")
+}
+
+#[cfg(not(target_pointer_width = "64"))]
+fn address_from_js(value: &JsValue) -> usize {
+    // We know this doesn't truncate or lose sign as the `f64` is a
+    // representation of a 32-bit pointer.
+    #![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+
+    value.as_f64().expect("pointers should be numbers") as usize
+}
@@ -1,7 +1,9 @@
 // Copyright (c) Zefchain Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use super::{JsValue, js_sys};
+use std::any::{Any, TypeId};
+
+use super::{JsValue, js_sys, wasm_bindgen, wasm_bindgen::JsCast as _};
 
 /// Objects that can be sent via `postMessage`.  A type that is `Post`
 /// supports being serialized into a JavaScript object that can be
@@ -55,28 +57,130 @@ impl PostExt for web_sys::Worker {
     }
 }
 
-/// A serializable (JS-friendly) representation of a message plus its
-/// transferables.
-#[derive(serde::Serialize)]
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_name = "structuredClone", catch)]
+    fn structured_clone(value: &JsValue, options: &JsValue) -> Result<JsValue, JsValue>;
+}
+
+/// Move `message` and its transferables into a copy, as `postMessage`
+/// would, if there are any transferables.  Values such as channel
+/// ends close their ports when dropped, so this must be done before
+/// the value `message` came from is dropped, whereas the message may
+/// be posted later, or not at all when running on the current thread.
+pub fn detach(
+    message: JsValue,
+    transfer: js_sys::Array,
+) -> Result<(JsValue, js_sys::Array), JsValue> {
+    if transfer.length() == 0 {
+        return Ok((message, transfer));
+    }
+    let options = js_sys::Object::new();
+    js_sys::Reflect::set(&options, &"transfer".into(), &transfer)?;
+    let copy: js_sys::Array =
+        structured_clone(&js_sys::Array::of2(&message, &transfer), &options)?.unchecked_into();
+    Ok((copy.get(0), copy.get(1).unchecked_into()))
+}
+
+/// A message plus its transferables, as sent back from a worker.
 pub struct Postable {
-    #[serde(with = "serde_wasm_bindgen::preserve")]
     message: JsValue,
-    #[serde(with = "serde_wasm_bindgen::preserve")]
     transfer: js_sys::Array,
 }
 
 impl Postable {
-    pub fn new(message: impl Post) -> Result<Self, JsValue> {
+    pub fn new<T: Post + 'static>(message: T) -> Result<Self, JsValue> {
         // While not syntactically consumed, the use of `postMessage`
         // may leave `Context` in an invalid state (setting
         // transferred JavaScript values to `undefined`).
         #![allow(clippy::needless_pass_by_value)]
 
-        Ok(Self {
-            message: message.to_js()?,
-            transfer: message.transferables(),
-        })
+        let (message, transfer) = detach(to_js(&message)?, message.transferables())?;
+        Ok(Self { message, transfer })
+    }
+
+    /// The message, for delivery without `postMessage`.
+    pub fn into_message(self) -> JsValue {
+        self.message
+    }
+
+    /// The message and its transferables, for `postMessage`.
+    pub fn into_parts(self) -> (JsValue, js_sys::Array) {
+        (self.message, self.transfer)
+    }
+
+    /// The message and its transferables as a JavaScript object, for
+    /// `worker.js`.
+    pub fn into_js(self) -> JsValue {
+        let object = js_sys::Object::new();
+        // setting properties on a fresh object can't fail
+        let _ = js_sys::Reflect::set(&object, &"message".into(), &self.message);
+        let _ = js_sys::Reflect::set(&object, &"transfer".into(), &self.transfer);
+        object.into()
+    }
+}
+
+/// Move `value` to type `T`, if it is of that type.
+fn cast<T: 'static, U: 'static>(value: U) -> Option<T> {
+    let mut value = Some(value);
+    (&mut value as &mut dyn Any)
+        .downcast_mut::<Option<T>>()
+        .and_then(Option::take)
+}
+
+/// Like [`AsJs::to_js`], but converting `()`, small integers and
+/// strings directly rather than through `serde`.
+pub fn to_js<T: Post + 'static>(value: &T) -> Result<JsValue, JsValue> {
+    let any = value as &dyn Any;
+    macro_rules! direct {
+        ($($type:ty),*) => {
+            $(if let Some(value) = any.downcast_ref::<$type>() {
+                return Ok(JsValue::from(*value));
+            })*
+        };
+    }
+    if any.is::<()>() {
+        return Ok(JsValue::UNDEFINED);
+    }
+    direct!(u8, u16, u32, i8, i16, i32);
+    if let Some(value) = any.downcast_ref::<String>() {
+        return Ok(JsValue::from_str(value));
+    }
+    value.to_js()
+}
+
+/// Like [`AsJs::from_js`], but converting `()`, small integers and
+/// strings directly rather than through `serde`.
+pub fn from_js<T: Post + 'static>(value: JsValue) -> Result<T, JsValue> {
+    // Casting saturates, and any number that doesn't fit in an `i64`
+    // doesn't fit in the target type either.
+    #![allow(clippy::cast_possible_truncation)]
+
+    macro_rules! direct {
+        ($($type:ty),*) => {
+            $(if TypeId::of::<T>() == TypeId::of::<$type>() {
+                return value
+                    .as_f64()
+                    .filter(|number| number.fract() == 0.)
+                    .and_then(|number| <$type>::try_from(number as i64).ok())
+                    .and_then(cast)
+                    .ok_or_else(|| js_sys::TypeError::new(
+                        concat!("expected a `", stringify!($type), "`")
+                    ).into());
+            })*
+        };
+    }
+    if TypeId::of::<T>() == TypeId::of::<()>() {
+        return cast(()).ok_or(JsValue::UNDEFINED);
+    }
+    direct!(u8, u16, u32, i8, i16, i32);
+    if TypeId::of::<T>() == TypeId::of::<String>() {
+        return value
+            .as_string()
+            .and_then(cast)
+            .ok_or_else(|| js_sys::TypeError::new("expected a string").into());
     }
+    T::from_js(value)
 }
 
 /// An object-safe version of
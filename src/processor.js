@@ -0,0 +1,31 @@
+// @ts-check
+
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// This must be made available by the library consumer, since we don't
+// know where it is yet.
+import * as wasm from 'web-thread:wasm-shim';
+
+class web_thread$Processor extends AudioWorkletProcessor {
+  constructor(options) {
+    super();
+    const [module, memory, processor] = options.processorOptions;
+    wasm.initSync({ module, memory });
+    this.processor = processor;
+    this.port.onmessage = event => {
+      if (this.processor !== null)
+        wasm.__web_thread_audio_receive(this.processor, event.data);
+    };
+  }
+
+  process(inputs, outputs, parameters) {
+    if (this.processor === null) return false;
+    const keepAlive = wasm.__web_thread_audio_process(this.processor, inputs, outputs, parameters);
+    // the processor has been dropped
+    if (!keepAlive) this.processor = null;
+    return keepAlive;
+  }
+}
+
+registerProcessor('web-thread', web_thread$Processor);
@@ -0,0 +1,118 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Code waiting to be run on a worker.  Workers are sent an id rather
+//! than a pointer to the code, and take the code from here, so that
+//! it is run at most once and is dropped if its thread dies first.
+//!
+//! The code is kept in a slab whose slots are reused across tasks,
+//! rather than allocating for every task.  An id names a slot and the
+//! generation of the slot's use, so that the id of code dropped with
+//! its thread can't take code later put in the same slot.
+
+use std::sync::{
+    Mutex,
+    atomic::{AtomicU32, Ordering},
+};
+
+use super::Code;
+
+/// The bits of an id that name its slot, leaving the rest for the
+/// generation.
+const INDEX_BITS: u32 = 20;
+const INDEX_MASK: u32 = (1 << INDEX_BITS) - 1;
+
+struct Entry {
+    thread: u32,
+    code: Code,
+}
+
+#[derive(Default)]
+struct Slot {
+    generation: u32,
+    entry: Option<Entry>,
+}
+
+struct Slab {
+    slots: Vec<Slot>,
+    /// The indices of the empty slots.
+    free: Vec<u32>,
+}
+
+impl Slab {
+    /// The index of the slot named by `id`, if it is still in the
+    /// generation `id` was given out in.
+    fn index(&self, id: u32) -> Option<u32> {
+        let index = id & INDEX_MASK;
+        let slot = self.slots.get(index as usize)?;
+        (slot.generation == id >> INDEX_BITS).then_some(index)
+    }
+
+    fn remove(&mut self, index: u32) -> Option<Entry> {
+        let slot = &mut self.slots[index as usize];
+        let entry = slot.entry.take()?;
+        slot.generation = (slot.generation + 1) & (u32::MAX >> INDEX_BITS);
+        self.free.push(index);
+        Some(entry)
+    }
+}
+
+static PENDING: Mutex<Slab> = Mutex::new(Slab {
+    slots: Vec::new(),
+    free: Vec::new(),
+});
+
+/// A new id for a thread whose code is registered.
+pub fn thread_id() -> u32 {
+    static NEXT: AtomicU32 = AtomicU32::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Register code to be run on `thread`, returning its id.
+///
+/// # Panics
+///
+/// If more than 2²⁰ tasks are waiting to be run.
+pub fn insert(thread: u32, code: Code) -> u32 {
+    let mut pending = PENDING.lock().unwrap();
+    let index = pending.free.pop().unwrap_or_else(|| {
+        let index = u32::try_from(pending.slots.len())
+            .ok()
+            .filter(|index| *index <= INDEX_MASK)
+            .expect("too many tasks waiting to be run");
+        pending.slots.push(Slot::default());
+        index
+    });
+    let slot = &mut pending.slots[index as usize];
+    slot.entry = Some(Entry { thread, code });
+    slot.generation << INDEX_BITS | index
+}
+
+/// Take the code registered as `id`, if it hasn't already been taken
+/// or dropped with its thread.
+pub fn take(id: u32) -> Option<Code> {
+    let mut pending = PENDING.lock().unwrap();
+    let index = pending.index(id)?;
+    pending.remove(index).map(|entry| entry.code)
+}
+
+/// Drop the code that `thread` hasn't yet taken.
+pub fn remove_thread(thread: u32) {
+    let mut pending = PENDING.lock().unwrap();
+    let indices: Vec<u32> = (0..)
+        .zip(&pending.slots)
+        .filter(|(_, slot)| {
+            slot.entry
+                .as_ref()
+                .is_some_and(|entry| entry.thread == thread)
+        })
+        .map(|(index, _)| index)
+        .collect();
+    let removed: Vec<Entry> = indices
+        .into_iter()
+        .filter_map(|index| pending.remove(index))
+        .collect();
+    // dropping code may drop values that lock the registry again
+    drop(pending);
+    drop(removed);
+}
@@ -0,0 +1,324 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A single-producer, single-consumer ring buffer of bytes in shared
+//! memory, for streams of data such as audio, video or sensor
+//! readings at rates that posting a message per chunk can't keep up
+//! with.
+//!
+//! The buffer lives in the Wasm module's memory, which is a
+//! `SharedArrayBuffer`, so the [`Writer`] and [`Reader`] can be moved
+//! to different threads.  Each wakes the other when it has written or
+//! read, which across threads is done with `Atomics.notify` and
+//! `Atomics.waitAsync`.
+//!
+//! ```no_run
+//! # async fn f(thread: &web_thread::Thread) -> std::io::Result<()> {
+//! use futures::{AsyncReadExt as _, AsyncWriteExt as _};
+//!
+//! let (mut writer, mut reader) = web_thread::ring::channel(1 << 16);
+//! drop(thread.run((), move |()| async move {
+//!     writer.write_all(b"sensor readings").await.unwrap();
+//! }));
+//! let mut readings = Vec::new();
+//! reader.read_to_end(&mut readings).await?;
+//! # Ok(()) }
+//! ```
+
+use std::{
+    cell::UnsafeCell,
+    io,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+};
+
+use futures::task::AtomicWaker;
+
+struct Shared {
+    buffer: Box<[UnsafeCell<u8>]>,
+    /// The number of bytes read so far, wrapping around, written only
+    /// by the reader.
+    read: AtomicUsize,
+    /// The number of bytes written so far, wrapping around, written
+    /// only by the writer.
+    written: AtomicUsize,
+    writer_closed: AtomicBool,
+    reader_closed: AtomicBool,
+    reader_waker: AtomicWaker,
+    writer_waker: AtomicWaker,
+}
+
+// The bytes between `read` and `written` belong to the reader, and
+// the rest to the writer, so they never access the same bytes at once.
+unsafe impl Sync for Shared {}
+
+impl Shared {
+    fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// The buffer as a pointer, for copying to and from.
+    fn ptr(&self) -> *mut u8 {
+        UnsafeCell::raw_get(self.buffer.as_ptr())
+    }
+
+    /// The number of bytes written but not yet read.  The counters
+    /// wrap around, but are never more than the capacity apart.
+    fn len(&self) -> usize {
+        self.written
+            .load(Ordering::Acquire)
+            .wrapping_sub(self.read.load(Ordering::Acquire))
+    }
+
+    /// The two parts of the buffer, from `offset` and wrapping around,
+    /// that make up `len` bytes, as offsets and lengths.
+    fn segments(&self, offset: usize, len: usize) -> [(usize, usize); 2] {
+        let first = len.min(self.capacity() - offset);
+        [(offset, first), (0, len - first)]
+    }
+}
+
+/// Create a ring buffer that holds `capacity` bytes, returning its
+/// writing and reading ends.
+///
+/// # Panics
+///
+/// If `capacity` is zero.
+#[must_use]
+pub fn channel(capacity: usize) -> (Writer, Reader) {
+    channel_from(capacity, 0)
+}
+
+/// Create a ring buffer whose counters start at `start`, so that they
+/// can be tested wrapping around.
+fn channel_from(capacity: usize, start: usize) -> (Writer, Reader) {
+    assert!(capacity > 0, "a ring buffer must hold at least one byte");
+    let shared = Arc::new(Shared {
+        buffer: (0..capacity).map(|_| UnsafeCell::new(0)).collect(),
+        read: AtomicUsize::new(start),
+        written: AtomicUsize::new(start),
+        writer_closed: AtomicBool::new(false),
+        reader_closed: AtomicBool::new(false),
+        reader_waker: AtomicWaker::new(),
+        writer_waker: AtomicWaker::new(),
+    });
+    (
+        Writer {
+            shared: shared.clone(),
+            offset: 0,
+        },
+        Reader { shared, offset: 0 },
+    )
+}
+
+/// The writing end of a ring buffer, which waits for room when the
+/// buffer is full.  Dropping or closing it ends the reader's stream
+/// once the reader has read what was written.
+pub struct Writer {
+    shared: Arc<Shared>,
+    /// Where in the buffer the next byte is written.
+    offset: usize,
+}
+
+/// The reading end of a ring buffer, which waits for bytes when the
+/// buffer is empty.  As a [`Stream`](futures::Stream), it yields all
+/// the bytes available at once.
+pub struct Reader {
+    shared: Arc<Shared>,
+    /// Where in the buffer the next byte is read from.
+    offset: usize,
+}
+
+impl Writer {
+    fn poll_room(&self, context: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let shared = &self.shared;
+        let room = || shared.capacity() - shared.len();
+        if shared.reader_closed.load(Ordering::Acquire) {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        if room() > 0 {
+            return Poll::Ready(Ok(room()));
+        }
+        shared.writer_waker.register(context.waker());
+        // check again, in case the reader read in the meantime
+        if shared.reader_closed.load(Ordering::Acquire) {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        match room() {
+            0 => Poll::Pending,
+            room => Poll::Ready(Ok(room)),
+        }
+    }
+}
+
+impl futures::AsyncWrite for Writer {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        context: &mut Context<'_>,
+        bytes: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if bytes.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let room = futures::ready!(self.poll_room(context))?;
+        let this = self.get_mut();
+        let shared = &this.shared;
+        let len = bytes.len().min(room);
+        let mut copied = 0;
+        for (offset, segment) in shared.segments(this.offset, len) {
+            // the reader doesn't touch these bytes until `written` is
+            // advanced past them
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    bytes[copied..].as_ptr(),
+                    shared.ptr().add(offset),
+                    segment,
+                );
+            }
+            copied += segment;
+        }
+        this.offset = (this.offset + len) % shared.capacity();
+        let written = shared.written.load(Ordering::Relaxed);
+        shared
+            .written
+            .store(written.wrapping_add(len), Ordering::Release);
+        shared.reader_waker.wake();
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _context: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _context: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.shared.writer_closed.store(true, Ordering::Release);
+        self.shared.reader_waker.wake();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        self.shared.writer_closed.store(true, Ordering::Release);
+        self.shared.reader_waker.wake();
+    }
+}
+
+impl Reader {
+    /// Wait for bytes to read, returning how many there are, or zero
+    /// if the writer is gone and there will be no more.
+    fn poll_available(&self, context: &mut Context<'_>) -> Poll<usize> {
+        let shared = &self.shared;
+        // the writer closes after writing, so check first
+        let closed = || shared.writer_closed.load(Ordering::Acquire);
+        let available = || shared.len();
+        let is_closed = closed();
+        if available() > 0 || is_closed {
+            return Poll::Ready(available());
+        }
+        shared.reader_waker.register(context.waker());
+        // check again, in case the writer wrote in the meantime
+        let is_closed = closed();
+        match available() {
+            0 if !is_closed => Poll::Pending,
+            available => Poll::Ready(available),
+        }
+    }
+
+    /// Fill `bytes` with available bytes.
+    fn read(&mut self, bytes: &mut [u8]) {
+        let shared = &self.shared;
+        let mut copied = 0;
+        for (offset, segment) in shared.segments(self.offset, bytes.len()) {
+            // the writer doesn't touch these bytes until `read` is
+            // advanced past them
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    shared.ptr().add(offset),
+                    bytes[copied..].as_mut_ptr(),
+                    segment,
+                );
+            }
+            copied += segment;
+        }
+        self.offset = (self.offset + bytes.len()) % shared.capacity();
+        let read = shared.read.load(Ordering::Relaxed);
+        shared
+            .read
+            .store(read.wrapping_add(bytes.len()), Ordering::Release);
+        shared.writer_waker.wake();
+    }
+}
+
+impl futures::AsyncRead for Reader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        context: &mut Context<'_>,
+        bytes: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if bytes.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let len = futures::ready!(self.poll_available(context)).min(bytes.len());
+        self.read(&mut bytes[..len]);
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl futures::Stream for Reader {
+    type Item = Vec<u8>;
+
+    fn poll_next(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Option<Vec<u8>>> {
+        match futures::ready!(self.poll_available(context)) {
+            0 => Poll::Ready(None),
+            len => {
+                let mut bytes = vec![0; len];
+                self.read(&mut bytes);
+                Poll::Ready(Some(bytes))
+            }
+        }
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        self.shared.reader_closed.store(true, Ordering::Release);
+        self.shared.writer_waker.wake();
+    }
+}
+
+#[test]
+fn bytes_arrive_in_order() {
+    use futures::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    let (mut writer, mut reader) = channel(7);
+    let bytes: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+    let expected = bytes.clone();
+    let writing = std::thread::spawn(move || {
+        futures::executor::block_on(writer.write_all(&bytes)).unwrap();
+    });
+    let mut received = Vec::new();
+    futures::executor::block_on(reader.read_to_end(&mut received)).unwrap();
+    writing.join().unwrap();
+    assert_eq!(received, expected);
+}
+
+#[test]
+fn counters_wrap_around() {
+    use futures::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    let (mut writer, mut reader) = channel_from(7, usize::MAX - 100);
+    let bytes: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+    let expected = bytes.clone();
+    let writing = std::thread::spawn(move || {
+        futures::executor::block_on(writer.write_all(&bytes)).unwrap();
+    });
+    let mut received = Vec::new();
+    futures::executor::block_on(reader.read_to_end(&mut received)).unwrap();
+    writing.join().unwrap();
+    assert_eq!(received, expected);
+}
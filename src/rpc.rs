@@ -0,0 +1,120 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Functions registered by name, for calling on any thread, or from
+//! JavaScript, without sending closures.
+//!
+//! The registry is kept in shared memory, so a function registered
+//! once, on any thread, can be called on every thread.
+//!
+//! ```no_run
+//! # async fn f(thread: &web_thread::Thread) -> web_thread::Result<()> {
+//! web_thread::rpc::register("double", async |x: u32| x * 2);
+//! let four: u32 = web_thread::rpc::call("double", 2_u32).await?;
+//! let six: u32 = thread.call("double", 3_u32).await?;
+//! # Ok(()) }
+//! ```
+//!
+//! From JavaScript, registered functions can be called with
+//! `web_thread_call(name, args)`, exported from the Wasm module, which
+//! returns a promise of the result.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use super::{
+    Code, JsTask, JsValue, Post, Result, Task, Thread, error, js_sys, post, post::Postable,
+    wasm_bindgen,
+};
+
+type Function = Arc<dyn Fn(JsValue) -> JsTask + Send + Sync>;
+
+static FUNCTIONS: Mutex<BTreeMap<String, Function>> = Mutex::new(BTreeMap::new());
+
+/// Register `function` as `name`, replacing any function registered
+/// as `name` before.
+///
+/// # Panics
+///
+/// If the registry's lock has been poisoned by a panic.
+pub fn register<Args: Post + 'static, R: Post + 'static>(
+    name: impl Into<String>,
+    function: impl AsyncFn(Args) -> R + Send + Sync + 'static,
+) {
+    let function = Arc::new(function);
+    let function: Function = Arc::new(move |args| {
+        let function = function.clone();
+        Box::pin(async move { Postable::new(function(post::from_js(args)?).await) })
+    });
+    FUNCTIONS.lock().unwrap().insert(name.into(), function);
+}
+
+/// Remove the function registered as `name`, returning whether there
+/// was one.
+///
+/// # Panics
+///
+/// If the registry's lock has been poisoned by a panic.
+pub fn unregister(name: &str) -> bool {
+    FUNCTIONS.lock().unwrap().remove(name).is_some()
+}
+
+/// Run the function registered as `name` with `args`, failing if
+/// there is none.
+fn invoke(name: &str, args: JsValue) -> JsTask {
+    let Some(function) = FUNCTIONS.lock().unwrap().get(name).cloned() else {
+        let error = js_sys::Error::new(&format!("no function is registered as `{name}`"));
+        return Box::pin(async move { Err(error.into()) });
+    };
+    function(args)
+}
+
+/// Call the function registered as `name` with `args` on the current
+/// thread.
+///
+/// # Errors
+///
+/// If no function is registered as `name`, or the arguments or result
+/// could not be converted.
+pub async fn call<Args: Post + 'static, R: Post + 'static>(name: &str, args: Args) -> Result<R> {
+    let args = post::to_js(&args).map_err(error::serialization)?;
+    let result = invoke(name, args).await.map_err(error::from_task)?;
+    post::from_js(result.into_message()).map_err(error::serialization)
+}
+
+impl Thread {
+    /// Call the function registered as `name` (see [`rpc::register`](crate::rpc::register))
+    /// with `args` on this thread.
+    pub fn call<Args: Post + 'static, R: Post + 'static>(
+        &self,
+        name: impl Into<String>,
+        args: Args,
+    ) -> Task<R> {
+        // While not syntactically consumed, the use of `postMessage`
+        // here may leave `args` in an invalid state (setting
+        // transferred JavaScript values to `undefined`).
+        #![allow(clippy::needless_pass_by_value)]
+
+        let name = name.into();
+        let transfer = args.transferables();
+        self.submit(
+            post::to_js(&args),
+            transfer,
+            Code(Box::new(move |args| invoke(&name, args))),
+        )
+    }
+}
+
+/// Call the function registered as `name` with `args`, for
+/// JavaScript.
+///
+/// # Errors
+///
+/// If no function is registered as `name`, or the arguments or result
+/// could not be converted.
+#[wasm_bindgen]
+pub async fn web_thread_call(name: String, args: JsValue) -> Result<JsValue, JsValue> {
+    Ok(invoke(&name, args).await?.into_message())
+}
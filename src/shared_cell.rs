@@ -0,0 +1,162 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! State in shared memory that many threads read and update, such as
+//! configuration or a model that workers follow as it changes.
+
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, AtomicU64, Ordering},
+    },
+};
+
+use super::{
+    AsJs, JsValue, Post, js_sys,
+    sync::{Notify, RwLock},
+};
+
+struct Inner<T> {
+    /// The value, behind a lock that is waited for asynchronously, as
+    /// the main thread can't block.
+    value: RwLock<T>,
+    /// The number of updates so far.
+    version: AtomicU64,
+    updated: Notify,
+}
+
+/// Handles to cells that are being posted, by id, so that a handle can
+/// only be received once, and can't be forged from JavaScript.
+static POSTED: Mutex<BTreeMap<u32, Box<dyn Any + Send>>> = Mutex::new(BTreeMap::new());
+
+/// A cell in shared memory whose value many threads can read and
+/// update, and wait for changes to.
+///
+/// Handles to a cell can be cloned, and are [`Post`], so they can be
+/// passed to tasks on other threads as (part of) their context.  Each
+/// handle keeps track of which changes it has seen.  A handle that is
+/// posted but never received, as when its task is dropped before it
+/// starts, is leaked.
+///
+/// The value is read and updated under an asynchronous
+/// [`RwLock`](crate::sync::RwLock), so an update holds up the tasks
+/// of other threads reading or updating it without blocking their
+/// threads.  The functions given to [`SharedCell::with`] and
+/// [`SharedCell::update`] are synchronous, so they can't wait on the
+/// cell they are called on.
+pub struct SharedCell<T> {
+    inner: Arc<Inner<T>>,
+    /// The version of the last value seen.
+    seen: u64,
+}
+
+impl<T> Clone for SharedCell<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            seen: self.seen,
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> SharedCell<T> {
+    /// Create a cell holding `value`.
+    pub fn new(value: T) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                value: RwLock::new(value),
+                version: AtomicU64::new(0),
+                updated: Notify::new(),
+            }),
+            seen: 0,
+        }
+    }
+
+    /// A copy of the value.
+    pub async fn get(&self) -> T
+    where
+        T: Clone,
+    {
+        self.inner.value.read().await.clone()
+    }
+
+    /// Call `f` with the value.
+    pub async fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&*self.inner.value.read().await)
+    }
+
+    /// Replace the value, waking the handles waiting for a change.
+    pub async fn set(&self, value: T) {
+        self.update(|current| *current = value).await;
+    }
+
+    /// Update the value in place, waking the handles waiting for a
+    /// change.
+    pub async fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let result = f(&mut *self.inner.value.write().await);
+        self.inner.version.fetch_add(1, Ordering::Release);
+        self.inner.updated.notify_waiters();
+        result
+    }
+
+    /// Whether the value has changed since this handle last saw it.
+    #[must_use]
+    pub fn has_changed(&self) -> bool {
+        self.inner.version.load(Ordering::Acquire) != self.seen
+    }
+
+    /// Wait for the value to change from the one this handle last saw,
+    /// and mark the change seen.  Changes in the meantime are
+    /// coalesced.
+    pub async fn changed(&mut self) {
+        loop {
+            // wait from before checking, so as not to miss a change in
+            // the meantime
+            let updated = self.inner.updated.notified();
+            let version = self.inner.version.load(Ordering::Acquire);
+            if version != self.seen {
+                self.seen = version;
+                return;
+            }
+            updated.await;
+        }
+    }
+
+    /// A stream of the value each time it changes, starting with any
+    /// change this handle hasn't seen.
+    pub fn changes(self) -> impl futures::Stream<Item = T>
+    where
+        T: Clone,
+    {
+        futures::stream::unfold(self, async |mut cell| {
+            cell.changed().await;
+            let value = cell.get().await;
+            Some((value, cell))
+        })
+    }
+}
+
+impl<T: Send + Sync + 'static> AsJs for SharedCell<T> {
+    fn to_js(&self) -> Result<JsValue, JsValue> {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        let id = NEXT.fetch_add(1, Ordering::Relaxed);
+        POSTED.lock().unwrap().insert(id, Box::new(self.clone()));
+        Ok(id.into())
+    }
+
+    fn from_js(value: JsValue) -> Result<Self, JsValue> {
+        // The id came from `to_js`, so it is a `u32`.
+        #![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+
+        value
+            .as_f64()
+            .and_then(|id| POSTED.lock().unwrap().remove(&(id as u32)))
+            .and_then(|cell| cell.downcast().ok())
+            .map(|cell| *cell)
+            .ok_or_else(|| js_sys::TypeError::new("expected a posted `SharedCell`").into())
+    }
+}
+
+impl<T: Send + Sync + 'static> Post for SharedCell<T> {}
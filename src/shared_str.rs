@@ -0,0 +1,110 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Strings that stay in shared memory when they are posted.  Only an
+//! id is posted, naming the string in a table that the receiver takes
+//! it from, so strings can't be forged from JavaScript.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
+};
+
+use super::{AsJs, JsValue, Post, js_sys};
+
+static POSTED: Mutex<BTreeMap<u32, Arc<str>>> = Mutex::new(BTreeMap::new());
+
+/// A string that is passed to other threads without being copied, as
+/// a [`Post`] context or output.
+///
+/// A `String` is converted to a JavaScript string (as UTF-16) and back
+/// again every time it is posted.  A `SharedStr` instead stays where it
+/// is in the Wasm module's shared memory, and is shared with the
+/// thread it is posted to, making it cheap to post large texts.
+///
+/// A `SharedStr` that is posted but never received, as when its task
+/// is dropped before it starts, is leaked.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SharedStr(Arc<str>);
+
+impl SharedStr {
+    /// Share `string`.
+    pub fn new(string: impl Into<Arc<str>>) -> Self {
+        Self(string.into())
+    }
+
+    /// The string.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::ops::Deref for SharedStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for SharedStr {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::borrow::Borrow<str> for SharedStr {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for SharedStr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<String> for SharedStr {
+    fn from(string: String) -> Self {
+        Self::new(string)
+    }
+}
+
+impl From<&str> for SharedStr {
+    fn from(string: &str) -> Self {
+        Self::new(string)
+    }
+}
+
+impl From<SharedStr> for String {
+    fn from(string: SharedStr) -> Self {
+        string.0.as_ref().into()
+    }
+}
+
+impl AsJs for SharedStr {
+    fn to_js(&self) -> Result<JsValue, JsValue> {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        let id = NEXT.fetch_add(1, Ordering::Relaxed);
+        POSTED.lock().unwrap().insert(id, self.0.clone());
+        Ok(id.into())
+    }
+
+    fn from_js(value: JsValue) -> Result<Self, JsValue> {
+        // The id came from `to_js`, so it is a `u32`.
+        #![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+
+        value
+            .as_f64()
+            .and_then(|id| POSTED.lock().unwrap().remove(&(id as u32)))
+            .map(Self)
+            .ok_or_else(|| js_sys::TypeError::new("expected a posted `SharedStr`").into())
+    }
+}
+
+impl Post for SharedStr {}
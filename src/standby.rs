@@ -0,0 +1,56 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Workers spawned ahead of time, so that spawning a thread needn't
+//! wait for a worker to start.
+
+use std::{cell::RefCell, collections::VecDeque};
+
+use super::{Builder, Client};
+
+#[derive(Default)]
+struct Standby {
+    count: usize,
+    clients: VecDeque<Client>,
+}
+
+thread_local! {
+    static STANDBY: RefCell<Standby> = RefCell::default();
+}
+
+/// Keep `count` workers spawned and starting up in the background,
+/// for [`Thread::new`](crate::Thread::new) and threads spawned with
+/// the default [`Builder`] to take instead of spawning workers of
+/// their own.  A worker taken from standby is replaced in the
+/// background.
+///
+/// Standby workers are kept by the current thread, and a count of
+/// zero, the default, destroys them.
+pub fn keep_standby(count: usize) {
+    let surplus = STANDBY.with_borrow_mut(|standby| {
+        standby.count = count;
+        standby.clients.split_off(count.min(standby.clients.len()))
+    });
+    for client in surplus {
+        client.destroy();
+    }
+    replenish();
+}
+
+/// Take a standby worker, if there is one, replacing it in the
+/// background.
+pub fn take() -> Option<Client> {
+    let client = STANDBY.with_borrow_mut(|standby| standby.clients.pop_front())?;
+    wasm_bindgen_futures::spawn_local(async { replenish() });
+    Some(client)
+}
+
+fn replenish() {
+    while STANDBY.with_borrow(|standby| standby.clients.len() < standby.count) {
+        let Ok(client) = Builder::default().client() else {
+            // the failure is reported when a thread is spawned
+            return;
+        };
+        STANDBY.with_borrow_mut(|standby| standby.clients.push_back(client));
+    }
+}
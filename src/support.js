@@ -0,0 +1,12 @@
+// @ts-check
+
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// The `Cross-Origin-Embedder-Policy` header the current document or
+// worker script is served with, which isn't otherwise visible to
+// scripts.
+export async function web_thread$embedderPolicy() {
+  const response = await fetch(self.location.href, { method: 'HEAD', cache: 'no-store' });
+  return response.headers.get('Cross-Origin-Embedder-Policy');
+}
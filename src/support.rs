@@ -0,0 +1,169 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{JsValue, Result, error, js_sys, wasm_bindgen};
+
+#[wasm_bindgen(module = "/src/support.js")]
+extern "C" {
+    #[wasm_bindgen(js_name = "web_thread$embedderPolicy", catch)]
+    async fn fetch_embedder_policy() -> Result<JsValue, JsValue>;
+}
+
+/// What the current context offers for running threads, for deciding
+/// up front between threaded and single-threaded code.
+///
+/// Its `Display` implementation explains what is missing and how to
+/// fix it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+// the flags are independent capabilities, not states
+#[allow(clippy::struct_excessive_bools)]
+pub struct Support {
+    /// Whether workers can be created, which they can't from a
+    /// service worker, nor under Node.js without the `node` feature.
+    pub workers: bool,
+    /// Whether the page is [cross-origin
+    /// isolated](https://developer.mozilla.org/en-US/docs/Web/API/Window/crossOriginIsolated),
+    /// which browsers require before they make `SharedArrayBuffer`
+    /// available.
+    pub cross_origin_isolated: bool,
+    /// Whether `SharedArrayBuffer` is available, without which memory
+    /// can't be shared with workers.  Electron's `SharedArrayBuffer`
+    /// feature switch and Chrome's enterprise policies make it
+    /// available without cross-origin isolation.
+    pub shared_array_buffer: bool,
+    /// Whether `Atomics.waitAsync` is available.  It isn't required,
+    /// but without it waiting on another thread from the main thread
+    /// falls back to a slower polyfill.
+    pub wait_async: bool,
+}
+
+impl Support {
+    /// Check the current context.
+    #[must_use]
+    pub fn detect() -> Self {
+        let global = js_sys::global();
+        let has = |object: &JsValue, name: &str| {
+            js_sys::Reflect::has(object, &name.into()).unwrap_or(false)
+        };
+        Self {
+            workers: has(&global, "Worker") || (cfg!(feature = "node") && is_node(&global)),
+            // browsers report `crossOriginIsolated` only where it may
+            // be false, so a missing value is taken to mean isolation
+            cross_origin_isolated: js_sys::Reflect::get(&global, &"crossOriginIsolated".into())
+                .ok()
+                .and_then(|isolated| isolated.as_bool())
+                != Some(false),
+            shared_array_buffer: has(&global, "SharedArrayBuffer"),
+            wait_async: js_sys::Reflect::get(&global, &"Atomics".into())
+                .is_ok_and(|atomics| atomics.is_object() && has(&atomics, "waitAsync")),
+        }
+    }
+
+    /// Find out the
+    /// [`Cross-Origin-Embedder-Policy`](https://developer.mozilla.org/en-US/docs/Web/HTTP/Reference/Headers/Cross-Origin-Embedder-Policy)
+    /// the current document (or worker) is served with, by fetching
+    /// its headers again.
+    ///
+    /// Browsers that don't support a policy ignore it: in particular,
+    /// a page served with [`EmbedderPolicy::Credentialless`] that
+    /// isn't [cross-origin isolated](Support::cross_origin_isolated)
+    /// is in a browser that doesn't support `credentialless`, such as
+    /// Safari, and needs `require-corp` instead.
+    ///
+    /// # Errors
+    ///
+    /// If the headers could not be fetched.
+    pub async fn embedder_policy() -> Result<EmbedderPolicy> {
+        let header = fetch_embedder_policy().await.map_err(error::other)?;
+        Ok(EmbedderPolicy::parse(
+            header.as_string().as_deref().unwrap_or_default(),
+        ))
+    }
+
+    /// Whether [`Thread`](crate::Thread)s run their tasks on workers
+    /// rather than [falling back](crate::Thread::is_fallback) to the
+    /// current thread.
+    #[must_use]
+    pub fn is_supported(&self) -> bool {
+        self.workers && self.shared_array_buffer
+    }
+}
+
+/// A value of the `Cross-Origin-Embedder-Policy` header.  See
+/// [`Support::embedder_policy`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EmbedderPolicy {
+    /// No policy, which leaves the page without cross-origin
+    /// isolation.
+    #[default]
+    UnsafeNone,
+    /// Cross-origin resources must opt in to being loaded.
+    RequireCorp,
+    /// Cross-origin resources are loaded without credentials unless
+    /// they opt in.
+    Credentialless,
+}
+
+impl EmbedderPolicy {
+    /// Parse a header value, ignoring its parameters.  Unknown values
+    /// are treated as no policy, as browsers do.
+    fn parse(header: &str) -> Self {
+        match header.split(';').next().unwrap_or_default().trim() {
+            "require-corp" => Self::RequireCorp,
+            "credentialless" => Self::Credentialless,
+            _ => Self::UnsafeNone,
+        }
+    }
+}
+
+impl std::fmt::Display for Support {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.is_supported() {
+            write!(f, "threads are supported")?;
+        } else {
+            write!(f, "threads are not supported")?;
+        }
+        if !self.workers {
+            write!(
+                f,
+                "; workers can't be created from this context, as in a service worker"
+            )?;
+        }
+        if !self.shared_array_buffer {
+            if self.cross_origin_isolated {
+                write!(f, "; `SharedArrayBuffer` isn't available in this browser")?;
+            } else {
+                write!(
+                    f,
+                    "; the page isn't cross-origin isolated: serve it with the headers \
+                     `Cross-Origin-Opener-Policy: same-origin` and \
+                     `Cross-Origin-Embedder-Policy: require-corp` (or `credentialless`, \
+                     which Safari doesn't support)"
+                )?;
+            }
+        }
+        if !self.wait_async {
+            write!(
+                f,
+                "; `Atomics.waitAsync` isn't available, so waiting is polyfilled"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether this is Node.js, with `worker_threads` available
+/// synchronously through `process.getBuiltinModule`.
+fn is_node(global: &js_sys::Object) -> bool {
+    js_sys::Reflect::get(global, &"process".into()).is_ok_and(|process| {
+        process.is_object()
+            && js_sys::Reflect::has(&process, &"getBuiltinModule".into()).unwrap_or(false)
+    })
+}
+
+/// Whether [`Thread`](crate::Thread)s can run their tasks on workers
+/// in the current context.  See [`Support`] for the details.
+#[must_use]
+pub fn is_supported() -> bool {
+    Support::detect().is_supported()
+}
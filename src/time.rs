@@ -0,0 +1,145 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Timers that work on the main thread and in workers alike, each
+//! running on the event loop of the thread it was created on.
+//!
+//! The shim has the same functions, so task code can wait without
+//! choosing a timer crate for each target:
+//!
+//! ```no_run
+//! # async fn f() -> web_thread::Result<()> {
+//! use std::time::Duration;
+//! use futures::StreamExt as _;
+//! use web_thread::time;
+//!
+//! time::sleep(Duration::from_millis(100)).await;
+//! let mut ticks = time::interval(Duration::from_secs(1)).take(3);
+//! while ticks.next().await.is_some() {}
+//! let output = time::timeout(async { 42 }, Duration::from_secs(1)).await?;
+//! # Ok(()) }
+//! ```
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll, ready},
+    time::Duration,
+};
+
+use futures::{FutureExt as _, future};
+use wasm_bindgen_futures::JsFuture;
+
+use super::{Error, JsValue, Result, js_sys, wasm_bindgen};
+
+#[wasm_bindgen]
+extern "C" {
+    /// The time in milliseconds, from a clock that, unlike the wall
+    /// clock, never jumps.
+    #[wasm_bindgen(js_namespace = performance, js_name = now)]
+    fn now() -> f64;
+}
+
+/// Call the global function `name`, such as `setTimeout`.
+fn call(name: &str, arguments: &[&JsValue]) -> Result<JsValue, JsValue> {
+    let function: js_sys::Function = js_sys::Reflect::get(&js_sys::global(), &name.into())?.into();
+    function.apply(&JsValue::UNDEFINED, &arguments.iter().copied().collect())
+}
+
+/// A future that completes once a timer has fired.  Dropping it
+/// clears the timer.
+#[must_use = "futures do nothing unless polled"]
+pub struct Sleep {
+    future: JsFuture,
+    handle: JsValue,
+}
+
+/// Wait for `duration`, using `setTimeout`.
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_millis(duration.as_secs_f64() * 1000.)
+}
+
+fn sleep_millis(millis: f64) -> Sleep {
+    let mut handle = JsValue::UNDEFINED;
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        // without `setTimeout` there's no waking up
+        handle = call("setTimeout", &[&resolve, &millis.into()]).unwrap_or_default();
+    });
+    Sleep {
+        future: JsFuture::from(promise),
+        handle,
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<()> {
+        self.future.poll_unpin(context).map(|_| ())
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        // clearing a timer that has fired does nothing
+        let _ = call("clearTimeout", &[&self.handle]);
+    }
+}
+
+/// A [`Stream`](futures::Stream) that yields every `period`.
+///
+/// Ticks are kept to a grid of `period`s from the time the interval
+/// was created, rather than scheduled from the previous tick, so they
+/// don't drift.  Ticks missed while the thread was busy are skipped
+/// rather than bunched up, and the next tick is back on the grid.
+#[must_use = "streams do nothing unless polled"]
+pub struct Interval {
+    period: f64,
+    /// The time of the next tick, as given by `performance.now()`.
+    deadline: f64,
+    sleep: Sleep,
+}
+
+/// Yield every `period`, starting a `period` from now.
+///
+/// # Panics
+///
+/// If `period` is zero.
+pub fn interval(period: Duration) -> Interval {
+    assert!(!period.is_zero(), "an interval must have a period");
+    let period = period.as_secs_f64() * 1000.;
+    Interval {
+        period,
+        deadline: now() + period,
+        sleep: sleep_millis(period),
+    }
+}
+
+impl futures::Stream for Interval {
+    type Item = ();
+
+    fn poll_next(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Option<()>> {
+        ready!(self.sleep.poll_unpin(context));
+        let period = self.period;
+        let now = now();
+        self.deadline += period;
+        if self.deadline <= now {
+            // skip the ticks missed, to the next one on the grid
+            self.deadline += (((now - self.deadline) / period).floor() + 1.) * period;
+        }
+        self.sleep = sleep_millis(self.deadline - now);
+        Poll::Ready(Some(()))
+    }
+}
+
+/// Wait for `future` to complete, for at most `duration`.
+///
+/// # Errors
+///
+/// [`Error::Timeout`] if `future` didn't complete in time, in which
+/// case it is dropped.
+pub async fn timeout<F: Future>(future: F, duration: Duration) -> Result<F::Output> {
+    match future::select(std::pin::pin!(future), sleep(duration)).await {
+        future::Either::Left((output, _)) => Ok(output),
+        future::Either::Right(((), _)) => Err(Error::Timeout),
+    }
+}
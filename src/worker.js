@@ -3,33 +3,58 @@
 // Copyright (c) Zefchain Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-// This must be made available by the library consumer, since we don't
-// know where it is yet.
-import * as wasm from 'web-thread:wasm-shim';
-
 const pending = new Map();
+let wasm;
+
+// Node.js workers talk to their parent through `parentPort` rather
+// than their global scope.
+const nodeParent = typeof self === 'undefined'
+  ? globalThis.process?.getBuiltinModule?.('node:worker_threads')?.parentPort
+  : undefined;
+const reply = nodeParent === undefined
+  ? (message, transfer) => self.postMessage(message, transfer)
+  : (message, transfer) => nodeParent.postMessage(message, transfer);
+const exit = nodeParent === undefined ? () => self.close() : () => process.exit();
 
-self.onmessage = async (event) => {
+const run = async ({ id, code, context }) => {
+  pending.set(id, null);
+  try {
+    const { message: result, transfer } = await wasm.__web_thread_worker_entry_point(code, context);
+    reply({ type: 'response', id, result }, transfer);
+  } catch (error) {
+    console.error(error);
+    reply({ type: 'response', id, error });
+  } finally {
+    pending.delete(id);
+  }
+};
+
+const onMessage = async (event) => {
   if (event.data.type === 'init') {
-    await wasm.default(event.data);
-    self.postMessage({ type: 'ready' });
+    const { module, memory, moduleUrl, mainPort } = event.data;
+    wasm = moduleUrl === undefined
+      // This must be made available by the library consumer, since we
+      // don't know where it is yet.
+      ? await import('web-thread:wasm-shim')
+      : await import(/* @vite-ignore */ /* webpackIgnore: true */ moduleUrl);
+    await wasm.default({ module, memory });
+    // the worker's way to run code on the main thread
+    wasm.__web_thread_set_main_port(mainPort);
+    reply({ type: 'ready' });
   } else if (event.data.type === 'destroy') {
     for (const id of pending.keys())
-      self.postMessage({ type: 'response', id, error: new Error('thread destroyed during execution') });
-    self.close();
+      reply({ type: 'response', id, error: new Error('thread destroyed during execution') });
+    exit();
   } else if (event.data.type === 'run') {
-    const { id, code, context } = event.data;
-    pending.set(id, null);
-    try {
-      const { message: result, transfer } = await wasm.__web_thread_worker_entry_point(code, context);
-      self.postMessage({ type: 'response', id, result }, transfer);
-    } catch (error) {
-      console.error(error);
-      self.postMessage({ type: 'response', id, error });
-    } finally {
-      pending.delete(id);
-    }
+    await run(event.data);
+  } else if (event.data.type === 'batch') {
+    await Promise.all(event.data.runs.map(run));
   } else {
     console.error('[web-thread] malformed request', event.data);
   }
 }
+
+if (nodeParent === undefined)
+  self.onmessage = onMessage;
+else
+  nodeParent.on('message', data => onMessage({ data }));